@@ -0,0 +1,334 @@
+//! Byte-level building blocks shared by the crate's binary encodings.
+//!
+//! All integers are written big-endian, and variable-length fields carry a
+//! `u32` length prefix. Keeping this in one place means framing and bounds
+//! checks only need to be right once.
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+#[cfg(not(feature = "std"))]
+use core::result::Result;
+
+use core::convert::TryFrom;
+use core::fmt;
+
+use bitvec::prelude::BitVec;
+
+/// Reasons encoding or decoding failed.
+#[derive(Debug, PartialEq)]
+pub enum CodecError {
+    /// Input ended early; `needed` more bytes were required.
+    UnexpectedEnd { needed: usize, remaining: usize },
+    /// Output slice is too small for the data being written.
+    BufferFull,
+    /// A length does not fit in the `u32` length prefix (or in `usize`).
+    LengthOverflow,
+    /// Input had bytes left over once decoding finished.
+    TrailingBytes { remaining: usize },
+    /// A `BitVec` had set bits in the padding of its final byte.
+    NonZeroPadding,
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodecError::UnexpectedEnd { needed, remaining } =>
+                write!(f, "Needed {} bytes but only {} remain", needed, remaining),
+            CodecError::BufferFull =>
+                write!(f, "Output buffer is full"),
+            CodecError::LengthOverflow =>
+                write!(f, "Length does not fit in the encoding"),
+            CodecError::TrailingBytes { remaining } =>
+                write!(f, "{} unexpected trailing bytes", remaining),
+            CodecError::NonZeroPadding =>
+                write!(f, "Bit vector padding is not zero"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CodecError {}
+
+/// Bytes of `bits`, with the final byte padded out using zero bits.
+pub(crate) fn bitvec_to_bytes(bits: &BitVec) -> Vec<u8> {
+    let mut padded = bits.clone();
+    while padded.len() % 8 != 0 {
+        padded.push(false);
+    }
+    padded.into_vec()
+}
+
+enum Sink<'a> {
+    Vec(&'a mut Vec<u8>),
+    Slice(&'a mut [u8]),
+    Count,
+}
+
+/// Appends encoded data to a `Vec`, a fixed-size slice, or nowhere at all
+/// (to compute the exact encoded size before allocating).
+pub struct Writer<'a> {
+    sink: Sink<'a>,
+    written: usize,
+}
+
+impl<'a> Writer<'a> {
+    /// Writer appending to the end of `buf`.
+    pub fn new(buf: &'a mut Vec<u8>) -> Writer<'a> {
+        Writer { sink: Sink::Vec(buf), written: 0 }
+    }
+
+    /// Writer filling `buf` from the start, failing once it is full.
+    pub fn into_slice(buf: &'a mut [u8]) -> Writer<'a> {
+        Writer { sink: Sink::Slice(buf), written: 0 }
+    }
+
+    /// Writer that stores nothing and only counts bytes.
+    pub fn counting() -> Writer<'static> {
+        Writer { sink: Sink::Count, written: 0 }
+    }
+
+    /// Number of bytes written so far.
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Write raw bytes with no length prefix.
+    pub fn put_bytes(&mut self, bytes: &[u8]) -> Result<(), CodecError> {
+        let end = self.written.checked_add(bytes.len())
+            .ok_or(CodecError::LengthOverflow)?;
+        match &mut self.sink {
+            Sink::Vec(buf) => buf.extend_from_slice(bytes),
+            Sink::Slice(buf) => {
+                if end > buf.len() {
+                    return Err(CodecError::BufferFull);
+                }
+                buf[self.written..end].copy_from_slice(bytes);
+            },
+            Sink::Count => { },
+        }
+        self.written = end;
+        Ok(())
+    }
+
+    pub fn put_u8(&mut self, value: u8) -> Result<(), CodecError> {
+        self.put_bytes(&[value])
+    }
+
+    pub fn put_u32(&mut self, value: u32) -> Result<(), CodecError> {
+        self.put_bytes(&value.to_be_bytes())
+    }
+
+    pub fn put_u64(&mut self, value: u64) -> Result<(), CodecError> {
+        self.put_bytes(&value.to_be_bytes())
+    }
+
+    /// Write a `u32` length, as used ahead of variable-length fields.
+    pub fn put_len(&mut self, len: usize) -> Result<(), CodecError> {
+        let len = u32::try_from(len).map_err(|_| CodecError::LengthOverflow)?;
+        self.put_u32(len)
+    }
+
+    /// Write `bytes` preceded by its `u32` length.
+    pub fn put_len_prefixed(&mut self, bytes: &[u8]) -> Result<(), CodecError> {
+        self.put_len(bytes.len())?;
+        self.put_bytes(bytes)
+    }
+
+    /// Write a `BitVec` as its `u32` bit length followed by its zero-padded bytes.
+    pub fn put_bitvec(&mut self, bits: &BitVec) -> Result<(), CodecError> {
+        self.put_len(bits.len())?;
+        self.put_bytes(&bitvec_to_bytes(bits))
+    }
+}
+
+/// Zero-copy, bounds-checked cursor over encoded bytes.
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Reader<'a> {
+        Reader { buf, pos: 0 }
+    }
+
+    /// Number of bytes not yet consumed.
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Borrow the next `len` bytes.
+    pub fn take_bytes(&mut self, len: usize) -> Result<&'a [u8], CodecError> {
+        if len > self.remaining() {
+            return Err(CodecError::UnexpectedEnd {
+                needed: len,
+                remaining: self.remaining(),
+            });
+        }
+        let bytes = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn take_u8(&mut self) -> Result<u8, CodecError> {
+        Ok(self.take_bytes(1)?[0])
+    }
+
+    pub fn take_u32(&mut self) -> Result<u32, CodecError> {
+        Ok(u32::from_be_bytes(self.take_hash::<4>()?))
+    }
+
+    pub fn take_u64(&mut self) -> Result<u64, CodecError> {
+        Ok(u64::from_be_bytes(self.take_hash::<8>()?))
+    }
+
+    /// Copy the next `N` bytes out as a fixed-size array (e.g. a hash).
+    pub fn take_hash<const N: usize>(&mut self) -> Result<[u8; N], CodecError> {
+        let mut out = [0u8; N];
+        out.copy_from_slice(self.take_bytes(N)?);
+        Ok(out)
+    }
+
+    /// Read a `u32` length written by `Writer::put_len`.
+    pub fn take_len(&mut self) -> Result<usize, CodecError> {
+        usize::try_from(self.take_u32()?).map_err(|_| CodecError::LengthOverflow)
+    }
+
+    /// Borrow a field written by `Writer::put_len_prefixed`.
+    pub fn take_len_prefixed(&mut self) -> Result<&'a [u8], CodecError> {
+        let len = self.take_len()?;
+        self.take_bytes(len)
+    }
+
+    /// Read a `BitVec` written by `Writer::put_bitvec`.
+    pub fn take_bitvec(&mut self) -> Result<BitVec, CodecError> {
+        let len = self.take_len()?;
+        let mut bits = BitVec::from_slice(self.take_bytes(len.div_ceil(8))?);
+        // Only zero padding is canonical, matching `bitvec_to_bytes`
+        if bits.iter().skip(len).any(|bit| bit) {
+            return Err(CodecError::NonZeroPadding);
+        }
+        bits.truncate(len);
+        Ok(bits)
+    }
+
+    /// Succeed only if every byte has been consumed.
+    pub fn finish(self) -> Result<(), CodecError> {
+        match self.remaining() {
+            0 => Ok(()),
+            remaining => Err(CodecError::TrailingBytes { remaining }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn encode_sample(w: &mut Writer) -> Result<(), CodecError> {
+        w.put_u8(1)?;
+        w.put_u32(0xdead_beef)?;
+        w.put_u64(42)?;
+        w.put_len_prefixed(b"plasma")?;
+        w.put_bytes(&[7u8; 4])?;
+        w.put_bitvec(&sample_bits())
+    }
+
+    fn sample_bits() -> BitVec {
+        let mut bits = BitVec::new();
+        for bit in &[true, false, true, true, false, false, true, true, true] {
+            bits.push(*bit);
+        }
+        bits
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut buf = Vec::new();
+        encode_sample(&mut Writer::new(&mut buf)).unwrap();
+
+        let mut r = Reader::new(&buf);
+        assert_eq!(r.take_u8().unwrap(), 1);
+        assert_eq!(r.take_u32().unwrap(), 0xdead_beef);
+        assert_eq!(r.take_u64().unwrap(), 42);
+        assert_eq!(r.take_len_prefixed().unwrap(), b"plasma");
+        assert_eq!(r.take_hash::<4>().unwrap(), [7u8; 4]);
+        assert_eq!(r.take_bitvec().unwrap(), sample_bits());
+        assert!(r.finish().is_ok());
+    }
+
+    #[test]
+    fn counting_matches_slice_and_vec() {
+        let mut counter = Writer::counting();
+        encode_sample(&mut counter).unwrap();
+        let size = counter.written();
+
+        let mut buf = Vec::new();
+        encode_sample(&mut Writer::new(&mut buf)).unwrap();
+        assert_eq!(buf.len(), size);
+
+        let mut exact = vec![0u8; size];
+        encode_sample(&mut Writer::into_slice(&mut exact)).unwrap();
+        assert_eq!(exact, buf);
+
+        let mut short = vec![0u8; size - 1];
+        assert_eq!(
+            encode_sample(&mut Writer::into_slice(&mut short)),
+            Err(CodecError::BufferFull)
+        );
+    }
+
+    #[test]
+    fn truncated_input_errors() {
+        let mut buf = Vec::new();
+        encode_sample(&mut Writer::new(&mut buf)).unwrap();
+        for len in 0..buf.len() {
+            let mut r = Reader::new(&buf[..len]);
+            let result = (|| {
+                r.take_u8()?;
+                r.take_u32()?;
+                r.take_u64()?;
+                r.take_len_prefixed()?;
+                r.take_hash::<4>()?;
+                r.take_bitvec()
+            })();
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn oversized_length_prefix_errors() {
+        // Length prefix claims far more than is available
+        let buf = [0xff, 0xff, 0xff, 0xff, 1, 2, 3];
+        let mut r = Reader::new(&buf);
+        assert_eq!(
+            r.take_len_prefixed(),
+            Err(CodecError::UnexpectedEnd { needed: 0xffff_ffff, remaining: 3 })
+        );
+    }
+
+    #[test]
+    fn trailing_bytes_rejected() {
+        let buf = [0u8, 1];
+        let mut r = Reader::new(&buf);
+        r.take_u8().unwrap();
+        assert_eq!(r.finish(), Err(CodecError::TrailingBytes { remaining: 1 }));
+    }
+
+    #[test]
+    fn nonzero_padding_rejected() {
+        let bits: BitVec = [true, false, true].iter().copied().collect();
+        let mut buf = Vec::new();
+        Writer::new(&mut buf).put_bitvec(&bits).unwrap();
+        assert_eq!(Reader::new(&buf).take_bitvec(), Ok(bits));
+
+        // Same 3 bits, but with a padding bit set in the final byte
+        *buf.last_mut().unwrap() |= 0b0000_0001;
+        assert_eq!(Reader::new(&buf).take_bitvec(), Err(CodecError::NonZeroPadding));
+    }
+}
@@ -0,0 +1,276 @@
+//! Per-transfer fees, so both the operator and its users can compute the
+//! same fees over a history.
+
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeMap;
+
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
+
+#[cfg(not(feature = "std"))]
+use core::result::Result;
+
+use core::convert::TryFrom;
+use core::fmt;
+
+use crate::transaction::PlasmaCashTxn;
+
+/// Errors from computing fees, see `FeeSchedule` and `Token::total_fees`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FeeError {
+    /// Fee does not fit in the amount type.
+    Overflow,
+    /// Fee depends on the coin's amount, but the transaction doesn't declare
+    /// one.
+    MissingAmount,
+}
+
+impl fmt::Display for FeeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FeeError::Overflow =>
+                write!(f, "Fee does not fit in the amount type."),
+            FeeError::MissingAmount =>
+                write!(f, "Transaction has no amount to charge a fee on."),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FeeError {}
+
+/// Amount fees are paid in (see `PlasmaCashTxn::Amount`), with the checked
+/// arithmetic needed to compute them without wrapping.
+pub trait FeeAmount: Copy + PartialEq + fmt::Debug {
+    /// No fee.
+    const ZERO: Self;
+
+    fn checked_add(self, other: Self) -> Option<Self>;
+
+    fn checked_mul(self, other: Self) -> Option<Self>;
+
+    fn checked_div(self, other: Self) -> Option<Self>;
+
+    /// `n` as an amount, if it fits.
+    fn from_u32(n: u32) -> Option<Self>;
+}
+
+macro_rules! impl_fee_amount {
+    ($($amount:ty),*) => {$(
+        impl FeeAmount for $amount {
+            const ZERO: Self = 0;
+
+            fn checked_add(self, other: Self) -> Option<Self> {
+                <$amount>::checked_add(self, other)
+            }
+
+            fn checked_mul(self, other: Self) -> Option<Self> {
+                <$amount>::checked_mul(self, other)
+            }
+
+            fn checked_div(self, other: Self) -> Option<Self> {
+                <$amount>::checked_div(self, other)
+            }
+
+            fn from_u32(n: u32) -> Option<Self> {
+                <$amount>::try_from(n).ok()
+            }
+        }
+    )*};
+}
+
+impl_fee_amount!(u8, u16, u32, u64, u128, usize);
+
+/// How much each transfer is expected to pay, to check the fee it declares
+/// (see `PlasmaCashTxn::fee()`).
+#[derive(Debug, PartialEq, Clone)]
+pub enum FeeSchedule<Amount, BlockNum> {
+    /// Every transfer pays the same fee.
+    Flat(Amount),
+    /// Fee set from a block on: a transfer pays the fee of the latest block
+    /// at or before the one it spends (see `PlasmaCashTxn::prev_block()`),
+    /// and nothing before the first.
+    PerBlock(BTreeMap<BlockNum, Amount>),
+    /// Fee in basis points (1/10000) of the coin's amount (see
+    /// `PlasmaCashTxn::amount()`), rounded down.
+    BasisPoints(u32),
+}
+
+impl<Amount, BlockNum> FeeSchedule<Amount, BlockNum>
+    where
+        Amount: FeeAmount,
+        BlockNum: Ord,
+{
+    /// Fee `txn` should pay under this schedule.
+    pub fn expected_fee<TxnType>(&self, txn: &TxnType) -> Result<Amount, FeeError>
+        where TxnType: PlasmaCashTxn<Amount = Amount, BlockNum = BlockNum>,
+    {
+        match self {
+            FeeSchedule::Flat(fee) => Ok(*fee),
+            FeeSchedule::PerBlock(fees) => Ok(
+                fees.range(..=txn.prev_block()).next_back().map_or(Amount::ZERO, |(_, fee)| *fee)
+            ),
+            FeeSchedule::BasisPoints(bps) => {
+                let amount = txn.amount().ok_or(FeeError::MissingAmount)?;
+                let bps = Amount::from_u32(*bps).ok_or(FeeError::Overflow)?;
+                let scale = Amount::from_u32(10_000).ok_or(FeeError::Overflow)?;
+                amount.checked_mul(bps)
+                    .and_then(|fee| fee.checked_div(scale))
+                    .ok_or(FeeError::Overflow)
+            },
+        }
+    }
+
+    /// Whether the fee `txn` declares is the one it should pay, a transaction
+    /// without a fee paying none.
+    pub fn matches<TxnType>(&self, txn: &TxnType) -> Result<bool, FeeError>
+        where TxnType: PlasmaCashTxn<Amount = Amount, BlockNum = BlockNum>,
+    {
+        Ok(txn.fee().unwrap_or(Amount::ZERO) == self.expected_fee(txn)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitvec::prelude::BitVec;
+
+    use crate::token::test::{move_to_status, new_token, Malformed, MockTransaction};
+    use crate::token::{Token, TokenError, TokenStatus};
+    use crate::transaction::TxnCmp;
+
+    // Declares a fee, and the amount of the coin
+    #[derive(PartialEq, Clone, Debug)]
+    struct FeeTransaction {
+        txn: MockTransaction,
+        fee: Option<u16>,
+        amount: Option<u16>,
+    }
+
+    impl FeeTransaction {
+        fn new(sender: u8, receiver: u8, block_num: u8, fee: Option<u16>) -> Self {
+            let txn = MockTransaction::new(BitVec::from_element(1u8), sender, receiver, block_num);
+            FeeTransaction { txn, fee, amount: None }
+        }
+    }
+
+    impl PlasmaCashTxn for FeeTransaction {
+        type HashType = [u8; 8];
+        type Hasher = fn(&[u8]) -> [u8; 8];
+        type Owner = u8;
+        type BlockNum = u8;
+        type Amount = u16;
+        type Error = Malformed;
+
+        fn token_id(&self) -> BitVec {
+            self.txn.token_id()
+        }
+
+        fn receiver(&self) -> Option<u8> {
+            self.txn.receiver()
+        }
+
+        fn validate(&self) -> Result<(), Malformed> {
+            self.txn.validate()
+        }
+
+        fn prev_block(&self) -> u8 {
+            self.txn.prev_block()
+        }
+
+        fn fee(&self) -> Option<u16> {
+            self.fee
+        }
+
+        fn amount(&self) -> Option<u16> {
+            self.amount
+        }
+
+        fn leaf_hash(&self) -> [u8; 8] {
+            self.txn.leaf_hash()
+        }
+
+        fn empty_leaf_hash() -> [u8; 8] {
+            MockTransaction::empty_leaf_hash()
+        }
+
+        fn hasher() -> Self::Hasher {
+            MockTransaction::hasher()
+        }
+
+        fn compare(&self, other: &Self) -> TxnCmp {
+            self.txn.compare(&other.txn)
+        }
+    }
+
+    #[test]
+    fn expected_fees() {
+        let txn = FeeTransaction::new(1, 2, 5, Some(3));
+        assert_eq!(FeeSchedule::Flat(3).expected_fee(&txn), Ok(3));
+        assert_eq!(FeeSchedule::Flat(3).matches(&txn), Ok(true));
+
+        // Fee of block 4 applies to a transfer spending block 5
+        let per_block = FeeSchedule::PerBlock(vec![(2, 1), (4, 3), (6, 9)].into_iter().collect());
+        assert_eq!(per_block.expected_fee(&txn), Ok(3));
+        assert_eq!(per_block.expected_fee(&FeeTransaction::new(0, 1, 1, None)), Ok(0));
+        assert_eq!(per_block.expected_fee(&FeeTransaction::new(1, 2, 6, None)), Ok(9));
+
+        // 250 bps of 60, rounded down
+        let mut txn = FeeTransaction::new(1, 2, 5, Some(1));
+        txn.amount = Some(60);
+        assert_eq!(FeeSchedule::BasisPoints(250).expected_fee(&txn), Ok(1));
+        assert_eq!(FeeSchedule::BasisPoints(250).matches(&txn), Ok(true));
+        txn.amount = Some(200);
+        assert_eq!(FeeSchedule::BasisPoints(250).expected_fee(&txn), Ok(5));
+    }
+
+    #[test]
+    fn mismatched_fees_detected() {
+        let schedule = FeeSchedule::Flat(3);
+        assert_eq!(schedule.matches(&FeeTransaction::new(1, 2, 5, Some(2))), Ok(false));
+        assert_eq!(schedule.matches(&FeeTransaction::new(1, 2, 5, None)), Ok(false));
+        assert_eq!(FeeSchedule::Flat(0).matches(&FeeTransaction::new(1, 2, 5, None)), Ok(true));
+
+        let mut txn = FeeTransaction::new(1, 2, 5, Some(1));
+        assert_eq!(FeeSchedule::BasisPoints(500).matches(&txn), Err(FeeError::MissingAmount));
+        txn.amount = Some(20);
+        assert_eq!(FeeSchedule::BasisPoints(500).matches(&txn), Ok(true));
+        assert_eq!(FeeSchedule::BasisPoints(1000).matches(&txn), Ok(false));
+    }
+
+    #[test]
+    fn fee_arithmetic_is_checked() {
+        // 500 bps of 200 overflows a `u16` before being scaled down, and
+        // 100000 bps doesn't fit in one at all
+        let mut txn = FeeTransaction::new(1, 2, 5, None);
+        txn.amount = Some(200);
+        assert_eq!(FeeSchedule::BasisPoints(500).expected_fee(&txn), Err(FeeError::Overflow));
+        txn.amount = Some(1);
+        assert_eq!(FeeSchedule::BasisPoints(100_000).expected_fee(&txn), Err(FeeError::Overflow));
+
+        let mut t: Token<FeeTransaction, [u8; 8]> = Token::new(BitVec::from_element(1u8));
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        for (i, fee) in [None, Some(30_000), Some(30_000), Some(30_000)].iter().enumerate() {
+            let i = i as u8;
+            assert!(t.add_transaction(FeeTransaction::new(i, i + 1, i, *fee)).is_ok());
+        }
+        assert_eq!(t.total_fees(0..0), Ok(0));
+        assert_eq!(t.total_fees(0..3), Ok(60_000));
+        assert_eq!(t.total_fees(2..4), Ok(60_000));
+        assert_eq!(t.total_fees(0..4), Err(TokenError::Fee(FeeError::Overflow)));
+        assert_eq!(t.total_fees(2..5), Err(TokenError::EntryOutOfRange { index: 4, len: 4 }));
+    }
+
+    #[test]
+    fn fee_free_token_unaffected() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        for i in 0..3 {
+            assert!(t.add_transaction(MockTransaction::new(t.uid.clone(), i, i + 1, i)).is_ok());
+        }
+        assert!(t.is_valid());
+        assert_eq!(t.total_fees(0..3), Ok(0));
+        assert!(t.transactions().all(|txn| FeeSchedule::Flat(0).matches(txn) == Ok(true)));
+    }
+}
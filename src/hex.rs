@@ -0,0 +1,149 @@
+//! Hex encoding and decoding helpers.
+//!
+//! Accepts an optional `0x` prefix on input, so values copied straight from
+//! Ethereum tooling can be parsed without pre-processing.
+
+#[cfg(not(feature = "std"))]
+use alloc::{string::String, vec::Vec};
+
+#[cfg(not(feature = "std"))]
+use core::result::Result;
+
+use core::fmt;
+
+const HEX_CHARS: &[u8; 16] = b"0123456789abcdef";
+
+/// Reasons a hex string failed to parse.
+#[derive(Debug, PartialEq)]
+pub enum HexError {
+    /// Number of hex digits (after any `0x` prefix) is odd.
+    OddLength,
+    /// Character at this index of the input is not a hex digit.
+    InvalidChar { index: usize },
+    /// Decoded byte count does not match the expected size.
+    InvalidLength { expected: usize, got: usize },
+}
+
+impl fmt::Display for HexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HexError::OddLength =>
+                write!(f, "Hex string has an odd number of digits"),
+            HexError::InvalidChar { index } =>
+                write!(f, "Invalid hex character at index {}", index),
+            HexError::InvalidLength { expected, got } =>
+                write!(f, "Expected {} bytes, got {}", expected, got),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for HexError {}
+
+fn strip_prefix(s: &str) -> (&str, usize) {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        (&s[2..], 2)
+    } else {
+        (s, 0)
+    }
+}
+
+fn nibble(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Decode a hex string (with or without `0x` prefix) into bytes.
+pub fn decode(s: &str) -> Result<Vec<u8>, HexError> {
+    let (digits, offset) = strip_prefix(s);
+    let digits = digits.as_bytes();
+    if digits.len() % 2 != 0 {
+        return Err(HexError::OddLength);
+    }
+
+    let mut bytes = Vec::with_capacity(digits.len() / 2);
+    for (i, pair) in digits.chunks(2).enumerate() {
+        let index = offset + 2 * i;
+        let hi = nibble(pair[0]).ok_or(HexError::InvalidChar { index })?;
+        let lo = nibble(pair[1]).ok_or(HexError::InvalidChar { index: index + 1 })?;
+        bytes.push((hi << 4) | lo);
+    }
+    Ok(bytes)
+}
+
+/// Decode a hex string into exactly `N` bytes.
+pub fn decode_fixed<const N: usize>(s: &str) -> Result<[u8; N], HexError> {
+    let bytes = decode(s)?;
+    if bytes.len() != N {
+        return Err(HexError::InvalidLength { expected: N, got: bytes.len() });
+    }
+    let mut out = [0u8; N];
+    out.copy_from_slice(&bytes);
+    Ok(out)
+}
+
+/// Write `bytes` as lowercase hex (no prefix) without allocating.
+pub fn write_hex<W: fmt::Write>(f: &mut W, bytes: &[u8]) -> fmt::Result {
+    for b in bytes {
+        f.write_char(HEX_CHARS[(b >> 4) as usize] as char)?;
+        f.write_char(HEX_CHARS[(b & 0x0f) as usize] as char)?;
+    }
+    Ok(())
+}
+
+/// Encode `bytes` as lowercase hex, optionally `0x`-prefixed.
+pub fn encode(bytes: &[u8], prefix: bool) -> String {
+    let mut s = String::with_capacity(2 * bytes.len() + 2);
+    if prefix {
+        s.push_str("0x");
+    }
+    write_hex(&mut s, bytes).expect("Writing to a String cannot fail");
+    s
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_with_and_without_prefix() {
+        assert_eq!(decode("0x00ff10").unwrap(), vec![0x00, 0xff, 0x10]);
+        assert_eq!(decode("00FF10").unwrap(), vec![0x00, 0xff, 0x10]);
+        assert_eq!(decode("0x").unwrap(), Vec::<u8>::new());
+        assert_eq!(decode("").unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn decode_rejects_bad_input() {
+        assert_eq!(decode("0x123"), Err(HexError::OddLength));
+        assert_eq!(decode("0x12g4"), Err(HexError::InvalidChar { index: 4 }));
+        assert_eq!(decode("zz"), Err(HexError::InvalidChar { index: 0 }));
+    }
+
+    #[test]
+    fn decode_fixed_checks_length() {
+        assert_eq!(decode_fixed::<2>("0xabcd").unwrap(), [0xab, 0xcd]);
+        assert_eq!(
+            decode_fixed::<4>("abcd"),
+            Err(HexError::InvalidLength { expected: 4, got: 2 })
+        );
+    }
+
+    #[test]
+    fn round_trip_random_bytes() {
+        // Simple LCG so the test is deterministic without extra dependencies
+        let mut seed: u32 = 0x1234_5678;
+        for len in 0..64 {
+            let bytes: Vec<u8> = (0..len).map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (seed >> 16) as u8
+            }).collect();
+            assert_eq!(decode(&encode(&bytes, true)).unwrap(), bytes);
+            assert_eq!(decode(&encode(&bytes, false)).unwrap(), bytes);
+        }
+    }
+}
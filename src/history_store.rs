@@ -0,0 +1,260 @@
+//! Storage backends for a token's history.
+//!
+//! `Token` keeps its history in a `Vec` by default, but only goes through the
+//! `HistoryStore` trait, so e.g. an operator tracking a large number of coins
+//! can keep histories on disk instead by implementing it downstream.
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+use core::marker::PhantomData;
+
+use crate::token::HistoryEntry;
+
+/// Ordered storage for the entries of a token's history, oldest first.
+///
+/// # Note
+/// `Token` checks every entry before it is stored, and only ever adds or
+/// removes entries at either end. Stored entries are only changed to attach
+/// their inclusion block and proof (see `Token::set_inclusion`).
+pub trait HistoryStore<TxnType, HashType> {
+    /// Number of entries stored.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Entry at `index`, if there is one.
+    fn get(&self, index: usize) -> Option<&HistoryEntry<TxnType, HashType>>;
+
+    /// Mutable entry at `index`, if there is one.
+    fn get_mut(&mut self, index: usize) -> Option<&mut HistoryEntry<TxnType, HashType>>;
+
+    /// Newest entry.
+    fn last(&self) -> Option<&HistoryEntry<TxnType, HashType>> {
+        self.len().checked_sub(1).and_then(|index| self.get(index))
+    }
+
+    /// Add `entry` after the newest one.
+    fn push(&mut self, entry: HistoryEntry<TxnType, HashType>);
+
+    /// Remove and return the newest entry.
+    fn pop(&mut self) -> Option<HistoryEntry<TxnType, HashType>>;
+
+    /// Keep only the first `len` entries (nothing happens if there are fewer).
+    fn truncate(&mut self, len: usize);
+
+    /// Remove the oldest `count` entries (`count` is at most `len()`).
+    fn remove_front(&mut self, count: usize);
+
+    /// Entries, oldest first.
+    fn iter(&self) -> HistoryIter<'_, Self, TxnType, HashType>
+        where
+            Self: Sized,
+    {
+        HistoryIter {
+            store: self,
+            front: 0,
+            back: self.len(),
+            _entry: PhantomData,
+        }
+    }
+}
+
+impl<TxnType, HashType> HistoryStore<TxnType, HashType> for Vec<HistoryEntry<TxnType, HashType>> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn get(&self, index: usize) -> Option<&HistoryEntry<TxnType, HashType>> {
+        <[_]>::get(self, index)
+    }
+
+    fn get_mut(&mut self, index: usize) -> Option<&mut HistoryEntry<TxnType, HashType>> {
+        <[_]>::get_mut(self, index)
+    }
+
+    fn last(&self) -> Option<&HistoryEntry<TxnType, HashType>> {
+        <[_]>::last(self)
+    }
+
+    fn push(&mut self, entry: HistoryEntry<TxnType, HashType>) {
+        Vec::push(self, entry)
+    }
+
+    fn pop(&mut self) -> Option<HistoryEntry<TxnType, HashType>> {
+        Vec::pop(self)
+    }
+
+    fn truncate(&mut self, len: usize) {
+        Vec::truncate(self, len)
+    }
+
+    fn remove_front(&mut self, count: usize) {
+        self.drain(..count);
+    }
+}
+
+/// Iterator over the entries of a `HistoryStore`, see `HistoryStore::iter`.
+pub struct HistoryIter<'a, Store, TxnType, HashType> {
+    store: &'a Store,
+    front: usize,
+    back: usize,
+    _entry: PhantomData<&'a HistoryEntry<TxnType, HashType>>,
+}
+
+impl<Store, TxnType, HashType> Clone for HistoryIter<'_, Store, TxnType, HashType> {
+    fn clone(&self) -> Self {
+        HistoryIter {
+            store: self.store,
+            front: self.front,
+            back: self.back,
+            _entry: PhantomData,
+        }
+    }
+}
+
+impl<'a, Store, TxnType, HashType> Iterator for HistoryIter<'a, Store, TxnType, HashType>
+    where
+        Store: HistoryStore<TxnType, HashType>,
+{
+    type Item = &'a HistoryEntry<TxnType, HashType>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front == self.back {
+            return None;
+        }
+        self.front += 1;
+        self.store.get(self.front - 1)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.back - self.front;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<Store, TxnType, HashType> DoubleEndedIterator for HistoryIter<'_, Store, TxnType, HashType>
+    where
+        Store: HistoryStore<TxnType, HashType>,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front == self.back {
+            return None;
+        }
+        self.back -= 1;
+        self.store.get(self.back)
+    }
+}
+
+impl<Store, TxnType, HashType> ExactSizeIterator for HistoryIter<'_, Store, TxnType, HashType>
+    where
+        Store: HistoryStore<TxnType, HashType>,
+{}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{BitVec, Token, TokenStatus};
+    use crate::token::test::{move_to_status, MockTransaction};
+
+    type Entry = HistoryEntry<MockTransaction, [u8; 8]>;
+
+    const CAPACITY: usize = 4;
+
+    // Fixed-size storage, e.g. for environments without an allocator
+    #[derive(Default)]
+    struct FixedStore {
+        entries: [Option<Entry>; CAPACITY],
+        len: usize,
+    }
+
+    impl HistoryStore<MockTransaction, [u8; 8]> for FixedStore {
+        fn len(&self) -> usize {
+            self.len
+        }
+
+        fn get(&self, index: usize) -> Option<&Entry> {
+            if index < self.len { self.entries[index].as_ref() } else { None }
+        }
+
+        fn get_mut(&mut self, index: usize) -> Option<&mut Entry> {
+            if index < self.len { self.entries[index].as_mut() } else { None }
+        }
+
+        fn push(&mut self, entry: Entry) {
+            assert!(self.len < CAPACITY, "FixedStore is full");
+            self.entries[self.len] = Some(entry);
+            self.len += 1;
+        }
+
+        fn pop(&mut self) -> Option<Entry> {
+            self.len = self.len.checked_sub(1)?;
+            self.entries[self.len].take()
+        }
+
+        fn truncate(&mut self, len: usize) {
+            while self.len > len {
+                self.pop();
+            }
+        }
+
+        fn remove_front(&mut self, count: usize) {
+            self.entries[..self.len].rotate_left(count);
+            self.truncate(self.len - count);
+        }
+    }
+
+    #[test]
+    fn token_with_fixed_store() {
+        let uid = BitVec::from_element(1u8);
+        let txns: Vec<MockTransaction> = (0..3)
+            .map(|i| MockTransaction::new(uid.clone(), i, i + 1, i))
+            .collect();
+
+        let mut t = Token::new_with_store(uid.clone(), FixedStore::default()).unwrap();
+        let mut expected: Token<MockTransaction, [u8; 8]> = Token::new(uid.clone());
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        move_to_status(&mut expected, TokenStatus::PlasmaChain);
+        for txn in &txns {
+            assert!(t.add_transaction(txn.clone()).is_ok());
+            assert!(expected.add_transaction(txn.clone()).is_ok());
+        }
+        assert!(t.is_valid());
+        assert_eq!(t.history_len(), 3);
+        assert_eq!(t.history().iter().next_back().map(|entry| &entry.txn), Some(&txns[2]));
+        assert!(t.transactions().eq(txns.iter()));
+        assert!(t.is_owned_by(&3));
+        assert_eq!(t.audit_history(), expected.audit_history());
+
+        // Rejected transactions leave the store untouched
+        assert!(t.add_transaction(txns[0].clone()).is_err());
+        assert_eq!(t.history_len(), 3);
+
+        let encode = |txn: &MockTransaction| txn.as_bytes().to_vec();
+        assert_eq!(t.to_bytes(encode), expected.to_bytes(encode));
+
+        assert_eq!(t.revert_to(2).len(), 1);
+        assert_eq!(t.revert_last_transaction(), Some(txns[1].clone()));
+        assert!(t.add_transaction(txns[1].clone()).is_ok());
+        assert!(t.add_transaction(txns[2].clone()).is_ok());
+
+        assert!(t.prune_history(2).is_ok());
+        assert_eq!(t.history_len(), 1);
+        assert_eq!(t.transaction_at(0), Some(&txns[2]));
+        assert!(t.is_valid());
+
+        // Existing stores are validated when the token is built
+        let mut store = FixedStore::default();
+        store.push(HistoryEntry::new(txns[1].clone()));
+        store.push(HistoryEntry::new(txns[0].clone()));
+        assert!(Token::new_with_store(uid.clone(), store).is_err());
+
+        let mut store = FixedStore::default();
+        store.push(HistoryEntry::new(txns[0].clone()));
+        let t = Token::new_with_store(uid, store).unwrap();
+        assert_eq!(t.status(), TokenStatus::PlasmaChain);
+    }
+}
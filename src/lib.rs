@@ -8,9 +8,34 @@ extern crate alloc;
 pub use bitvec::prelude::{LittleEndian, BigEndian, BitVec};
 
 mod transaction;
-pub use transaction::{PlasmaCashTxn, TxnCmp};
+pub use transaction::{PlasmaCashTxn, ConfirmableTxn, EncodableTxn, MaybeSync, TxnCmp};
 
 mod token;
-pub use token::{Token, TokenStatus};
+pub use token::{
+    Token, TokenStatus, TokenError, HistoryError, HistoryEntry, RejectedEntry,
+    Checkpoint, ChallengeKind, ChallengeRecord, ConflictResolution, EntryReport, ExitData, ExitPriority, TransferBundle,
+    ValidationReport, VerifiedEntry,
+    validate_history_iter,
+};
 
-mod merkle;
+mod history_store;
+pub use history_store::{HistoryStore, HistoryIter};
+
+mod observer;
+pub use observer::TokenObserver;
+
+mod token_set;
+pub use token_set::{TokenSet, TokenSetError};
+
+pub mod merkle;
+pub use merkle::{MerkleError, NodeHasher, Proof};
+
+pub mod hex;
+
+pub mod codec;
+
+mod fee;
+pub use fee::{FeeAmount, FeeError, FeeSchedule};
+
+#[cfg(feature = "serde")]
+pub mod serde_utils;
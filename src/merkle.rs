@@ -9,28 +9,219 @@ use core::result::Result;
 #[cfg(not(feature = "std"))]
 use core::convert::AsRef;
 
+use core::fmt;
+use core::iter::Rev;
+use core::slice;
+
 use bitvec::prelude::BitSlice;
 
-pub fn get_root<HashType>(
+use crate::codec::{CodecError, Reader, Writer};
+
+/// Errors from Sparse Merkle Tree proof verification.
+#[derive(Debug, PartialEq)]
+pub enum MerkleError {
+    /// Proof does not have one sibling per bit of the key.
+    ProofLengthMismatch { key_bits: usize, proof_len: usize },
+    /// Default nodes do not have one entry per proof level.
+    DefaultNodesLengthMismatch { proof_len: usize, default_nodes_len: usize },
+}
+
+impl fmt::Display for MerkleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MerkleError::ProofLengthMismatch { .. } =>
+                write!(f, "Key must be the same size as the proof!"),
+            MerkleError::DefaultNodesLengthMismatch { .. } =>
+                write!(f, "Default nodes must be the same size as the proof!"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MerkleError {}
+
+/// Hash function for the nodes of a Sparse Merkle Tree.
+///
+/// # Note
+/// Implemented for plain functions and closures (e.g. `fn(&[u8]) -> H256`),
+/// but a type implementing this can also carry configuration the hash needs,
+/// such as a key or a domain separation tag.
+pub trait NodeHasher {
+    type Out: AsRef<[u8]>;
+
+    /// Hash of `data`.
+    fn hash(&self, data: &[u8]) -> Self::Out;
+}
+
+// Plain hash functions don't need any configuration
+impl<F, Out> NodeHasher for F
+    where
+        F: Fn(&[u8]) -> Out,
+        Out: AsRef<[u8]>,
+{
+    type Out = Out;
+
+    fn hash(&self, data: &[u8]) -> Out {
+        self(data)
+    }
+}
+
+/// Un-compressed Sparse Merkle Tree proof: one sibling hash per level of the
+/// tree, in root->leaf order.
+///
+/// # Note
+/// `From<Vec<HashType>>` skips the depth check, but a proof is always checked
+/// against the size of the key it is used with (see `get_root`).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Proof<HashType>(Vec<HashType>);
+
+impl<HashType> Proof<HashType> {
+    /// Proof made of `nodes` (root->leaf order), for a tree of `depth` levels.
+    pub fn new(nodes: Vec<HashType>, depth: usize) -> Result<Self, MerkleError> {
+        if nodes.len() != depth {
+            return Err(MerkleError::ProofLengthMismatch {
+                key_bits: depth,
+                proof_len: nodes.len(),
+            });
+        }
+        Ok(Proof(nodes))
+    }
+
+    /// Proof copied from `nodes` (root->leaf order), for a tree of `depth` levels.
+    pub fn from_slice(nodes: &[HashType], depth: usize) -> Result<Self, MerkleError>
+        where
+            HashType: Clone,
+    {
+        Proof::new(nodes.to_vec(), depth)
+    }
+
+    /// Number of levels covered, i.e. the depth of the tree.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Sibling hashes, in root->leaf order.
+    pub fn as_slice(&self) -> &[HashType] {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> Vec<HashType> {
+        self.0
+    }
+
+    /// Sibling hashes, in root->leaf order.
+    pub fn iter(&self) -> slice::Iter<'_, HashType> {
+        self.0.iter()
+    }
+
+    /// Sibling hashes, in leaf->root order (the order they are hashed in).
+    pub fn iter_leaf_to_root(&self) -> Rev<slice::Iter<'_, HashType>> {
+        self.0.iter().rev()
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.0.shrink_to_fit()
+    }
+
+    /// Encode as a `u32` hash count followed by the raw hashes.
+    pub fn to_bytes(&self) -> Vec<u8>
+        where
+            HashType: AsRef<[u8]>,
+    {
+        let mut buf = Vec::new();
+        self.write(&mut Writer::new(&mut buf))
+            .expect("writing to a Vec only fails on a length overflow");
+        buf
+    }
+
+    /// Decode a proof written by `to_bytes()`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CodecError>
+        where
+            HashType: AsMut<[u8]> + Default,
+    {
+        let mut r = Reader::new(bytes);
+        let proof = Proof::read(&mut r)?;
+        r.finish()?;
+        Ok(proof)
+    }
+
+    pub(crate) fn write(&self, w: &mut Writer) -> Result<(), CodecError>
+        where
+            HashType: AsRef<[u8]>,
+    {
+        w.put_len(self.0.len())?;
+        for hash in &self.0 {
+            w.put_bytes(hash.as_ref())?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read(r: &mut Reader) -> Result<Self, CodecError>
+        where
+            HashType: AsMut<[u8]> + Default,
+    {
+        let proof_len = r.take_len()?;
+        let mut nodes = Vec::with_capacity(proof_len.min(r.remaining()));
+        for _ in 0..proof_len {
+            let mut hash = HashType::default();
+            let hash_size = hash.as_mut().len();
+            hash.as_mut().copy_from_slice(r.take_bytes(hash_size)?);
+            nodes.push(hash);
+        }
+        Ok(Proof(nodes))
+    }
+}
+
+impl<HashType> From<Vec<HashType>> for Proof<HashType> {
+    fn from(nodes: Vec<HashType>) -> Self {
+        Proof(nodes)
+    }
+}
+
+impl<HashType> AsRef<[HashType]> for Proof<HashType> {
+    fn as_ref(&self) -> &[HashType] {
+        &self.0
+    }
+}
+
+impl<'a, HashType> IntoIterator for &'a Proof<HashType> {
+    type Item = &'a HashType;
+    type IntoIter = slice::Iter<'a, HashType>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Compute the Sparse Merkle Tree root for `leaf_hash` at position `key`
+/// using the (un-compressed) `proof`, given in root->leaf order.
+pub fn get_root<HashType, Hasher>(
     key: &BitSlice,
     leaf_hash: HashType,
-    proof: Vec<HashType>,
-    hash_fn: (fn(&[u8]) -> HashType),
-) -> Result<HashType, &'static str>
+    proof: &Proof<HashType>,
+    hasher: Hasher,
+) -> Result<HashType, MerkleError>
     where
         HashType: AsRef<[u8]>,
+        Hasher: NodeHasher<Out = HashType>,
 {
     // Validate key size to proof size
     if key.len() != proof.len() { // Sanity check that sizes match
-        return Err("Key must be the same size as the proof!");
+        return Err(MerkleError::ProofLengthMismatch {
+            key_bits: key.len(),
+            proof_len: proof.len(),
+        });
     }
 
     // Start result at leaf
     let mut node_hash = leaf_hash;
 
     // Path is the bits of key in leaf->root order (MSB to LSB), so reverse it!
-    // Branch is in root->leaf order, so reverse it!
-    for (is_right, sibling_node) in key.iter().rev().zip(proof.iter().rev()) {
+    for (is_right, sibling_node) in key.iter().rev().zip(proof.iter_leaf_to_root()) {
         let node = if is_right {
             sibling_node.as_ref().iter()
                 .chain(node_hash.as_ref().iter())
@@ -40,11 +231,131 @@ pub fn get_root<HashType>(
                 .chain(sibling_node.as_ref().iter())
                 .copied().collect::<Vec<u8>>()
         };
-        node_hash = (hash_fn)(node.as_slice());
+        node_hash = hasher.hash(node.as_slice());
     }
     Ok(node_hash)
 }
 
+/// Compute the root for `leaf_hash` at `key` once, and return the index of the
+/// first entry in `candidates` it matches (or `None` if it matches none).
+///
+/// # Note
+/// Useful when more than one root may be valid for the same block (e.g. during
+/// a root chain re-org). Every candidate is compared in constant time, and all
+/// candidates are always checked so timing does not reveal the matching index.
+pub fn match_root<HashType, Hasher>(
+    key: &BitSlice,
+    leaf_hash: HashType,
+    proof: &Proof<HashType>,
+    candidates: &[HashType],
+    hasher: Hasher,
+) -> Result<Option<usize>, MerkleError>
+    where
+        HashType: AsRef<[u8]>,
+        Hasher: NodeHasher<Out = HashType>,
+{
+    let root = get_root(key, leaf_hash, proof, hasher)?;
+
+    let mut matched = None;
+    for (i, candidate) in candidates.iter().enumerate() {
+        if ct_eq(root.as_ref(), candidate.as_ref()) && matched.is_none() {
+            matched = Some(i);
+        }
+    }
+    Ok(matched)
+}
+
+/// Result of a key-binding verification with `verify_bound`.
+#[derive(Debug, PartialEq)]
+pub enum Binding {
+    /// Proof verifies against the root, and only for this key.
+    Bound,
+    /// Proof verifies against the root, but flipping the key bit at this
+    /// level (index into the proof, root->leaf order) would also verify.
+    AmbiguousBinding { level: usize },
+    /// Proof does not verify against the root.
+    RootMismatch,
+}
+
+/// Verify `proof` for `leaf_hash` at `key` against `root`, additionally
+/// checking that the proof could not equally be claimed for a different key.
+///
+/// `default_nodes` are the empty-subtree hashes for each level, in the same
+/// root->leaf order as `proof`. For every level where the sibling is the
+/// default node, the node is re-hashed with the path bit flipped; if both
+/// orderings produce the same parent, the key bit at that level is not
+/// actually bound by the proof.
+///
+/// # Note
+/// This costs up to one extra hash per level, so roughly twice the hashing
+/// of `get_root` on proofs that are mostly default siblings (the common case
+/// for sparse trees).
+pub fn verify_bound<HashType, Hasher>(
+    key: &BitSlice,
+    leaf_hash: HashType,
+    proof: &Proof<HashType>,
+    root: &HashType,
+    default_nodes: &[HashType],
+    hasher: Hasher,
+) -> Result<Binding, MerkleError>
+    where
+        HashType: AsRef<[u8]>,
+        Hasher: NodeHasher<Out = HashType>,
+{
+    if key.len() != proof.len() {
+        return Err(MerkleError::ProofLengthMismatch {
+            key_bits: key.len(),
+            proof_len: proof.len(),
+        });
+    }
+    if default_nodes.len() != proof.len() {
+        return Err(MerkleError::DefaultNodesLengthMismatch {
+            proof_len: proof.len(),
+            default_nodes_len: default_nodes.len(),
+        });
+    }
+
+    let mut node_hash = leaf_hash;
+    let mut ambiguous_level = None;
+
+    // Same leaf->root walk as `get_root`, tracking the level as a proof index
+    let levels = key.iter().zip(proof.iter()).zip(default_nodes.iter()).enumerate().rev();
+    for (level, ((is_right, sibling_node), default_node)) in levels {
+        let (left, right) = if is_right {
+            (sibling_node.as_ref(), node_hash.as_ref())
+        } else {
+            (node_hash.as_ref(), sibling_node.as_ref())
+        };
+        let node = left.iter().chain(right.iter()).copied().collect::<Vec<u8>>();
+        let parent = hasher.hash(node.as_slice());
+
+        if ambiguous_level.is_none() && sibling_node.as_ref() == default_node.as_ref() {
+            let flipped = right.iter().chain(left.iter()).copied().collect::<Vec<u8>>();
+            if hasher.hash(flipped.as_slice()).as_ref() == parent.as_ref() {
+                ambiguous_level = Some(level);
+            }
+        }
+
+        node_hash = parent;
+    }
+
+    if !ct_eq(node_hash.as_ref(), root.as_ref()) {
+        return Ok(Binding::RootMismatch);
+    }
+    Ok(match ambiguous_level {
+        Some(level) => Binding::AmbiguousBinding { level },
+        None => Binding::Bound,
+    })
+}
+
+// Compare two byte strings without short-circuiting on the first difference
+fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 // TODO Add SMT MerkleDB for txn trie inclusion/exclusion checks
 
 /// Tests generated using Python package `py-trie`, which contains a Sparse Merkle Tree
@@ -55,18 +366,15 @@ mod test {
 
     use bitvec::prelude::*;
     use ethereum_types::H256;
-    extern crate hex;
     use keccak_hash::keccak;
+    use crate::hex::decode_fixed;
 
     fn hasher(input: &[u8]) -> H256 {
         keccak(input)
     }
 
     fn hex_to_h256(hexstr: &str) -> H256 {
-        let bytes = hex::decode(hexstr).unwrap();
-        let mut bytes32 = [0u8; 32];
-        bytes32.copy_from_slice(bytes.as_ref());
-        H256::from(bytes32)
+        H256::from(decode_fixed::<32>(hexstr).unwrap())
     }
 
     #[test]
@@ -76,22 +384,26 @@ mod test {
         let leaf_hash = hex_to_h256(
             "0000000000000000000000000000000000000000000000000000000000000000"
         );
-        let proof = vec![
+        let proof = [
             // Should be 8 nodes, not 1
             "0000000000000000000000000000000000000000000000000000000000000000",
         ].iter().map(|h| hex_to_h256(h)).collect::<Vec<H256>>();
-        assert!(get_root(key, leaf_hash, proof, hasher).is_err());
+        assert_eq!(
+            get_root(key, leaf_hash, &Proof::from(proof), hasher),
+            Err(MerkleError::ProofLengthMismatch { key_bits: 8, proof_len: 1 })
+        );
+        assert_eq!(
+            MerkleError::ProofLengthMismatch { key_bits: 8, proof_len: 1 }.to_string(),
+            "Key must be the same size as the proof!"
+        );
     }
 
-    #[test]
-    /// `calc_root(b"\x07", EMPTY_BYTES32, [EMPTY_BYTES32] * 8)`
-    fn depth_8_root_blank_node() {
-        let key: u8 = 7;
-        let key: &BitSlice = key.as_bitslice::<BigEndian>();
+    /// Leaf hash, proof, and root for `depth_8_root_blank_node` at key `7`.
+    fn depth_8_fixture() -> (H256, Proof<H256>, H256) {
         let leaf_hash = hex_to_h256( // hash of empty bytes32
             "290decd9548b62a8d60345a988386fc84ba6bc95484008f6362f93160ef3e563"
         );
-        let proof = vec![
+        let proof = [
             "0000000000000000000000000000000000000000000000000000000000000008",
             "0000000000000000000000000000000000000000000000000000000000000007",
             "0000000000000000000000000000000000000000000000000000000000000006",
@@ -101,10 +413,204 @@ mod test {
             "0000000000000000000000000000000000000000000000000000000000000002",
             "0000000000000000000000000000000000000000000000000000000000000001",
         ].iter().map(|h| hex_to_h256(h)).collect::<Vec<H256>>();
-        let calculated_root = get_root(key, leaf_hash, proof, hasher).unwrap();
         let root = hex_to_h256(
             "1c0285e9d02f7aec67b4916dfe37254a507e00159bb4bb87a8511f9b6375f5ca"
         );
+        (leaf_hash, Proof::new(proof, 8).unwrap(), root)
+    }
+
+    #[test]
+    /// `calc_root(b"\x07", EMPTY_BYTES32, [EMPTY_BYTES32] * 8)`
+    fn depth_8_root_blank_node() {
+        let key: u8 = 7;
+        let key: &BitSlice = key.as_bitslice::<BigEndian>();
+        let (leaf_hash, proof, root) = depth_8_fixture();
+        let calculated_root = get_root(key, leaf_hash, &proof, hasher).unwrap();
         assert_eq!(root, calculated_root);
     }
+
+    /// Default nodes (root->leaf order) and root of an empty tree of `depth`.
+    fn empty_tree(depth: usize, empty_leaf: H256) -> (Vec<H256>, H256) {
+        let mut defaults = vec![empty_leaf];
+        for _ in 0..depth {
+            let last = *defaults.last().unwrap();
+            let node = last.as_ref().iter().chain(last.as_ref().iter())
+                .copied().collect::<Vec<u8>>();
+            defaults.push(hasher(&node));
+        }
+        let root = defaults.pop().unwrap();
+        defaults.reverse();
+        (defaults, root)
+    }
+
+    #[test]
+    fn verify_bound_single_leaf() {
+        let key: u8 = 7;
+        let key: &BitSlice = key.as_bitslice::<BigEndian>();
+        let empty_leaf = hasher(&[0u8; 32]);
+        let (defaults, _) = empty_tree(8, empty_leaf);
+
+        // Tree holding a single non-empty leaf at key 7
+        let leaf_hash = hasher(b"transaction");
+        let proof = Proof::from_slice(&defaults, 8).unwrap();
+        let root = get_root(key, leaf_hash, &proof, hasher).unwrap();
+        assert_eq!(
+            verify_bound(key, leaf_hash, &proof, &root, &defaults, hasher),
+            Ok(Binding::Bound)
+        );
+
+        // The same proof paired with another key must not verify
+        let other: u8 = 6;
+        let other: &BitSlice = other.as_bitslice::<BigEndian>();
+        assert_eq!(
+            verify_bound(other, leaf_hash, &proof, &root, &defaults, hasher),
+            Ok(Binding::RootMismatch)
+        );
+    }
+
+    #[test]
+    fn verify_bound_detects_ambiguous_key() {
+        let key: u8 = 7;
+        let key: &BitSlice = key.as_bitslice::<BigEndian>();
+        let empty_leaf = hasher(&[0u8; 32]);
+        let (defaults, root) = empty_tree(8, empty_leaf);
+
+        // Exclusion proof in an empty tree verifies for every key
+        let proof = Proof::from_slice(&defaults, 8).unwrap();
+        assert_eq!(get_root(key, empty_leaf, &proof, hasher).unwrap(), root);
+        assert_eq!(
+            verify_bound(key, empty_leaf, &proof, &root, &defaults, hasher),
+            Ok(Binding::AmbiguousBinding { level: 7 })
+        );
+    }
+
+    #[test]
+    fn verify_bound_checks_sizes() {
+        let key: u8 = 7;
+        let key: &BitSlice = key.as_bitslice::<BigEndian>();
+        let (leaf_hash, proof, root) = depth_8_fixture();
+        assert_eq!(
+            verify_bound(key, leaf_hash, &proof, &root, &[], hasher),
+            Err(MerkleError::DefaultNodesLengthMismatch { proof_len: 8, default_nodes_len: 0 })
+        );
+    }
+
+    #[test]
+    fn match_root_second_candidate() {
+        let key: u8 = 7;
+        let key: &BitSlice = key.as_bitslice::<BigEndian>();
+        let (leaf_hash, proof, root) = depth_8_fixture();
+        let candidates = [H256::zero(), root];
+        let matched = match_root(key, leaf_hash, &proof, &candidates, hasher).unwrap();
+        assert_eq!(matched, Some(1));
+    }
+
+    #[test]
+    fn match_root_no_candidate() {
+        let key: u8 = 7;
+        let key: &BitSlice = key.as_bitslice::<BigEndian>();
+        let (leaf_hash, proof, _) = depth_8_fixture();
+        let candidates = [H256::zero(), H256::repeat_byte(1)];
+        let matched = match_root(key, leaf_hash, &proof, &candidates, hasher).unwrap();
+        assert_eq!(matched, None);
+    }
+
+    #[test]
+    fn match_root_single_candidate_agrees_with_get_root() {
+        let key: u8 = 7;
+        let key: &BitSlice = key.as_bitslice::<BigEndian>();
+        let (leaf_hash, proof, root) = depth_8_fixture();
+        let calculated_root = get_root(key, leaf_hash, &proof, hasher).unwrap();
+        let matched = match_root(key, leaf_hash, &proof, &[root], hasher).unwrap();
+        assert_eq!(matched.is_some(), calculated_root == root);
+
+        // Wrong-length proofs still error instead of matching nothing
+        assert_eq!(
+            match_root(key, leaf_hash, &Proof::default(), &[root], hasher),
+            Err(MerkleError::ProofLengthMismatch { key_bits: 8, proof_len: 0 })
+        );
+    }
+    // Key is only known at runtime, so can't be baked into a plain `fn`
+    #[derive(Clone, Copy)]
+    struct KeyedHasher {
+        key: [u8; 32],
+    }
+
+    impl NodeHasher for KeyedHasher {
+        type Out = H256;
+
+        fn hash(&self, data: &[u8]) -> H256 {
+            keccak([&self.key[..], data].concat())
+        }
+    }
+
+    #[test]
+    fn keyed_hasher() {
+        let key: u8 = 7;
+        let key: &BitSlice = key.as_bitslice::<BigEndian>();
+        let (leaf_hash, proof, root) = depth_8_fixture();
+        let hasher_a = KeyedHasher { key: [1; 32] };
+        let hasher_b = KeyedHasher { key: [2; 32] };
+
+        let root_a = get_root(key, leaf_hash, &proof, hasher_a).unwrap();
+        let root_b = get_root(key, leaf_hash, &proof, hasher_b).unwrap();
+        assert_ne!(root_a, root_b);
+        assert_ne!(root_a, root);
+        assert_eq!(match_root(key, leaf_hash, &proof, &[root_b, root_a], hasher_a), Ok(Some(1)));
+
+        // Plain functions and closures still work
+        assert_eq!(get_root(key, leaf_hash, &proof, |data: &[u8]| keccak(data)), Ok(root));
+        assert_eq!(hasher.hash(b"data"), keccak(b"data"));
+    }
+
+    #[test]
+    fn proof_from_slice() {
+        let (_, proof, _) = depth_8_fixture();
+        let nodes = proof.as_slice().to_vec();
+        assert_eq!(Proof::from_slice(&nodes, 8), Ok(proof.clone()));
+        assert_eq!(proof.len(), 8);
+        assert!(proof.iter().eq(nodes.iter()));
+        assert!(proof.iter_leaf_to_root().eq(nodes.iter().rev()));
+        assert_eq!(proof.into_inner(), nodes);
+    }
+
+    #[test]
+    fn proof_wrong_depth_rejected() {
+        let (_, proof, _) = depth_8_fixture();
+        assert_eq!(
+            Proof::from_slice(proof.as_slice(), 16),
+            Err(MerkleError::ProofLengthMismatch { key_bits: 16, proof_len: 8 })
+        );
+        assert_eq!(
+            Proof::from_slice(&proof.as_slice()[1..], 8),
+            Err(MerkleError::ProofLengthMismatch { key_bits: 8, proof_len: 7 })
+        );
+    }
+
+    #[test]
+    fn proof_bytes_round_trip() {
+        let (_, proof, _) = depth_8_fixture();
+        let bytes = proof.to_bytes();
+        assert_eq!(bytes.len(), 4 + 8 * 32);
+        assert_eq!(Proof::<H256>::from_bytes(&bytes), Ok(proof));
+        assert_eq!(Proof::<H256>::from_bytes(&0u32.to_be_bytes()), Ok(Proof::default()));
+
+        // Truncated hashes and trailing bytes are both rejected
+        assert!(Proof::<H256>::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut long = bytes.clone();
+        long.push(0);
+        assert_eq!(
+            Proof::<H256>::from_bytes(&long),
+            Err(CodecError::TrailingBytes { remaining: 1 })
+        );
+    }
+
+    #[test]
+    fn proof_bytes_unchanged() {
+        // Node count, then the raw nodes, as the first `to_bytes()` wrote it
+        let proof: Proof<[u8; 4]> = Proof::from(vec![[1u8; 4], [0xab; 4], [0xff, 0, 0x10, 2]]);
+        let bytes = [0, 0, 0, 3, 1, 1, 1, 1, 0xab, 0xab, 0xab, 0xab, 0xff, 0, 0x10, 2];
+        assert_eq!(proof.to_bytes(), bytes);
+        assert_eq!(Proof::from_bytes(&bytes), Ok(proof));
+    }
 }
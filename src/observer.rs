@@ -0,0 +1,159 @@
+//! Callbacks for mirroring changes to a `Token` elsewhere (e.g. a database).
+
+use crate::token::TokenStatus;
+
+/// Notified of changes to a token's status and history made through the
+/// methods on `Token`, see `Token::set_observer`.
+///
+/// Every callback does nothing by default, so only the interesting ones need
+/// implementing.
+///
+/// # Note
+/// Indices and lengths are those of `Token::history()`. Changes to the
+/// token's challenges, exclusion proofs and settings (e.g.
+/// `Token::set_max_history`) are not reported, nor are changes made directly
+/// to the public fields of `Token` (e.g. `exclusion_proofs`).
+pub trait TokenObserver<TxnType> {
+    /// `txn` was added to the history at `index`.
+    fn on_transaction_added(&mut self, _txn: &TxnType, _index: usize) { }
+
+    /// Status changed from `old` to `new`.
+    fn on_status_changed(&mut self, _old: TokenStatus, _new: TokenStatus) { }
+
+    /// Entries were removed from the end of the history, leaving `new_len`.
+    fn on_history_truncated(&mut self, _new_len: usize) { }
+
+    /// The first `count` entries were pruned from the front of the history
+    /// (see `Token::prune_history`), moving the rest down by `count`.
+    fn on_history_pruned(&mut self, _count: usize) { }
+
+    /// Inclusion block, proof or confirmation of the entry at `index` changed
+    /// (see `Token::set_inclusion` and `Token::confirm`).
+    fn on_entry_updated(&mut self, _index: usize) { }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use crate::BitVec;
+    use crate::token::test::{move_to_status, new_token, proven_token, MockTransaction};
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Added(MockTransaction, usize),
+        StatusChanged(TokenStatus, TokenStatus),
+        Truncated(usize),
+        Pruned(usize),
+        Updated(usize),
+    }
+
+    struct Recorder(Arc<Mutex<Vec<Event>>>);
+
+    impl TokenObserver<MockTransaction> for Recorder {
+        fn on_transaction_added(&mut self, txn: &MockTransaction, index: usize) {
+            self.0.lock().unwrap().push(Event::Added(txn.clone(), index));
+        }
+
+        fn on_status_changed(&mut self, old: TokenStatus, new: TokenStatus) {
+            self.0.lock().unwrap().push(Event::StatusChanged(old, new));
+        }
+
+        fn on_history_truncated(&mut self, new_len: usize) {
+            self.0.lock().unwrap().push(Event::Truncated(new_len));
+        }
+
+        fn on_history_pruned(&mut self, count: usize) {
+            self.0.lock().unwrap().push(Event::Pruned(count));
+        }
+
+        fn on_entry_updated(&mut self, index: usize) {
+            self.0.lock().unwrap().push(Event::Updated(index));
+        }
+    }
+
+    #[test]
+    fn records_changes_in_order() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut t = new_token(1);
+        t.set_observer(Box::new(Recorder(events.clone())));
+
+        let uid = BitVec::from_element(1u8);
+        let txns: Vec<MockTransaction> = (0..3)
+            .map(|i| MockTransaction::new(uid.clone(), i, i + 1, i))
+            .collect();
+
+        assert!(t.begin_deposit().is_ok());
+        assert!(t.finalize_deposit().is_ok());
+        assert!(t.add_transaction(txns[0].clone()).is_ok());
+        assert!(t.add_transactions(txns[1..].to_vec()).is_ok());
+        assert_eq!(t.revert_last_transaction(), Some(txns[2].clone()));
+        assert!(t.add_transaction(txns[0].clone()).is_err()); // Rejected, so not reported
+        assert!(t.begin_withdrawal().is_ok());
+        assert!(t.start_exit(7).is_ok());
+
+        // Clones don't share the observer
+        let mut clone = t.clone();
+        assert!(clone.challenge_exit().is_ok());
+
+        assert!(t.take_observer().is_some());
+        assert!(t.finalize_withdrawal().is_ok());
+
+        assert_eq!(*events.lock().unwrap(), vec![
+            Event::StatusChanged(TokenStatus::RootChain, TokenStatus::Deposit),
+            Event::StatusChanged(TokenStatus::Deposit, TokenStatus::PlasmaChain),
+            Event::Added(txns[0].clone(), 0),
+            Event::Added(txns[1].clone(), 1),
+            Event::Added(txns[2].clone(), 2),
+            Event::Truncated(2),
+            Event::StatusChanged(TokenStatus::PlasmaChain, TokenStatus::Withdrawal),
+            Event::StatusChanged(TokenStatus::Withdrawal, TokenStatus::Exiting { started_at_block: 7 }),
+        ]);
+    }
+
+    #[test]
+    fn reports_rollback() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        t.set_observer(Box::new(Recorder(events.clone())));
+
+        let cp = t.checkpoint();
+        let txn = MockTransaction::new(t.uid.clone(), 0, 1, 0);
+        assert!(t.add_transaction(txn.clone()).is_ok());
+        assert!(t.begin_withdrawal().is_ok());
+        assert!(t.rollback(cp).is_ok());
+        assert!(t.revert_to(0).is_empty()); // Nothing to remove, so not reported
+
+        assert_eq!(*events.lock().unwrap(), vec![
+            Event::Added(txn, 0),
+            Event::StatusChanged(TokenStatus::PlasmaChain, TokenStatus::Withdrawal),
+            Event::Truncated(0),
+            Event::StatusChanged(TokenStatus::Withdrawal, TokenStatus::PlasmaChain),
+        ]);
+    }
+
+    #[test]
+    fn reports_pruning_and_entry_updates() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let (mut t, roots) = proven_token();
+        let mut entry = t.history()[1].clone();
+        entry.block_root = Some(roots[&4]);
+        assert!(t.revert_last_transaction().is_some());
+        assert!(t.add_entry(entry.clone()).is_ok());
+        t.set_observer(Box::new(Recorder(events.clone())));
+
+        assert!(t.confirm(1, [&[1u8][..], &roots[&4]].concat()).is_ok());
+        assert!(t.set_inclusion(1, 4, entry.proof.unwrap()).is_ok());
+        assert!(t.prune_history(1).is_ok());
+        assert!(t.prune_history(0).is_ok()); // Nothing to prune, so not reported
+
+        assert_eq!(*events.lock().unwrap(), vec![
+            Event::Updated(1),
+            Event::Updated(1),
+            Event::Pruned(1),
+        ]);
+    }
+}
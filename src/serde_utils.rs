@@ -0,0 +1,370 @@
+//! Serde representations for field types without a stable one of their own.
+//!
+//! Hashes (and byte-aligned uids) are `0x`-prefixed hex strings in
+//! human-readable formats such as JSON, and raw bytes in binary formats. The
+//! modules here work with `#[serde(with = "...")]`, so transaction types can
+//! encode their own hash fields the same way, e.g.
+//! ```ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct Transaction {
+//!     #[serde(with = "plasma_cash_tokens::serde_utils::hash")]
+//!     prev_hash: H256,
+//!     ...
+//! }
+//! ```
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+#[cfg(not(feature = "std"))]
+use core::result::Result;
+
+use core::convert::TryFrom;
+use core::fmt;
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::{self, Visitor, SeqAccess, Unexpected};
+use serde::ser::SerializeSeq;
+
+use crate::hex;
+use crate::merkle::Proof;
+
+// Serializes as bytes, so binary formats don't store one integer per byte,
+// or as a `0x`-prefixed hex string for human-readable formats
+struct Bytes<'a>(&'a [u8]);
+
+impl Serialize for Bytes<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&hex::encode(self.0, true))
+        } else {
+            serializer.serialize_bytes(self.0)
+        }
+    }
+}
+
+// Accepts native bytes, hex strings, and sequences of integers (e.g. JSON
+// arrays written before hashes were encoded as hex)
+struct ByteBuf(Vec<u8>);
+
+struct ByteBufVisitor;
+
+impl<'de> Visitor<'de> for ByteBufVisitor {
+    type Value = ByteBuf;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a byte array")
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<ByteBuf, E> {
+        Ok(ByteBuf(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<ByteBuf, E> {
+        Ok(ByteBuf(v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<ByteBuf, E> {
+        hex::decode(v).map(ByteBuf).map_err(E::custom)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ByteBuf, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(b) = seq.next_element()? {
+            bytes.push(b);
+        }
+        Ok(ByteBuf(bytes))
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ByteBuf, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(ByteBufVisitor)
+        } else {
+            deserializer.deserialize_bytes(ByteBufVisitor)
+        }
+    }
+}
+
+// Proof as a sequence of hashes, each stored as bytes
+impl<HashType: AsRef<[u8]>> Serialize for Proof<HashType> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len()))?;
+        for hash in self {
+            seq.serialize_element(&Bytes(hash.as_ref()))?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de, HashType: Default + AsMut<[u8]>> Deserialize<'de> for Proof<HashType> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let nodes = <Vec<ByteBuf>>::deserialize(deserializer)?;
+        nodes.iter()
+            .map(|ByteBuf(bytes)| hash_from_bytes(bytes))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Proof::from)
+    }
+}
+
+fn hash_from_bytes<HashType, E>(bytes: &[u8]) -> Result<HashType, E>
+    where
+        HashType: Default + AsMut<[u8]>,
+        E: de::Error,
+{
+    let mut hash = HashType::default();
+    if hash.as_mut().len() != bytes.len() {
+        return Err(E::invalid_length(bytes.len(), &"a hash of the expected size"));
+    }
+    hash.as_mut().copy_from_slice(bytes);
+    Ok(hash)
+}
+
+/// `BitVec` as `(bit length, bytes)`, with unused trailing bits zeroed so the
+/// encoding is canonical and bit order survives a round-trip.
+///
+/// # Note
+/// Human-readable formats store a `BitVec` of whole bytes as just its hex
+/// string, since the bit length follows from it.
+pub mod bitvec {
+    use super::*;
+
+    use ::bitvec::prelude::BitVec;
+
+    use crate::codec::bitvec_to_bytes;
+
+    pub fn serialize<S: Serializer>(uid: &BitVec, serializer: S) -> Result<S::Ok, S::Error> {
+        let bytes = bitvec_to_bytes(uid);
+        if serializer.is_human_readable() && uid.len() % 8 == 0 {
+            Bytes(&bytes).serialize(serializer)
+        } else {
+            (uid.len() as u64, Bytes(&bytes)).serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BitVec, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(BitVecVisitor)
+        } else {
+            let (len, ByteBuf(bytes)) = <(u64, ByteBuf)>::deserialize(deserializer)?;
+            from_parts(len, bytes)
+        }
+    }
+
+    fn from_parts<E: de::Error>(len: u64, bytes: Vec<u8>) -> Result<BitVec, E> {
+        let len = usize::try_from(len)
+            .map_err(|_| E::invalid_value(Unexpected::Unsigned(len), &"a bit length that fits in usize"))?;
+        if bytes.len() != len.div_ceil(8) {
+            return Err(E::invalid_length(bytes.len(), &"ceil(bit length / 8) bytes"));
+        }
+        let mut uid = BitVec::from_vec(bytes);
+        // Only zero padding is canonical, the same as for `codec::Reader::take_bitvec`
+        if uid.iter().skip(len).any(|bit| bit) {
+            return Err(E::custom("bit vector padding is not zero"));
+        }
+        uid.truncate(len);
+        Ok(uid)
+    }
+
+    struct BitVecVisitor;
+
+    impl<'de> Visitor<'de> for BitVecVisitor {
+        type Value = BitVec;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "a hex string or a (bit length, bytes) pair")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<BitVec, E> {
+            hex::decode(v).map(BitVec::from_vec).map_err(E::custom)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<BitVec, A::Error> {
+            let len = seq.next_element()?
+                .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+            let ByteBuf(bytes) = seq.next_element()?
+                .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+            if seq.next_element::<de::IgnoredAny>()?.is_some() {
+                return Err(de::Error::invalid_length(3, &self));
+            }
+            from_parts(len, bytes)
+        }
+    }
+}
+
+/// `HashType` stored as bytes.
+pub mod hash {
+    use super::*;
+
+    pub fn serialize<HashType, S>(hash: &HashType, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            HashType: AsRef<[u8]>,
+            S: Serializer,
+    {
+        Bytes(hash.as_ref()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, HashType, D>(deserializer: D) -> Result<HashType, D::Error>
+        where
+            HashType: Default + AsMut<[u8]>,
+            D: Deserializer<'de>,
+    {
+        let ByteBuf(bytes) = ByteBuf::deserialize(deserializer)?;
+        hash_from_bytes(&bytes)
+    }
+}
+
+/// `Option<HashType>` stored as bytes.
+pub mod optional_hash {
+    use super::*;
+
+    pub fn serialize<HashType, S>(hash: &Option<HashType>, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            HashType: AsRef<[u8]>,
+            S: Serializer,
+    {
+        hash.as_ref().map(|hash| Bytes(hash.as_ref())).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, HashType, D>(deserializer: D) -> Result<Option<HashType>, D::Error>
+        where
+            HashType: Default + AsMut<[u8]>,
+            D: Deserializer<'de>,
+    {
+        match <Option<ByteBuf>>::deserialize(deserializer)? {
+            Some(ByteBuf(bytes)) => hash_from_bytes(&bytes).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// `Vec<BitVec>` as a sequence of `BitVec`s, each the same as for `bitvec`.
+pub mod bitvecs {
+    use super::*;
+
+    use ::bitvec::prelude::BitVec;
+
+    struct Uid<'a>(&'a BitVec);
+
+    impl Serialize for Uid<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            super::bitvec::serialize(self.0, serializer)
+        }
+    }
+
+    struct OwnedUid(BitVec);
+
+    impl<'de> Deserialize<'de> for OwnedUid {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            super::bitvec::deserialize(deserializer).map(OwnedUid)
+        }
+    }
+
+    pub fn serialize<S: Serializer>(uids: &[BitVec], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(uids.len()))?;
+        for uid in uids {
+            seq.serialize_element(&Uid(uid))?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<BitVec>, D::Error> {
+        let uids = <Vec<OwnedUid>>::deserialize(deserializer)?;
+        Ok(uids.into_iter().map(|OwnedUid(uid)| uid).collect())
+    }
+}
+
+/// `BTreeMap<u64, Proof<HashType>>` exclusion proofs, as a sequence of
+/// `(block number, proof)` pairs in block order.
+pub mod exclusion_proofs {
+    use super::*;
+
+    #[cfg(not(feature = "std"))]
+    use alloc::collections::BTreeMap;
+
+    #[cfg(feature = "std")]
+    use std::collections::BTreeMap;
+
+    pub fn serialize<HashType, S>(
+        proofs: &BTreeMap<u64, Proof<HashType>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+        where
+            HashType: AsRef<[u8]>,
+            S: Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(proofs.len()))?;
+        for (block_num, proof) in proofs {
+            seq.serialize_element(&(block_num, proof))?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, HashType, D>(deserializer: D) -> Result<BTreeMap<u64, Proof<HashType>>, D::Error>
+        where
+            HashType: Default + AsMut<[u8]>,
+            D: Deserializer<'de>,
+    {
+        let raw = <Vec<(u64, Proof<HashType>)>>::deserialize(deserializer)?;
+        let mut proofs = BTreeMap::new();
+        for (block_num, proof) in raw {
+            if proofs.insert(block_num, proof).is_some() {
+                return Err(de::Error::custom("duplicate exclusion proof block number"));
+            }
+        }
+        Ok(proofs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Downstream transaction type reusing the helpers for its own fields
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Transaction {
+        #[serde(with = "hash")]
+        prev_hash: [u8; 4],
+        #[serde(with = "optional_hash")]
+        parent_root: Option<[u8; 4]>,
+    }
+
+    #[test]
+    fn hash_helpers_round_trip() {
+        let txn = Transaction { prev_hash: [0xde, 0xad, 0xbe, 0xef], parent_root: Some([1; 4]) };
+
+        let json = serde_json::to_string(&txn).unwrap();
+        assert_eq!(json, r#"{"prev_hash":"0xdeadbeef","parent_root":"0x01010101"}"#);
+        assert_eq!(serde_json::from_str::<Transaction>(&json).unwrap(), txn);
+
+        // Prefix is optional when decoding, but the size must match
+        let unprefixed = r#"{"prev_hash":"deadbeef","parent_root":null}"#;
+        let decoded: Transaction = serde_json::from_str(unprefixed).unwrap();
+        assert_eq!(decoded.prev_hash, txn.prev_hash);
+        assert_eq!(decoded.parent_root, None);
+        assert!(serde_json::from_str::<Transaction>(r#"{"prev_hash":"0xdead","parent_root":null}"#).is_err());
+        assert!(serde_json::from_str::<Transaction>(r#"{"prev_hash":"0xdeadbeeg","parent_root":null}"#).is_err());
+
+        let binary = bincode::serialize(&txn).unwrap();
+        assert!(binary.windows(4).any(|w| w == [0xde, 0xad, 0xbe, 0xef]));
+        assert_eq!(bincode::deserialize::<Transaction>(&binary).unwrap(), txn);
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Uid(#[serde(with = "bitvec")] ::bitvec::prelude::BitVec);
+
+    #[test]
+    fn bitvec_padding_must_be_zero() {
+        let uid = Uid([true, false, true].iter().copied().collect());
+        let json = serde_json::to_string(&uid).unwrap();
+        assert_eq!(json, r#"[3,"0xa0"]"#);
+        assert_eq!(serde_json::from_str::<Uid>(&json).unwrap(), uid);
+        assert!(serde_json::from_str::<Uid>(r#"[3,"0xa1"]"#).is_err());
+
+        let mut binary = bincode::serialize(&uid).unwrap();
+        assert_eq!(bincode::deserialize::<Uid>(&binary).unwrap(), uid);
+        *binary.last_mut().unwrap() |= 0b0000_0001;
+        assert!(bincode::deserialize::<Uid>(&binary).is_err());
+    }
+}
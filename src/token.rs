@@ -1,15 +1,64 @@
 #[cfg(not(feature = "std"))]
 use alloc::vec::Vec;
 
+#[cfg(not(feature = "std"))]
+use alloc::boxed::Box;
+
+#[cfg(not(feature = "std"))]
+use alloc::{format, string::String};
+
 #[cfg(not(feature = "std"))]
 use core::result::Result;
 
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeMap;
+
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
+
 use bitvec::prelude::BitVec;
 
-use crate::transaction::{PlasmaCashTxn, TxnCmp};
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+use core::cmp::Ordering;
+use core::convert::TryFrom;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::Range;
+use core::sync::atomic::{self, AtomicUsize};
+
+use crate::codec::{bitvec_to_bytes, CodecError, Reader, Writer};
+use crate::fee::{FeeAmount, FeeError};
+use crate::history_store::HistoryStore;
+use crate::hex::write_hex;
+use crate::merkle::{get_root, match_root, MerkleError, NodeHasher, Proof};
+use crate::observer::TokenObserver;
+use crate::transaction::{uid_matches, ConfirmableTxn, EncodableTxn, PlasmaCashTxn, TxnCmp};
+
+/// Version byte written at the start of `Token::to_bytes` output.
+const ENCODING_VERSION: u8 = 3;
+
+/// Version without block numbers or exclusion proofs, still accepted by `from_bytes`.
+const NO_EXCLUSIONS_ENCODING_VERSION: u8 = 2;
+
+/// Version with separate history and proof lists, still accepted by `from_bytes`.
+const LEGACY_ENCODING_VERSION: u8 = 1;
 
 /// Transfer and location status of the token.
-#[derive(Debug, PartialEq)]
+///
+/// Moves through `RootChain` → `Deposit` → `PlasmaChain` → `Withdrawal` →
+/// `Exiting` → `RootChain` via the transition methods on `Token`, with an
+/// exit moving between `Exiting` and `Challenged` while it is disputed.
+///
+/// # Note
+/// The binary encoding (`Token::to_bytes`) uses the stable discriminants
+/// `RootChain = 0`, `Deposit = 1`, `PlasmaChain = 2`, `Withdrawal = 3`,
+/// `Exiting = 4` and `Challenged = 5`, followed by `started_at_block` for
+/// the last two. New variants must only ever be appended.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum TokenStatus {
     /// Token is freely transferrable on the Root Chain.
     RootChain,
@@ -19,280 +68,5233 @@ pub enum TokenStatus {
     PlasmaChain,
     /// Token is in process of Withdrawal back to the Root Chain.
     Withdrawal,
+    /// Exit was started on the Root Chain at `started_at_block`.
+    Exiting { started_at_block: u64 },
+    /// Exit started at `started_at_block` is under challenge.
+    Challenged { started_at_block: u64 },
+}
+
+/// A transaction in a token's history, with its proof of inclusion in a
+/// block when one is known.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(
+    serialize = "TxnType: Serialize, HashType: AsRef<[u8]>",
+    deserialize = "TxnType: Deserialize<'de>, HashType: Default + AsMut<[u8]>",
+)))]
+pub struct HistoryEntry<TxnType, HashType> {
+    pub txn: TxnType,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub block_num: Option<u64>, // Child Chain block the transaction was included in
+    pub proof: Option<Proof<HashType>>, // SMT proof of inclusion
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::optional_hash"))]
+    pub block_root: Option<HashType>, // Root the proof was checked against
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub confirmation: Option<Vec<u8>>, // Sender's confirmation of the inclusion
+}
+
+impl<TxnType, HashType> HistoryEntry<TxnType, HashType> {
+    /// Entry for `txn` with no proof of inclusion.
+    pub fn new(txn: TxnType) -> HistoryEntry<TxnType, HashType> {
+        HistoryEntry {
+            txn,
+            block_num: None,
+            proof: None,
+            block_root: None,
+            confirmation: None,
+        }
+    }
+}
+
+impl<TxnType, HashType> HistoryEntry<TxnType, HashType>
+    where
+        TxnType: PlasmaCashTxn<HashType = HashType>,
+        HashType: AsRef<[u8]>,
+{
+    // `block_root`, if the entry's proof leads to it
+    fn proven_root(&self) -> Option<&HashType> {
+        let (proof, root) = (self.proof.as_ref()?, self.block_root.as_ref()?);
+        let candidates = core::slice::from_ref(root);
+        let key = self.txn.token_id();
+        let matched = match_root(&key, self.txn.leaf_hash(), proof, candidates, TxnType::hasher());
+        matches!(matched, Ok(Some(_))).then_some(root)
+    }
+
+    // Forget a `block_root` (and the confirmation of it) that the entry's
+    // proof doesn't prove, e.g. for an entry received from a peer
+    fn drop_unproven_root(&mut self) {
+        if self.proven_root().is_none() {
+            self.block_root = None;
+            self.confirmation = None;
+        }
+    }
 }
 
 /// Token storage type that performs history verification and challenge detection
 /// for a given token.
 ///
-/// Can be serialized for wire transmission and data storage purposes.
+/// Can be serialized for wire transmission and data storage purposes
+/// (enable the `serde` feature).
 ///
 /// # Example
 /// Users of this API should should define this e.g.
 /// ```ignore
 /// let t: Token<Transaction, H256> = Token::new(uid); // `uid` is BitVec
 /// ```
-pub struct Token<TxnType, HashType>
+///
+/// The history is kept in a `Vec` unless another `HistoryStore` is given,
+/// see `new_with_store()`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(
+    serialize = "TxnType: Serialize, Store: Serialize",
+    deserialize = "TxnType: Deserialize<'de>, Store: Deserialize<'de>, HashType: Default + AsMut<[u8]>",
+)))]
+pub struct Token<TxnType, HashType, Store = Vec<HistoryEntry<TxnType, HashType>>>
     where
         TxnType: PlasmaCashTxn,
         HashType: AsRef<[u8]>,
 {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::bitvec"))]
     pub uid: BitVec, // Key for Sparse Merkle Tree datastore
-    pub status: TokenStatus, // Convenience API
-    pub history: Vec<TxnType>, // List of transactions
-    pub proofs: Vec<Vec<HashType>>, // TODO Combine with history for complete inclusion/exclusion proofs
+    status: TokenStatus, // Convenience API
+    history: Store, // List of transactions, with proofs
+    #[cfg_attr(feature = "serde", serde(default, with = "crate::serde_utils::exclusion_proofs"))]
+    pub exclusion_proofs: BTreeMap<u64, Proof<HashType>>, // Block number -> proof token didn't move
+    #[cfg_attr(feature = "serde", serde(default))]
+    partially_imported: bool, // Set by `import_lenient` when entries were dropped
+    #[cfg_attr(feature = "serde", serde(default))]
+    pruned: usize, // Number of entries dropped from the front by `prune_history`
+    #[cfg_attr(feature = "serde", serde(default))]
+    max_history: Option<usize>, // Most entries the history may hold, see `set_max_history`
+    #[cfg_attr(feature = "serde", serde(default))]
+    strict: bool, // History must start with a deposit, see `new_strict`
+    #[cfg_attr(feature = "serde", serde(default))]
+    depth: Option<usize>, // Levels of the SMT the uid is a key of, see `new_with_depth`
+    #[cfg_attr(feature = "serde", serde(default, with = "crate::serde_utils::bitvecs"))]
+    split_from: Vec<BitVec>, // Uids of the coins this one was split from, see `split`
+    #[cfg_attr(feature = "serde", serde(default))]
+    challenges: Vec<ChallengeRecord<TxnType>>, // Conflicting txns seen, see `record_challenge`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    observer: Option<BoxedObserver<TxnType>>, // Notified of changes, see `set_observer`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    validated: AtomicUsize, // Leading entries known to pass `validate`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    _txn: PhantomData<TxnType>, // `Store` holds the transactions
 }
 
-impl<TxnType, HashType> Token<TxnType, HashType>
+type BoxedObserver<TxnType> = Box<dyn TokenObserver<TxnType> + Send + Sync>;
+
+// Observers aren't shared with clones
+impl<TxnType, HashType, Store> Clone for Token<TxnType, HashType, Store>
     where
-        TxnType: PlasmaCashTxn,
-        HashType: AsRef<[u8]>,
+        TxnType: PlasmaCashTxn + Clone,
+        HashType: AsRef<[u8]> + Clone,
+        Store: Clone,
 {
-    /// Create new token with given uid stored on the rootchain.
-    /// (history is empty to start)
-    pub fn new(uid: BitVec) -> Token<TxnType, HashType> {
+    fn clone(&self) -> Self {
         Token {
-            uid,
-            status: TokenStatus::RootChain,
-            history: Vec::new(),
-            proofs: Vec::new(),
+            uid: self.uid.clone(),
+            status: self.status,
+            history: self.history.clone(),
+            exclusion_proofs: self.exclusion_proofs.clone(),
+            partially_imported: self.partially_imported,
+            pruned: self.pruned,
+            max_history: self.max_history,
+            strict: self.strict,
+            depth: self.depth,
+            split_from: self.split_from.clone(),
+            challenges: self.challenges.clone(),
+            observer: None,
+            validated: AtomicUsize::new(self.validated.load(atomic::Ordering::Relaxed)),
+            _txn: PhantomData,
         }
     }
+}
 
-    /// Validate history of token is consistent
-    pub fn is_valid(&self) -> bool {
-        is_history_valid(&self.history)
-    }
+// Displays a uid as 0x-prefixed hex, zero-padded to whole bytes
+struct UidHex<'a>(&'a BitVec);
 
-    /// Add a new transaction to the history. Must first pass validation
-    /// that new transaction follows old one.
-    pub fn add_transaction(&mut self, txn: TxnType) -> Result<(), &'static str> {
-        match self.history.last() {
-            Some(last_txn) if txn.compare(last_txn) != TxnCmp::Child =>
-                Err("Transaction is not a child of previous transaction."),
-            _ => {
-                self.history.push(txn);
-                Ok(())
-            },
-        }
+impl fmt::Debug for UidHex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x")?;
+        write_hex(f, &bitvec_to_bytes(self.0))
     }
 }
 
-// Validate ordered list of all transactions for a given token
-fn is_history_valid<TxnType>(
-    history: &[TxnType],
-) -> bool
+// Summary only, histories can be very long
+impl<TxnType, HashType, Store> fmt::Debug for Token<TxnType, HashType, Store>
     where
         TxnType: PlasmaCashTxn,
-
+        HashType: AsRef<[u8]>,
+        Store: HistoryStore<TxnType, HashType>,
 {
-    // If token has no history, return True
-    if history.is_empty() {
-        return true;
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Token")
+            .field("uid", &UidHex(&self.uid))
+            .field("status", &self.status)
+            .field("history_len", &self.history.len())
+            .finish()
     }
+}
 
-    // Ensure all transactions are invidiually well-formed
-    if !history.iter().all(|txn| txn.valid()) {
-        return false;
+impl<TxnType, HashType, Store> PartialEq for Token<TxnType, HashType, Store>
+    where
+        TxnType: PlasmaCashTxn + PartialEq,
+        HashType: AsRef<[u8]> + PartialEq,
+        Store: HistoryStore<TxnType, HashType>,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.uid == other.uid
+            && self.status == other.status
+            && self.history.iter().eq(other.history.iter())
+            && self.exclusion_proofs == other.exclusion_proofs
+            && self.partially_imported == other.partially_imported
+            && self.pruned == other.pruned
+            && self.max_history == other.max_history
+            && self.strict == other.strict
+            && self.depth == other.depth
+            && self.split_from == other.split_from
+            && self.challenges == other.challenges
     }
+}
 
-    // History is valid if each txn is the child of the previous
-    let mut history_iter = history.iter().peekable();
-    while let Some(prev_txn) = history_iter.next() {
-        if let Some(txn) = history_iter.peek() {
-            match txn.compare(&prev_txn) {
-                TxnCmp::Child => { },
-                _ => { return false },
-            }
+impl<TxnType, HashType, Store> Eq for Token<TxnType, HashType, Store>
+    where
+        TxnType: PlasmaCashTxn + Eq,
+        HashType: AsRef<[u8]> + Eq,
+        Store: HistoryStore<TxnType, HashType>,
+{}
+
+/// Errors from building, extending, or decoding a `Token`.
+#[derive(Debug, PartialEq)]
+pub enum TokenError {
+    /// Transaction does not follow the last one in the history.
+    NotChildOfPrevious { got: TxnCmp },
+    /// Transaction at this index of a batch does not follow the one before it.
+    BatchNotChildOfPrevious { index: usize, got: TxnCmp },
+    /// Transaction failed its own well-formedness check.
+    InvalidTransaction,
+    /// Transaction at this index of the history failed its own
+    /// well-formedness check, with the `Debug` form of its error.
+    InvalidTransactionAt { index: usize, cause: String },
+    /// Transaction cannot start a history, as it is not a deposit.
+    NotDeposit,
+    /// Transaction doesn't spend a later block than the one before it.
+    BlockNumberNotIncreasing,
+    /// Transaction was not checked because an earlier one was rejected.
+    FollowsRejectedTransaction,
+    /// Operation is not allowed while the token has this status.
+    WrongStatus { current: TokenStatus },
+    /// Status change is not a legal step of the token lifecycle.
+    InvalidStatusTransition { from: TokenStatus, to: TokenStatus },
+    /// Encoding starts with a version this crate doesn't understand.
+    UnsupportedEncodingVersion(u8),
+    /// Encoding contains an unknown status discriminant.
+    UnknownStatus(u8),
+    /// Encoding contains unknown flag bits.
+    UnknownFlags(u8),
+    /// A transaction in the encoding could not be decoded.
+    MalformedTransaction,
+    /// Encoding is truncated, oversized, or has trailing bytes.
+    Codec(CodecError),
+    /// Fees could not be added up.
+    Fee(FeeError),
+    /// Decoded history does not pass validation.
+    InvalidHistory,
+    /// Legacy proof list does not line up with the history.
+    ProofCountMismatch { history: usize, proofs: usize },
+    /// Transaction at this index is for a different token.
+    WrongToken { index: usize },
+    /// History no longer contains the point the checkpoint was taken at.
+    StaleCheckpoint,
+    /// Token has no history to exit with.
+    EmptyHistory,
+    /// Tokens are not the two halves of a split coin.
+    NotSiblings,
+    /// Halves of a split coin have different histories, so can't be merged.
+    HistoryMismatch,
+    /// History entry at this index has no inclusion proof.
+    MissingEntryProof { index: usize },
+    /// History already holds its maximum number of entries.
+    HistoryFull { max: usize },
+    /// History is already longer than the requested maximum length.
+    MaxHistoryTooSmall { len: usize, max: usize },
+    /// History can only be pruned up to (not including) its last entry.
+    PruneOutOfRange { index: usize, len: usize },
+    /// History entry at this index has no block number.
+    MissingBlockNumber { index: usize },
+    /// History entry at this index (and every later one) is still waiting
+    /// for its inclusion block.
+    PendingInclusion { index: usize },
+    /// History has no entry at this index.
+    EntryOutOfRange { index: usize, len: usize },
+    /// Block is not between the blocks of the entries around this index.
+    InclusionOutOfOrder { index: usize, block: u64 },
+    /// History entry at this index has no block root to be confirmed against.
+    MissingInclusionRoot { index: usize },
+    /// Inclusion proof of the history entry at this index doesn't lead to
+    /// its block root.
+    UnprovenInclusionRoot { index: usize },
+    /// History entry at this index has not been confirmed by its sender.
+    MissingConfirmation { index: usize },
+    /// Confirmation of the history entry at this index is not valid.
+    InvalidConfirmation { index: usize },
+    /// No root was given for this block.
+    MissingBlockRoot { block: u64 },
+    /// No inclusion or exclusion proof is stored for this block.
+    MissingProof { block: u64 },
+    /// Proof for this block does not lead to the block's root.
+    InvalidProof { block: u64 },
+    /// Inclusion proof could not be checked.
+    Merkle(MerkleError),
+    /// Inclusion proof does not lead to the expected block root.
+    RootMismatch,
+    /// History does not match the expected commitment.
+    CommitmentMismatch,
+    /// Challenge transaction is for a different token.
+    ChallengeWrongToken,
+    /// Token has no recorded challenge at this index.
+    ChallengeOutOfRange { index: usize, len: usize },
+    /// Encoding contains an unknown `TxnCmp` discriminant.
+    UnknownTxnCmp(u8),
+    /// Uid does not have one bit per level of the tree.
+    UidDepthMismatch { uid_bits: usize, depth: usize },
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenError::NotChildOfPrevious { .. } =>
+                write!(f, "Transaction is not a child of previous transaction."),
+            TokenError::BatchNotChildOfPrevious { index, .. } =>
+                write!(f, "Transaction {} of the batch is not a child of previous transaction.", index),
+            TokenError::InvalidTransaction =>
+                write!(f, "Transaction is not well-formed."),
+            TokenError::InvalidTransactionAt { index, cause } =>
+                write!(f, "Transaction {} is not well-formed ({}).", index, cause),
+            TokenError::NotDeposit =>
+                write!(f, "Transaction is not a deposit."),
+            TokenError::BlockNumberNotIncreasing =>
+                write!(f, "Transaction spends an earlier block than its parent."),
+            TokenError::FollowsRejectedTransaction =>
+                write!(f, "Transaction follows a rejected transaction."),
+            TokenError::WrongStatus { current } =>
+                write!(f, "Operation not allowed while token status is {:?}.", current),
+            TokenError::InvalidStatusTransition { from, to } =>
+                write!(f, "Token status cannot change from {:?} to {:?}.", from, to),
+            TokenError::UnsupportedEncodingVersion(_) =>
+                write!(f, "Unsupported token encoding version."),
+            TokenError::UnknownStatus(_) =>
+                write!(f, "Unknown token status."),
+            TokenError::UnknownFlags(_) =>
+                write!(f, "Unknown token flags."),
+            TokenError::MalformedTransaction =>
+                write!(f, "Malformed transaction in token encoding."),
+            TokenError::Codec(CodecError::TrailingBytes { .. }) =>
+                write!(f, "Trailing bytes after token encoding."),
+            TokenError::Codec(_) =>
+                write!(f, "Token encoding is truncated or malformed."),
+            TokenError::InvalidHistory =>
+                write!(f, "Decoded token history is not valid."),
+            TokenError::ProofCountMismatch { history, proofs } =>
+                write!(f, "Cannot pair {} proofs with {} transactions.", proofs, history),
+            TokenError::WrongToken { index } =>
+                write!(f, "Transaction {} is for a different token.", index),
+            TokenError::StaleCheckpoint =>
+                write!(f, "Checkpoint is no longer part of the token history."),
+            TokenError::EmptyHistory =>
+                write!(f, "Token has no history."),
+            TokenError::NotSiblings =>
+                write!(f, "Tokens are not halves of the same coin."),
+            TokenError::HistoryMismatch =>
+                write!(f, "Token histories differ."),
+            TokenError::MissingEntryProof { index } =>
+                write!(f, "History entry {} has no inclusion proof.", index),
+            TokenError::HistoryFull { max } =>
+                write!(f, "History is full ({} entries).", max),
+            TokenError::MaxHistoryTooSmall { len, max } =>
+                write!(f, "History of {} entries is longer than {}.", len, max),
+            TokenError::PruneOutOfRange { index, len } =>
+                write!(f, "Cannot prune a history of {} entries up to entry {}.", len, index),
+            TokenError::MissingBlockNumber { index } =>
+                write!(f, "History entry {} has no block number.", index),
+            TokenError::PendingInclusion { index } =>
+                write!(f, "History entry {} is not yet included in a block.", index),
+            TokenError::EntryOutOfRange { index, len } =>
+                write!(f, "No entry {} in a history of {} entries.", index, len),
+            TokenError::InclusionOutOfOrder { index, block } =>
+                write!(f, "Block {} is out of order for history entry {}.", block, index),
+            TokenError::MissingInclusionRoot { index } =>
+                write!(f, "History entry {} has no block root to confirm.", index),
+            TokenError::UnprovenInclusionRoot { index } =>
+                write!(f, "Block root of history entry {} is not proven by its proof.", index),
+            TokenError::MissingConfirmation { index } =>
+                write!(f, "History entry {} is not confirmed.", index),
+            TokenError::InvalidConfirmation { index } =>
+                write!(f, "History entry {} has an invalid confirmation.", index),
+            TokenError::MissingBlockRoot { block } =>
+                write!(f, "No root for block {}.", block),
+            TokenError::MissingProof { block } =>
+                write!(f, "No proof for block {}.", block),
+            TokenError::InvalidProof { block } =>
+                write!(f, "Proof does not match the root of block {}.", block),
+            TokenError::Merkle(e) =>
+                write!(f, "Invalid inclusion proof: {}", e),
+            TokenError::Fee(e) =>
+                write!(f, "Invalid fees: {}", e),
+            TokenError::RootMismatch =>
+                write!(f, "Inclusion proof does not match the block root."),
+            TokenError::CommitmentMismatch =>
+                write!(f, "History does not match the commitment."),
+            TokenError::ChallengeWrongToken =>
+                write!(f, "Challenge transaction is for a different token."),
+            TokenError::ChallengeOutOfRange { index, len } =>
+                write!(f, "No challenge at index {} (token has {}).", index, len),
+            TokenError::UnknownTxnCmp(cmp) =>
+                write!(f, "Unknown transaction comparison {}.", cmp),
+            TokenError::UidDepthMismatch { uid_bits, depth } =>
+                write!(f, "Uid has {} bits, but the tree has {} levels.", uid_bits, depth),
         }
     }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TokenError {}
 
-    true
+impl From<CodecError> for TokenError {
+    fn from(e: CodecError) -> TokenError {
+        TokenError::Codec(e)
+    }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+impl From<MerkleError> for TokenError {
+    fn from(e: MerkleError) -> TokenError {
+        TokenError::Merkle(e)
+    }
+}
 
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::Hasher;
-    use std::mem::transmute;
+impl From<FeeError> for TokenError {
+    fn from(e: FeeError) -> TokenError {
+        TokenError::Fee(e)
+    }
+}
 
-    #[derive(PartialEq, Eq, Hash, Clone)]
-    struct MockTransaction {
-        token_id: BitVec,
-        pub sender: u8,
-        pub receiver: u8,
-        pub block_num: u8,
+/// Why a token's history failed validation, and at which entry, see
+/// `Token::validate`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum HistoryError<TxnError> {
+    /// Transaction is for a different token.
+    WrongToken { index: usize },
+    /// Transaction failed its own well-formedness check (`validate()`), for
+    /// this reason.
+    InvalidTransaction { index: usize, cause: TxnError },
+    /// Transaction is not the child of the one before it, but this instead.
+    NotChildOfPrevious { index: usize, got: TxnCmp },
+    /// Transaction doesn't spend a later block than the one before it.
+    BlockNumberNotIncreasing { index: usize },
+    /// History of a strict token doesn't start with a deposit.
+    NotDeposit,
+}
+
+impl<TxnError> HistoryError<TxnError> {
+    /// Index of the offending transaction.
+    pub fn index(&self) -> usize {
+        match self {
+            HistoryError::WrongToken { index }
+            | HistoryError::InvalidTransaction { index, .. }
+            | HistoryError::NotChildOfPrevious { index, .. }
+            | HistoryError::BlockNumberNotIncreasing { index } => *index,
+            HistoryError::NotDeposit => 0,
+        }
     }
+}
 
-    impl MockTransaction {
-        pub fn new(
-            token_id: BitVec,
-            sender: u8,
-            receiver: u8,
-            block_num: u8,
-        ) -> Self {
-            Self {
-                token_id,
-                sender,
-                receiver,
-                block_num,
-            }
+impl<TxnError: fmt::Debug> fmt::Display for HistoryError<TxnError> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HistoryError::WrongToken { index } =>
+                write!(f, "Transaction {} is for a different token.", index),
+            HistoryError::InvalidTransaction { index, cause } =>
+                write!(f, "Transaction {} is not well-formed ({:?}).", index, cause),
+            HistoryError::NotChildOfPrevious { index, got } =>
+                write!(f, "Transaction {} is not a child of the previous one ({:?}).", index, got),
+            HistoryError::BlockNumberNotIncreasing { index } =>
+                write!(f, "Transaction {} spends an earlier block than its parent.", index),
+            HistoryError::NotDeposit =>
+                write!(f, "History does not start with a deposit."),
         }
+    }
+}
 
-        pub fn as_bytes(&self) -> [u8; 4] {
-            let token_id: Vec<u8> = self.token_id.clone().into();
-            [token_id[0], self.sender, self.receiver, self.block_num]
+#[cfg(feature = "std")]
+impl<TxnError: fmt::Debug> std::error::Error for HistoryError<TxnError> {}
+
+impl<TxnError: fmt::Debug> From<HistoryError<TxnError>> for TokenError {
+    fn from(e: HistoryError<TxnError>) -> TokenError {
+        match e {
+            HistoryError::WrongToken { index } => TokenError::WrongToken { index },
+            HistoryError::InvalidTransaction { index, cause } =>
+                TokenError::InvalidTransactionAt { index, cause: format!("{:?}", cause) },
+            HistoryError::NotChildOfPrevious { got, .. } => TokenError::NotChildOfPrevious { got },
+            HistoryError::BlockNumberNotIncreasing { .. } => TokenError::BlockNumberNotIncreasing,
+            HistoryError::NotDeposit => TokenError::NotDeposit,
         }
     }
+}
 
-    impl PlasmaCashTxn for MockTransaction {
-        type HashType = [u8; 8]; // Type returned by DefaultHasher
+/// A transaction dropped by `Token::import_lenient`, and why.
+#[derive(Debug)]
+pub struct RejectedEntry<TxnType> {
+    /// Index of the transaction in the imported history.
+    pub index: usize,
+    /// The rejected transaction itself.
+    pub txn: TxnType,
+    /// Reason the transaction could not be added.
+    pub reason: TokenError,
+}
 
-        fn token_id(&self) -> BitVec {
-            self.token_id.clone()
+/// Result of checking one history entry, see `Token::audit_history`.
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EntryReport {
+    /// Index of the entry in the history.
+    pub index: usize,
+    /// Whether the transaction is for this token.
+    pub uid_matches: bool,
+    /// Whether the transaction passed its own well-formedness check.
+    pub valid: bool,
+    /// Relationship to the previous transaction (`None` for the first one).
+    pub cmp_to_previous: Option<TxnCmp>,
+}
+
+impl EntryReport {
+    /// Entry is for this token, well-formed, and follows the previous one.
+    pub fn passed(&self) -> bool {
+        self.uid_matches
+            && self.valid
+            && self.cmp_to_previous.map_or(true, |cmp| cmp == TxnCmp::Child)
+    }
+}
+
+/// Per-entry breakdown of a token's history validation.
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ValidationReport {
+    /// One report per history entry, in history order.
+    pub entries: Vec<EntryReport>,
+    /// Index of the first entry that failed, if any.
+    pub first_failure: Option<usize>,
+    /// Reason of each challenge still pending against the token (see
+    /// `Token::record_challenge`), which doesn't affect the history's validity.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub pending_challenges: Vec<TxnCmp>,
+    /// Entries were dropped when the history was imported (see
+    /// `Token::import_lenient`), so it isn't the token's full history.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub partially_imported: bool,
+}
+
+impl ValidationReport {
+    /// Every entry in the history passed.
+    ///
+    /// # Note
+    /// The history may still be incomplete, see `partially_imported`.
+    pub fn is_valid(&self) -> bool {
+        self.first_failure.is_none()
+    }
+}
+
+/// Point in a token's history that it can be rolled back to, see
+/// `Token::checkpoint`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Checkpoint {
+    history_len: usize, // Counting pruned entries
+    status: TokenStatus,
+}
+
+/// Position of a token's exit in the Root Chain exit queue, see
+/// `Token::exit_priority`.
+///
+/// Exits are processed oldest first, by the block the parent of the exiting
+/// transaction was included in (the deposit block when exiting a deposit).
+/// Ties are broken by uid, lowest first. A *greater* priority is processed
+/// *earlier*, so a `BinaryHeap` of priorities pops the next exit to process.
+#[derive(Debug, Clone)]
+pub struct ExitPriority {
+    block_num: u64,
+    uid: BitVec,
+}
+
+impl ExitPriority {
+    /// Block the exit's position is based on.
+    pub fn block_num(&self) -> u64 {
+        self.block_num
+    }
+
+    /// Uid of the exiting token.
+    pub fn uid(&self) -> &BitVec {
+        &self.uid
+    }
+}
+
+impl Ord for ExitPriority {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Uids compare by value, ignoring leading zero bits
+        let self_uid = self.uid.iter().skip_while(|bit| !bit);
+        let other_uid = other.uid.iter().skip_while(|bit| !bit);
+        other.block_num.cmp(&self.block_num)
+            .then_with(|| other_uid.clone().count().cmp(&self_uid.clone().count()))
+            .then_with(|| other_uid.cmp(self_uid))
+    }
+}
+
+impl PartialOrd for ExitPriority {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ExitPriority {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ExitPriority {}
+
+/// Everything needed to start an exit of a token on the Root Chain, see
+/// `Token::exit_data`.
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(
+    serialize = "TxnType: Serialize, HashType: AsRef<[u8]>",
+    deserialize = "TxnType: Deserialize<'de>, HashType: Default + AsMut<[u8]>",
+)))]
+pub struct ExitData<TxnType, HashType> {
+    /// Latest transaction of the token.
+    pub exit_txn: TxnType,
+    pub exit_proof: Proof<HashType>,
+    pub exit_block: Option<u64>,
+    /// Transaction before the latest one (`None` when exiting a deposit).
+    pub parent_txn: Option<TxnType>,
+    pub parent_proof: Option<Proof<HashType>>,
+    pub parent_block: Option<u64>,
+}
+
+/// History entry whose inclusion proof checked out against its block's root,
+/// see `Token::verified_iter`.
+#[derive(Debug, PartialEq)]
+pub struct VerifiedEntry<'a, TxnType, HashType> {
+    /// Index of the entry in the history.
+    pub index: usize,
+    pub block_num: u64,
+    pub txn: &'a TxnType,
+    pub proof: &'a Proof<HashType>,
+    pub root: &'a HashType,
+}
+
+/// A token's history and proofs, as handed to the recipient of a transfer so
+/// they can verify it independently, see `Token::export_transfer_bundle`.
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(
+    serialize = "TxnType: Serialize, HashType: AsRef<[u8]>",
+    deserialize = "TxnType: Deserialize<'de>, HashType: Default + AsMut<[u8]>",
+)))]
+pub struct TransferBundle<TxnType, HashType> {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::bitvec"))]
+    pub uid: BitVec,
+    /// Status as the sender sees it, which the recipient doesn't take on.
+    pub status: TokenStatus,
+    /// History entries, with their inclusion proofs.
+    pub history: Vec<HistoryEntry<TxnType, HashType>>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::exclusion_proofs"))]
+    pub exclusion_proofs: BTreeMap<u64, Proof<HashType>>,
+    /// Uids of the coins this one was split from, see `Token::split_from()`.
+    #[cfg_attr(feature = "serde", serde(default, with = "crate::serde_utils::bitvecs"))]
+    pub split_from: Vec<BitVec>,
+}
+
+/// Kinds of challenge a transaction can make against an exit.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ChallengeKind {
+    /// Exited coin was already spent, by a child of the exit transaction.
+    SpentCoin,
+    /// Parent of the exit transaction was spent before the exit transaction.
+    DoubleSpend,
+    /// History leading up to the exit was invalidated by an earlier spend.
+    InvalidHistory,
+}
+
+/// A transaction conflicting with a token's history, kept until the
+/// challenge it allows is answered on the Root Chain, see
+/// `Token::record_challenge`.
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ChallengeRecord<TxnType> {
+    pub txn: TxnType,
+    /// How the transaction conflicts with the history.
+    pub reason: TxnCmp,
+}
+
+/// Which of two competing histories of the same coin the Root Chain would
+/// favor, see `Token::resolve_conflict`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ConflictResolution {
+    /// Neither history contradicts the other (one may just be longer).
+    Equivalent,
+    /// Tokens have different uids, or their divergent transactions can't be
+    /// ordered against each other.
+    Unrelated,
+    /// First token's history is canonical. Both histories agree before
+    /// `divergence_index` (counting pruned entries).
+    First { divergence_index: usize },
+    /// Second token's history is canonical. Both histories agree before
+    /// `divergence_index` (counting pruned entries).
+    Second { divergence_index: usize },
+}
+
+impl<TxnType, HashType> Token<TxnType, HashType>
+    where
+        TxnType: PlasmaCashTxn,
+        HashType: AsRef<[u8]>,
+{
+    /// Create new token with given uid stored on the rootchain.
+    /// (history is empty to start)
+    pub fn new(uid: BitVec) -> Token<TxnType, HashType> {
+        Token {
+            uid,
+            status: TokenStatus::RootChain,
+            history: Vec::new(),
+            exclusion_proofs: BTreeMap::new(),
+            partially_imported: false,
+            pruned: 0,
+            max_history: None,
+            strict: false,
+            depth: None,
+            split_from: Vec::new(),
+            challenges: Vec::new(),
+            observer: None,
+            validated: AtomicUsize::new(0),
+            _txn: PhantomData,
         }
+    }
 
-        fn hash_fn() -> (fn(&[u8]) -> Self::HashType) {
-            | x: &[u8] | {
-                let mut hasher = DefaultHasher::new();
-                hasher.write(x);
-                let result = hasher.finish();
-                let result: [u8; 8] = unsafe { transmute(result.to_be()) };
-                result
-            }
+    /// Same as `new()`, but the first transaction added to the history must be
+    /// a deposit (see `PlasmaCashTxn::is_deposit()`), so a history can't start
+    /// out of nowhere.
+    pub fn new_strict(uid: BitVec) -> Token<TxnType, HashType> {
+        let mut token = Token::new(uid);
+        token.strict = true;
+        token
+    }
+
+    /// Same as `new()`, for a token whose uid is a key of a tree `depth`
+    /// levels deep, so proofs can be checked for the right length as soon as
+    /// they are attached.
+    ///
+    /// The uid must have exactly `depth` bits, see `new_with_padded_uid()`
+    /// for uids that leave out leading zero bits.
+    pub fn new_with_depth(uid: BitVec, depth: usize) -> Result<Token<TxnType, HashType>, TokenError> {
+        if uid.len() != depth {
+            return Err(TokenError::UidDepthMismatch { uid_bits: uid.len(), depth });
         }
+        let mut token = Token::new(uid);
+        token.depth = Some(depth);
+        Ok(token)
+    }
 
-        fn empty_leaf_hash() -> Self::HashType {
-            // Empty transaction
-            let empty_leaf = MockTransaction::new(BitVec::from_element(0u8), 0, 0, 0);
-            Self::hash_fn()(&empty_leaf.as_bytes())
+    /// Same as `new_with_depth()`, but a uid shorter than `depth` bits is
+    /// left-padded with zero bits (a longer one is still rejected).
+    pub fn new_with_padded_uid(uid: BitVec, depth: usize) -> Result<Token<TxnType, HashType>, TokenError> {
+        if uid.len() > depth {
+            return Err(TokenError::UidDepthMismatch { uid_bits: uid.len(), depth });
         }
+        let mut padded = BitVec::with_capacity(depth);
+        padded.resize(depth - uid.len(), false);
+        padded.extend(uid.iter());
+        Token::new_with_depth(padded, depth)
+    }
 
-        fn leaf_hash(&self) -> Self::HashType {
-            Self::hash_fn()(&self.as_bytes())
+    /// Same as `new()`, but the history may never hold more than `max`
+    /// entries (see `set_max_history()`).
+    pub fn with_max_history(uid: BitVec, max: usize) -> Token<TxnType, HashType> {
+        let mut token = Token::new(uid);
+        token.max_history = Some(max);
+        token
+    }
+
+    /// Create a token with an existing `history`, validated as a whole.
+    ///
+    /// The token is assumed to be on the Child Chain if it has any history,
+    /// and on the Root Chain otherwise (the same as `new()`).
+    pub fn new_with_history(
+        uid: BitVec,
+        history: Vec<TxnType>,
+    ) -> Result<Token<TxnType, HashType>, TokenError> {
+        Token::new_with_history_and_proofs(uid, history, Vec::new())
+    }
+
+    /// Same as `new_with_history()`, with a proof for each transaction (see
+    /// `replace_history()`).
+    pub fn new_with_history_and_proofs(
+        uid: BitVec,
+        history: Vec<TxnType>,
+        proofs: Vec<Proof<HashType>>,
+    ) -> Result<Token<TxnType, HashType>, TokenError> {
+        let mut token = Token::new(uid);
+        token.replace_history(history, proofs)?;
+        if !token.history.is_empty() {
+            token.status = TokenStatus::PlasmaChain;
         }
+        Ok(token)
+    }
 
-        fn valid(&self) -> bool {
-            true // All mocks are valid
+    /// Create a token on the Child Chain from its deposit transaction, which
+    /// was included in `deposit_block`.
+    ///
+    /// The deposit is the first history entry, and its block is where
+    /// `verify_history_against_roots()` starts checking proofs.
+    pub fn from_deposit(
+        uid: BitVec,
+        deposit_txn: TxnType,
+        deposit_block: u64,
+    ) -> Result<Token<TxnType, HashType>, TokenError> {
+        if !uid_matches(&uid, &deposit_txn.token_id()) {
+            return Err(TokenError::WrongToken { index: 0 });
+        }
+        if !deposit_txn.valid() {
+            return Err(TokenError::InvalidTransaction);
+        }
+        if !deposit_txn.is_deposit() {
+            return Err(TokenError::NotDeposit);
         }
 
-        fn compare(&self, other: &Self) -> TxnCmp {
-            if self == other {
-                return TxnCmp::Same;
-            }
+        let mut entry = HistoryEntry::new(deposit_txn);
+        entry.block_num = Some(deposit_block);
 
-            if self.receiver == other.sender {
-                return TxnCmp::Parent;
-            }
+        let mut token = Token::new(uid);
+        token.history.push(entry);
+        token.status = TokenStatus::PlasmaChain;
+        Ok(token)
+    }
 
-            if self.sender == other.receiver {
-                return TxnCmp::Child;
-            }
+    /// Create a token from the old layout, where transactions and their
+    /// proofs were kept in two separate lists.
+    ///
+    /// # Note
+    /// Nothing used to keep the two lists in sync, so `proofs` must either be
+    /// empty or have exactly one proof per transaction; anything else can't
+    /// be paired up reliably and is rejected. The block roots were never
+    /// stored, so they are left unset.
+    pub fn from_legacy_parts(
+        uid: BitVec,
+        history: Vec<TxnType>,
+        proofs: Vec<Proof<HashType>>,
+    ) -> Result<Token<TxnType, HashType>, TokenError> {
+        let history = pair_legacy_proofs(history, proofs)?;
+        if validate_history(Lineage::of(&uid), history.iter().map(|entry| &entry.txn)).is_err() {
+            return Err(TokenError::InvalidHistory);
+        }
 
-            if self.sender == other.sender {
-                if self.block_num < other.block_num {
-                    return TxnCmp::EarlierSibling;
-                }
+        let mut token = Token::new(uid);
+        token.history = history;
+        Ok(token)
+    }
 
-                if self.block_num > other.block_num {
-                    return TxnCmp::LaterSibling;
-                }
+    /// Create a token from the longest valid prefix of `history`, returning
+    /// every transaction after that prefix as a rejected entry.
+    ///
+    /// # Note
+    /// This is meant for support tooling recovering damaged histories. The
+    /// resulting token is flagged (see `is_partially_imported()`) whenever
+    /// anything was rejected, and the regular validated APIs should be used
+    /// for anything security-relevant.
+    pub fn import_lenient(
+        uid: BitVec,
+        history: Vec<TxnType>,
+    ) -> (Token<TxnType, HashType>, Vec<RejectedEntry<TxnType>>) {
+        let mut token = Token::new(uid);
+        let mut rejected = Vec::new();
 
-                if self.block_num == other.block_num {
-                    return TxnCmp::DoubleSpend;
-                }
+        for (index, txn) in history.into_iter().enumerate() {
+            // Everything after the first bad entry has no valid parent
+            if !rejected.is_empty() {
+                rejected.push(RejectedEntry {
+                    index,
+                    txn,
+                    reason: TokenError::FollowsRejectedTransaction,
+                });
+                continue;
+            }
+
+            if !uid_matches(&token.uid, &txn.token_id()) {
+                rejected.push(RejectedEntry {
+                    index,
+                    txn,
+                    reason: TokenError::WrongToken { index },
+                });
+                continue;
+            }
+
+            if !txn.valid() {
+                rejected.push(RejectedEntry {
+                    index,
+                    txn,
+                    reason: TokenError::InvalidTransaction,
+                });
+                continue;
             }
 
-            TxnCmp::Unrelated
+            // Only push when the check passes so we keep ownership on failure
+            let reason = match token.latest_transaction() {
+                Some(last_txn) => match txn.compare(last_txn) {
+                    TxnCmp::Child if !blocks_increase(last_txn, &txn) =>
+                        Some(TokenError::BlockNumberNotIncreasing),
+                    TxnCmp::Child => None,
+                    got => Some(TokenError::NotChildOfPrevious { got }),
+                },
+                None => None,
+            };
+            match reason {
+                Some(reason) => rejected.push(RejectedEntry { index, txn, reason }),
+                None => token.history.push(HistoryEntry::new(txn)),
+            }
         }
+
+        token.partially_imported = !rejected.is_empty();
+        (token, rejected)
     }
 
-    fn new_token(id: u8) -> Token<MockTransaction, [u8; 8]> {
-        Token::new(BitVec::from_element(id))
+    /// Same as `new()`, with room for `history_cap` history entries before
+    /// the history needs to grow (e.g. when the length of a history about to
+    /// be synced is known).
+    pub fn with_capacity(uid: BitVec, history_cap: usize) -> Token<TxnType, HashType> {
+        let mut token = Token::new(uid);
+        token.history.reserve_exact(history_cap);
+        token
     }
 
-    #[test]
-    fn test_add_transactions() {
-        let mut t = new_token(1);
-        assert!(t.is_valid());
+    /// Number of history entries that fit before the history needs to grow.
+    pub fn capacity(&self) -> usize {
+        self.history.capacity()
+    }
 
-        // Add three transactions in a row
-        let txn1 = MockTransaction::new(t.uid.clone(), 0, 1, 0);
-        assert!(t.add_transaction(txn1.clone()).is_ok());
-        assert!(t.is_valid());
+    /// Make room for at least `additional` more history entries.
+    pub fn reserve(&mut self, additional: usize) {
+        self.history.reserve(additional);
+    }
 
-        let txn2 = MockTransaction::new(t.uid.clone(), 1, 2, 1);
-        assert_eq!(txn1.compare(&txn2), TxnCmp::Parent);
-        assert!(t.add_transaction(txn2).is_ok());
-        assert!(t.is_valid());
+    /// Release unused memory, e.g. after `prune_history()`.
+    pub fn shrink_to_fit(&mut self) {
+        self.history.shrink_to_fit();
+        for entry in self.history.iter_mut() {
+            if let Some(proof) = &mut entry.proof {
+                proof.shrink_to_fit();
+            }
+        }
+        for proof in self.exclusion_proofs.values_mut() {
+            proof.shrink_to_fit();
+        }
+    }
 
-        let txn3 = MockTransaction::new(t.uid.clone(), 2, 3, 2);
-        assert!(t.add_transaction(txn3).is_ok());
-        assert!(t.is_valid());
+    /// Estimate of the heap memory used by the token, in bytes.
+    ///
+    /// # Note
+    /// This counts the uid, the history's allocation, and
+    /// `depth * size_of::<HashType>()` for each proof, but not any memory the
+    /// transactions themselves (or the map holding the exclusion proofs)
+    /// allocate.
+    pub fn heap_usage(&self) -> usize {
+        let proof_size = |proof: &Proof<HashType>| proof.len() * mem::size_of::<HashType>();
+        self.uid.len().div_ceil(8)
+            + self.history.capacity() * mem::size_of::<HistoryEntry<TxnType, HashType>>()
+            + self.history.iter().filter_map(|entry| entry.proof.as_ref()).map(proof_size).sum::<usize>()
+            + self.exclusion_proofs.values().map(proof_size).sum::<usize>()
     }
 
-    #[test]
-    fn test_add_twice() {
-        let mut t = new_token(1);
-        let txn1 = MockTransaction::new(t.uid.clone(), 0, 1, 0);
-        assert!(t.add_transaction(txn1.clone()).is_ok());
+    /// Entries after `block`, e.g. to send a peer that already knows the
+    /// history up to that block (see `extend_from()`).
+    ///
+    /// # Note
+    /// This is everything after the last entry included in `block` or
+    /// earlier, so it also holds entries still waiting for their inclusion
+    /// block. Without any such entry, the whole history is returned.
+    pub fn history_after(&self, block: u64) -> &[HistoryEntry<TxnType, HashType>] {
+        let start = self.history.iter()
+            .rposition(|entry| entry.block_num.is_some_and(|included| included <= block))
+            .map_or(0, |index| index + 1);
+        &self.history[start..]
+    }
+}
 
-        // Try and add the same transaction twice
-        assert_eq!(txn1.compare(&txn1), TxnCmp::Same);
-        assert!(t.add_transaction(txn1).is_err());
-        assert!(t.is_valid());
+impl<TxnType, HashType, Store> Token<TxnType, HashType, Store>
+    where
+        TxnType: PlasmaCashTxn,
+        HashType: AsRef<[u8]>,
+        Store: HistoryStore<TxnType, HashType>,
+{
+    /// Create a token keeping its history in `store`, which may already hold
+    /// a (validated) history, e.g. when reopening a disk-backed store.
+    ///
+    /// The token is assumed to be on the Child Chain if it has any history,
+    /// the same as for `new_with_history()`.
+    pub fn new_with_store(
+        uid: BitVec,
+        store: Store,
+    ) -> Result<Token<TxnType, HashType, Store>, TokenError> {
+        Self::new_split_with_store(uid, Vec::new(), store)
     }
 
-    #[test]
-    fn test_earlier_sibling() {
-        let mut t = new_token(1);
-        let txn1 = MockTransaction::new(t.uid.clone(), 0, 1, 1);
-        assert!(t.add_transaction(txn1.clone()).is_ok());
+    // Same as `new_with_store()`, for a coin split from the ones in
+    // `split_from` (see `split()`)
+    fn new_split_with_store(
+        uid: BitVec,
+        split_from: Vec<BitVec>,
+        store: Store,
+    ) -> Result<Token<TxnType, HashType, Store>, TokenError> {
+        let lineage = Lineage { split_from: &split_from, uid: &uid };
+        if let Some(index) = store.iter().position(|entry| lineage.coin(&entry.txn.token_id()).is_none()) {
+            return Err(TokenError::WrongToken { index });
+        }
+        if validate_history(lineage, store.iter().map(|entry| &entry.txn)).is_err() {
+            return Err(TokenError::InvalidHistory);
+        }
 
-        // Try and add a transaction sent before the stored one
-        let txn2 = MockTransaction::new(t.uid.clone(), 0, 2, 0);
-        assert_eq!(txn2.compare(&txn1), TxnCmp::EarlierSibling);
-        assert!(t.add_transaction(txn2).is_err());
-        assert!(t.is_valid());
+        let status = if store.is_empty() { TokenStatus::RootChain } else { TokenStatus::PlasmaChain };
+        Ok(Token {
+            uid,
+            status,
+            history: store,
+            exclusion_proofs: BTreeMap::new(),
+            partially_imported: false,
+            pruned: 0,
+            max_history: None,
+            strict: false,
+            depth: None,
+            split_from,
+            challenges: Vec::new(),
+            observer: None,
+            validated: AtomicUsize::new(0),
+            _txn: PhantomData,
+        })
     }
 
-    #[test]
-    fn test_later_sibling() {
-        let mut t = new_token(1);
-        let txn1 = MockTransaction::new(t.uid.clone(), 0, 1, 0);
-        assert!(t.add_transaction(txn1.clone()).is_ok());
+    /// History of the token, oldest first.
+    ///
+    /// # Note
+    /// History can only be changed through the methods on `Token`, which keep
+    /// it valid.
+    pub fn history(&self) -> &Store {
+        &self.history
+    }
 
-        // Try and add a transaction sent after the stored one that conflicts
-        let txn2 = MockTransaction::new(t.uid.clone(), 0, 2, 1);
-        assert_eq!(txn2.compare(&txn1), TxnCmp::LaterSibling);
-        assert!(t.add_transaction(txn2).is_err());
-        assert!(t.is_valid());
+    /// Number of entries in the history.
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Transaction at index `i` of the history.
+    pub fn transaction_at(&self, i: usize) -> Option<&TxnType> {
+        self.history.get(i).map(|entry| &entry.txn)
+    }
+
+    /// Transactions in the history, oldest first.
+    pub fn transactions(&self) -> impl Iterator<Item = &TxnType> + Clone {
+        self.history.iter().map(|entry| &entry.txn)
+    }
+
+    /// Proofs of inclusion for each transaction in the history, oldest first.
+    pub fn proofs(&self) -> impl Iterator<Item = Option<&Proof<HashType>>> {
+        self.history.iter().map(|entry| entry.proof.as_ref())
+    }
+
+    /// Child Chain block the entry at `index` was included in, if known.
+    pub fn inclusion_block(&self, index: usize) -> Option<u64> {
+        self.history.get(index)?.block_num
+    }
+
+    /// Index of the entry included in `block`, if any.
+    pub fn index_of_block(&self, block: u64) -> Option<usize> {
+        self.history.iter().position(|entry| entry.block_num == Some(block))
+    }
+
+    /// Number of newest entries whose inclusion block is not known yet.
+    pub fn pending_len(&self) -> usize {
+        self.history.iter().rev().take_while(|entry| entry.block_num.is_none()).count()
+    }
+
+    /// Latest transaction in the history.
+    pub fn latest_transaction(&self) -> Option<&TxnType> {
+        self.history.last().map(|entry| &entry.txn)
+    }
+
+    /// Current owner of the token, i.e. the receiver of the latest
+    /// transaction in its history (`None` if there is no history yet, or the
+    /// receiver can't be recovered).
+    pub fn owner(&self) -> Option<TxnType::Owner> {
+        self.latest_transaction().and_then(|txn| txn.receiver())
+    }
+
+    /// Whether `owner` is the current owner of the token.
+    pub fn is_owned_by(&self, owner: &TxnType::Owner) -> bool {
+        self.owner().as_ref() == Some(owner)
+    }
+
+    /// Every owner of the token in order, i.e. the sender of the first
+    /// transaction in the history followed by the receiver of each
+    /// transaction, with consecutive repeats removed (empty if there is no
+    /// history yet).
+    ///
+    /// # Note
+    /// The first sender and any receiver are left out if they can't be
+    /// recovered (see `PlasmaCashTxn::sender()` and `receiver()`). Owners
+    /// before pruned entries are not known.
+    pub fn provenance(&self) -> Vec<TxnType::Owner> {
+        let first_owner = self.history.get(0).and_then(|entry| entry.txn.sender());
+        let mut owners: Vec<TxnType::Owner> = Vec::with_capacity(self.history.len() + 1);
+        for owner in first_owner.into_iter().chain(self.transactions().filter_map(|txn| txn.receiver())) {
+            if owners.last() != Some(&owner) {
+                owners.push(owner);
+            }
+        }
+        owners
+    }
+
+    /// Whether `owner` has owned the token at any point of its history (see
+    /// `provenance()`).
+    pub fn was_owned_by(&self, owner: &TxnType::Owner) -> bool {
+        self.history.get(0).and_then(|entry| entry.txn.sender()).as_ref() == Some(owner)
+            || self.transactions().any(|txn| txn.receiver().as_ref() == Some(owner))
+    }
+
+    /// Sum of the fees paid by the transactions at `range` of the history (see
+    /// `PlasmaCashTxn::fee()`), failing rather than wrapping on overflow.
+    pub fn total_fees(&self, range: Range<usize>) -> Result<TxnType::Amount, TokenError> {
+        let len = self.history.len();
+        if range.end > len {
+            return Err(TokenError::EntryOutOfRange { index: range.end - 1, len });
+        }
+        range.filter_map(|i| self.history.get(i)?.txn.fee())
+            .try_fold(TxnType::Amount::ZERO, |total, fee| total.checked_add(fee))
+            .ok_or(TokenError::Fee(FeeError::Overflow))
+    }
+
+    /// Whether this token was built by `import_lenient` with entries dropped.
+    pub fn is_partially_imported(&self) -> bool {
+        self.partially_imported
+    }
+
+    /// Whether the history must start with a deposit, see `new_strict()`.
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Levels of the tree the uid is a key of, if known (see
+    /// `new_with_depth()`).
+    pub fn depth(&self) -> Option<usize> {
+        self.depth
+    }
+
+    // Proofs must have one node per level of the tree, when its depth is known
+    fn check_proof_depth(&self, proof: &Proof<HashType>) -> Result<(), TokenError> {
+        match self.depth {
+            Some(depth) if proof.len() != depth =>
+                Err(MerkleError::ProofLengthMismatch { key_bits: depth, proof_len: proof.len() }.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Validate history of token is consistent
+    pub fn is_valid(&self) -> bool {
+        self.validate().is_ok()
+    }
+
+    /// Same as `is_valid()`, but reports the first entry that failed and why.
+    ///
+    /// # Note
+    /// Indices are into the current history (see `transaction_at()`). Use
+    /// `audit_history()` to check every entry instead.
+    ///
+    /// Only entries added since the history was last found valid are checked
+    /// (entries added through the checked methods, e.g. `add_transaction()`,
+    /// don't need to be checked again).
+    pub fn validate(&self) -> Result<(), HistoryError<TxnType::Error>> {
+        // `uid` is public, so may have changed since the history was checked
+        let validated = match self.transaction_at(0) {
+            Some(first) if self.lineage().coin(&first.token_id()).is_some() =>
+                self.validated.load(atomic::Ordering::Relaxed),
+            _ => 0,
+        };
+        let len = self.history.len();
+        let result = if validated == 0 {
+            if self.strict && self.pruned == 0 {
+                validate_strict_history(self.lineage(), self.transactions())
+            } else {
+                validate_history(self.lineage(), self.transactions())
+            }
+        } else {
+            // Check the rest against the last entry known to be valid
+            let mut prev_txn = self.transaction_at(validated - 1);
+            self.transactions().enumerate().skip(validated).try_for_each(|(index, txn)| {
+                check_entry(Some(self.lineage()), prev_txn, txn, index)?;
+                prev_txn = Some(txn);
+                Ok(())
+            })
+        };
+        if result.is_ok() {
+            self.validated.store(len, atomic::Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Check every entry in the history, reporting each one's result rather
+    /// than stopping at the first failure.
+    ///
+    /// # Note
+    /// Indices in the report count entries dropped by `prune_history()`, so
+    /// they stay the same across pruning.
+    pub fn audit_history(&self) -> ValidationReport {
+        let mut prev_txn = None;
+        let entries: Vec<EntryReport> = self.transactions().enumerate().map(|(index, txn)| {
+            let report = EntryReport {
+                index: self.pruned + index,
+                uid_matches: self.lineage().coin(&txn.token_id()).is_some(),
+                valid: txn.valid(),
+                cmp_to_previous: prev_txn.map(|prev_txn| {
+                    let split = self.lineage().splits(Some(prev_txn), txn).unwrap_or(false);
+                    follows(prev_txn, txn, split)
+                }),
+            };
+            prev_txn = Some(txn);
+            report
+        }).collect();
+        let first_failure = entries.iter().find(|entry| !entry.passed()).map(|entry| entry.index);
+        let pending_challenges = self.challenges.iter().map(|challenge| challenge.reason).collect();
+        ValidationReport {
+            entries,
+            first_failure,
+            pending_challenges,
+            partially_imported: self.partially_imported,
+        }
+    }
+
+    /// Number of entries dropped from the front of the history so far.
+    pub fn pruned_len(&self) -> usize {
+        self.pruned
+    }
+
+    /// Most entries the history may hold (`None` if unbounded).
+    pub fn max_history(&self) -> Option<usize> {
+        self.max_history
+    }
+
+    /// Limit the history to at most `max` entries (or lift the limit with
+    /// `None`), after which adding transactions fails with `HistoryFull`
+    /// until the history is pruned.
+    ///
+    /// Fails if the history is already longer than `max`.
+    pub fn set_max_history(&mut self, max: Option<usize>) -> Result<(), TokenError> {
+        if let Some(max) = max {
+            let len = self.history.len();
+            if len > max {
+                return Err(TokenError::MaxHistoryTooSmall { len, max });
+            }
+        }
+        self.max_history = max;
+        Ok(())
+    }
+
+    // Whether `count` more entries fit in the history
+    fn check_capacity(&self, count: usize) -> Result<(), TokenError> {
+        match self.max_history {
+            Some(max) if self.history.len().saturating_add(count) > max =>
+                Err(TokenError::HistoryFull { max }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Drop the history before `keep_from_index` (and the proofs for it),
+    /// e.g. once the Root Chain has finalized a checkpoint covering it.
+    ///
+    /// The first remaining entry becomes the base later transactions are
+    /// checked against, so at least one entry must be kept.
+    pub fn prune_history(&mut self, keep_from_index: usize) -> Result<(), TokenError> {
+        if keep_from_index == 0 {
+            return Ok(());
+        }
+        if keep_from_index >= self.history.len() {
+            return Err(TokenError::PruneOutOfRange {
+                index: keep_from_index,
+                len: self.history.len(),
+            });
+        }
+
+        self.history.remove_front(keep_from_index);
+        self.pruned += keep_from_index;
+        let validated = self.validated.get_mut();
+        *validated = validated.saturating_sub(keep_from_index);
+        if let Some(first_block) = self.history.get(0).and_then(|entry| entry.block_num) {
+            self.exclusion_proofs = self.exclusion_proofs.split_off(&first_block);
+        }
+        if let Some(observer) = &mut self.observer {
+            observer.on_history_pruned(keep_from_index);
+        }
+        Ok(())
+    }
+
+    /// Priority of an exit of the token's latest transaction (see
+    /// `ExitPriority`), or `None` without history or if the entry it depends
+    /// on has no block number.
+    pub fn exit_priority(&self) -> Option<ExitPriority> {
+        let mut latest = self.history.iter().rev();
+        let exit = latest.next()?;
+        let block_num = latest.next().unwrap_or(exit).block_num?;
+        Some(ExitPriority { block_num, uid: self.uid.clone() })
+    }
+
+    /// Decide which challenge, if any, `challenger_txn` makes against an exit
+    /// of the history entry at `exit_txn_index`.
+    ///
+    /// # Note
+    /// A child of the exit transaction is a spent coin challenge, and a sibling
+    /// that is earlier than (or in the same block as) the exit transaction is a
+    /// double spend challenge. A transaction that is unrelated to the exit
+    /// transaction but spends an entry before the exit's parent is an invalid
+    /// history challenge.
+    pub fn classify_challenge(
+        &self,
+        exit_txn_index: usize,
+        challenger_txn: &TxnType,
+    ) -> Option<ChallengeKind> {
+        let exit_txn = &self.history.get(exit_txn_index)?.txn;
+        if self.lineage().coin(&challenger_txn.token_id()).is_none() || !challenger_txn.valid() {
+            return None;
+        }
+
+        match challenger_txn.compare(exit_txn) {
+            TxnCmp::Child => Some(ChallengeKind::SpentCoin),
+            TxnCmp::DoubleSpend | TxnCmp::EarlierSibling => Some(ChallengeKind::DoubleSpend),
+            TxnCmp::Unrelated => {
+                // Entries up to the exit's parent are covered by the sibling case
+                let older = self.transactions()
+                    .take(exit_txn_index.saturating_sub(1))
+                    .any(|txn| challenger_txn.compare(txn) == TxnCmp::Child);
+                if older { Some(ChallengeKind::InvalidHistory) } else { None }
+            },
+            _ => None,
+        }
+    }
+
+    /// Decide which of two histories for the same coin is canonical, e.g. when
+    /// handed competing histories during a dispute.
+    ///
+    /// # Note
+    /// Histories are compared where both hold entries, up to the first
+    /// transactions that differ. Of those, the one whose parent was included
+    /// in the lower block (see `PlasmaCashTxn::prev_block()`) wins. Otherwise
+    /// the earlier sibling wins, and of a double spend, the one included in
+    /// the lower block wins (the other could never have been included).
+    pub fn resolve_conflict(a: &Self, b: &Self) -> ConflictResolution {
+        if a.uid != b.uid {
+            return ConflictResolution::Unrelated;
+        }
+
+        let divergence_index = a.shared_prefix_end(b);
+        let diverged = a.history.get(divergence_index - a.pruned)
+            .zip(b.history.get(divergence_index - b.pruned));
+        let (a_entry, b_entry) = match diverged {
+            Some(diverged) => diverged,
+            None => return ConflictResolution::Equivalent,
+        };
+
+        let (a_parent, b_parent) = (a_entry.txn.prev_block(), b_entry.txn.prev_block());
+        let a_wins = match a_parent.cmp(&b_parent) {
+            Ordering::Less => Some(true),
+            Ordering::Greater => Some(false),
+            Ordering::Equal => match a_entry.txn.compare(&b_entry.txn) {
+                TxnCmp::EarlierSibling => Some(true),
+                TxnCmp::LaterSibling => Some(false),
+                TxnCmp::DoubleSpend => match (a_entry.block_num, b_entry.block_num) {
+                    (Some(a_block), Some(b_block)) if a_block != b_block => Some(a_block < b_block),
+                    (Some(_), None) => Some(true),
+                    (None, Some(_)) => Some(false),
+                    _ => None,
+                },
+                _ => None,
+            },
+        };
+
+        match a_wins {
+            Some(true) => ConflictResolution::First { divergence_index },
+            Some(false) => ConflictResolution::Second { divergence_index },
+            None => ConflictResolution::Unrelated,
+        }
+    }
+
+    /// Index (counting pruned entries) of the latest transaction both
+    /// histories hold, e.g. the point two histories for the same coin forked
+    /// at, or `None` if they have none in common (or the uids differ).
+    ///
+    /// # Note
+    /// Transactions are matched with `compare()`, so equivalent transactions
+    /// match even if they aren't equal (e.g. encoded differently). Histories
+    /// are only compared where both hold entries.
+    pub fn find_common_ancestor(&self, other: &Self) -> Option<usize> {
+        if self.uid != other.uid {
+            return None;
+        }
+        let end = self.shared_prefix_end(other);
+        if end > self.pruned.max(other.pruned) { Some(end - 1) } else { None }
+    }
+
+    // Index (counting pruned entries) of the first entry after the part both
+    // histories hold where they hold the same transactions
+    fn shared_prefix_end(&self, other: &Self) -> usize {
+        let start = self.pruned.max(other.pruned);
+        let shared = self.history.iter().skip(start - self.pruned)
+            .zip(other.history.iter().skip(start - other.pruned))
+            .take_while(|(own, theirs)| own.txn.compare(&theirs.txn) == TxnCmp::Same)
+            .count();
+        start + shared
+    }
+
+    /// Compare observed `candidates` against every transaction in the history,
+    /// and return the index of each history entry a candidate conflicts with,
+    /// along with how the candidate relates to it (`DoubleSpend`,
+    /// `EarlierSibling` or `LaterSibling`).
+    ///
+    /// Candidates for a different token are skipped.
+    pub fn detect_double_spend(&self, candidates: &[TxnType]) -> Vec<(usize, TxnCmp)> {
+        let mut conflicts = Vec::new();
+        for candidate in candidates {
+            if self.lineage().coin(&candidate.token_id()).is_none() {
+                continue;
+            }
+            for (index, txn) in self.transactions().enumerate() {
+                match candidate.compare(txn) {
+                    cmp @ TxnCmp::DoubleSpend
+                        | cmp @ TxnCmp::EarlierSibling
+                        | cmp @ TxnCmp::LaterSibling => conflicts.push((index, cmp)),
+                    _ => { },
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Keep `txn`, a transaction conflicting with the history (e.g. one that
+    /// `add_transaction()` rejected), until the challenge it allows has been
+    /// answered on the Root Chain. Returns the index of its record.
+    ///
+    /// # Note
+    /// Recording the same transaction again returns the existing record.
+    pub fn record_challenge(&mut self, txn: TxnType, reason: TxnCmp) -> Result<usize, TokenError> {
+        if self.lineage().coin(&txn.token_id()).is_none() {
+            return Err(TokenError::ChallengeWrongToken);
+        }
+        if let Some(index) = self.challenges.iter()
+            .position(|challenge| challenge.txn.compare(&txn) == TxnCmp::Same)
+        {
+            return Ok(index);
+        }
+        self.challenges.push(ChallengeRecord { txn, reason });
+        Ok(self.challenges.len() - 1)
+    }
+
+    /// Challenges recorded by `record_challenge()` that are still pending,
+    /// oldest first.
+    pub fn pending_challenges(&self) -> &[ChallengeRecord<TxnType>] {
+        &self.challenges
+    }
+
+    /// Drop the challenge at `index` once it was answered on the Root Chain,
+    /// returning its record. Later challenges move down by one index.
+    ///
+    /// # Note
+    /// Unlike `clear_challenge()`, this doesn't change the token's status.
+    pub fn remove_challenge(&mut self, index: usize) -> Result<ChallengeRecord<TxnType>, TokenError> {
+        let len = self.challenges.len();
+        if index >= len {
+            return Err(TokenError::ChallengeOutOfRange { index, len });
+        }
+        Ok(self.challenges.remove(index))
+    }
+
+    /// Where the token is in its lifecycle, changed by `begin_deposit()` and
+    /// the other transitions.
+    pub fn status(&self) -> TokenStatus {
+        self.status
+    }
+
+    /// Start depositing the token into the Child Chain.
+    pub fn begin_deposit(&mut self) -> Result<(), TokenError> {
+        self.transition(TokenStatus::RootChain, TokenStatus::Deposit)
+    }
+
+    /// Deposit was accepted, so the token is now on the Child Chain.
+    pub fn finalize_deposit(&mut self) -> Result<(), TokenError> {
+        self.transition(TokenStatus::Deposit, TokenStatus::PlasmaChain)
+    }
+
+    /// Start withdrawing the token back to the Root Chain.
+    pub fn begin_withdrawal(&mut self) -> Result<(), TokenError> {
+        self.transition(TokenStatus::PlasmaChain, TokenStatus::Withdrawal)
+    }
+
+    /// Exit for a withdrawing token was started on the Root Chain.
+    pub fn start_exit(&mut self, started_at_block: u64) -> Result<(), TokenError> {
+        self.transition(TokenStatus::Withdrawal, TokenStatus::Exiting { started_at_block })
+    }
+
+    /// The token's exit has been challenged.
+    ///
+    /// Fails with `WrongStatus` unless the token is `Exiting`.
+    pub fn challenge_exit(&mut self) -> Result<(), TokenError> {
+        match self.status {
+            TokenStatus::Exiting { started_at_block } => {
+                self.set_status(TokenStatus::Challenged { started_at_block });
+                Ok(())
+            },
+            current => Err(TokenError::WrongStatus { current }),
+        }
+    }
+
+    /// The challenge against the token's exit was answered.
+    ///
+    /// Fails with `WrongStatus` unless the token is `Challenged`.
+    pub fn clear_challenge(&mut self) -> Result<(), TokenError> {
+        match self.status {
+            TokenStatus::Challenged { started_at_block } => {
+                self.set_status(TokenStatus::Exiting { started_at_block });
+                Ok(())
+            },
+            current => Err(TokenError::WrongStatus { current }),
+        }
+    }
+
+    /// Exit completed, so the token is back on the Root Chain.
+    ///
+    /// Only an unchallenged exit (`Exiting`) can be finalized.
+    pub fn finalize_withdrawal(&mut self) -> Result<(), TokenError> {
+        match self.status {
+            TokenStatus::Exiting { .. } => {
+                self.set_status(TokenStatus::RootChain);
+                Ok(())
+            },
+            from => Err(TokenError::InvalidStatusTransition { from, to: TokenStatus::RootChain }),
+        }
+    }
+
+    fn transition(&mut self, from: TokenStatus, to: TokenStatus) -> Result<(), TokenError> {
+        if self.status != from {
+            return Err(TokenError::InvalidStatusTransition { from: self.status, to });
+        }
+        self.set_status(to);
+        Ok(())
+    }
+
+    fn set_status(&mut self, status: TokenStatus) {
+        let old = core::mem::replace(&mut self.status, status);
+        if let Some(observer) = &mut self.observer {
+            if old != status {
+                observer.on_status_changed(old, status);
+            }
+        }
+    }
+
+    // Push an entry that has already been checked against the previous one,
+    // keeping the history known to be valid if it was before
+    fn push_entry(&mut self, entry: HistoryEntry<TxnType, HashType>) {
+        let len = self.history.len();
+        if *self.validated.get_mut() == len && entry.txn.valid() {
+            *self.validated.get_mut() = len + 1;
+        }
+        self.history.push(entry);
+        if let (Some(observer), Some(entry)) = (&mut self.observer, self.history.last()) {
+            observer.on_transaction_added(&entry.txn, self.history.len() - 1);
+        }
+    }
+
+    // Tell the observer the entry at `index` changed in place
+    fn entry_updated(&mut self, index: usize) {
+        if let Some(observer) = &mut self.observer {
+            observer.on_entry_updated(index);
+        }
+    }
+
+    // Keep the validity cache within the history, and tell the observer
+    fn history_truncated(&mut self) {
+        let len = self.history.len();
+        let validated = self.validated.get_mut();
+        *validated = (*validated).min(len);
+        if let Some(observer) = &mut self.observer {
+            observer.on_history_truncated(self.history.len());
+        }
+    }
+
+    /// Notify `observer` of every later change to the token, replacing any
+    /// observer set before.
+    pub fn set_observer(&mut self, observer: Box<dyn TokenObserver<TxnType> + Send + Sync>) {
+        self.observer = Some(observer);
+    }
+
+    /// Stop notifying the current observer, returning it.
+    pub fn take_observer(&mut self) -> Option<Box<dyn TokenObserver<TxnType> + Send + Sync>> {
+        self.observer.take()
+    }
+
+    /// Add a new transaction to the history. Must first pass validation
+    /// that new transaction follows old one.
+    ///
+    /// Transfers are only accepted while the token is on the Child Chain
+    /// (`PlasmaChain`), apart from the deposit transaction itself, which is
+    /// accepted as the first entry while the token is being deposited.
+    pub fn add_transaction(&mut self, txn: TxnType) -> Result<(), TokenError> {
+        self.add_entry(HistoryEntry::new(txn))
+    }
+
+    /// Same as `add_transaction()`, but regardless of the token's status, e.g.
+    /// for tooling rebuilding a history outside of the token lifecycle.
+    pub fn add_transaction_unchecked(&mut self, txn: TxnType) -> Result<(), TokenError> {
+        self.append_entry(HistoryEntry::new(txn))
+    }
+
+    /// Add a new history entry, e.g. to record the block a transaction was
+    /// included in. Checked the same way as `add_transaction()`.
+    ///
+    /// # Note
+    /// Any proof in `entry` is stored as is, see `add_transaction_with_proof()`
+    /// or `verify_history_against_roots()` for checking it.
+    pub fn add_entry(&mut self, entry: HistoryEntry<TxnType, HashType>) -> Result<(), TokenError> {
+        self.check_transferable(Some(&entry.txn))?;
+        self.append_entry(entry)
+    }
+
+    // Check and add an entry, whatever the status
+    fn append_entry(&mut self, entry: HistoryEntry<TxnType, HashType>) -> Result<(), TokenError> {
+        self.check_capacity(1)?;
+
+        if !uid_matches(&self.uid, &entry.txn.token_id()) {
+            return Err(TokenError::WrongToken { index: self.history.len() });
+        }
+        self.check_genesis(&entry.txn)?;
+
+        if let Some(last_txn) = self.latest_transaction() {
+            let split = self.lineage().splits(Some(last_txn), &entry.txn)
+                .ok_or(TokenError::WrongToken { index: self.history.len() })?;
+            match follows(last_txn, &entry.txn, split) {
+                TxnCmp::Child if !blocks_increase(last_txn, &entry.txn) =>
+                    return Err(TokenError::BlockNumberNotIncreasing),
+                TxnCmp::Child => { },
+                got => return Err(TokenError::NotChildOfPrevious { got }),
+            }
+        }
+        self.push_entry(entry);
+        Ok(())
+    }
+
+    /// Add a batch of transactions, each of which must be the child of the one
+    /// before it (the first one following the current history), and return
+    /// how many were added.
+    ///
+    /// The whole batch is checked before anything is added, so nothing is
+    /// added if any transaction is rejected.
+    pub fn add_transactions<I>(&mut self, txns: I) -> Result<usize, TokenError>
+        where
+            I: IntoIterator<Item = TxnType>,
+    {
+        let txns: Vec<TxnType> = txns.into_iter().collect();
+        self.check_batch(&txns.iter().collect::<Vec<_>>(), 0)?;
+
+        let added = txns.len();
+        for txn in txns {
+            self.push_entry(HistoryEntry::new(txn));
+        }
+        Ok(added)
+    }
+
+    /// Append `entries` received from a peer (e.g. its `history_after()` the
+    /// latest block this token knows of), returning how many were added.
+    ///
+    /// # Note
+    /// Leading entries the history already holds are skipped, and the first
+    /// new entry must follow the latest one. Block numbers of the new entries
+    /// must keep increasing. The whole batch is checked before anything is
+    /// added, the same as for `add_transactions()` (batch indices in errors
+    /// are into `entries`).
+    ///
+    /// Block roots the new entries' proofs don't prove are dropped, along with
+    /// their confirmations (proofs themselves are checked by
+    /// `verify_history_against_roots()`).
+    pub fn extend_from(&mut self, entries: &[HistoryEntry<TxnType, HashType>]) -> Result<usize, TokenError>
+        where
+            TxnType: PlasmaCashTxn<HashType = HashType> + Clone,
+            HashType: Clone,
+    {
+        // Entries up to our tip must be the same as ours
+        let len = self.history.len();
+        let overlap_start = entries.first().and_then(|first| {
+            (0..len).rev().find(|index| {
+                self.history.get(*index)
+                    .is_some_and(|entry| entry.txn.compare(&first.txn) == TxnCmp::Same)
+            })
+        });
+        let skip = match overlap_start {
+            Some(start) => {
+                let own_entries = self.history.iter().skip(start);
+                for (index, (entry, own)) in entries.iter().zip(own_entries).enumerate() {
+                    match entry.txn.compare(&own.txn) {
+                        TxnCmp::Same => { },
+                        got => return Err(TokenError::BatchNotChildOfPrevious { index, got }),
+                    }
+                }
+                entries.len().min(len - start)
+            },
+            None => 0,
+        };
+        let new_entries = &entries[skip..];
+        if new_entries.is_empty() {
+            return Ok(0);
+        }
+
+        self.check_batch(&new_entries.iter().map(|entry| &entry.txn).collect::<Vec<_>>(), skip)?;
+        let mut last_block = self.history.iter().rev().find_map(|entry| entry.block_num);
+        for (index, entry) in new_entries.iter().enumerate() {
+            if let Some(block) = entry.block_num {
+                if last_block.is_some_and(|last| block <= last) {
+                    return Err(TokenError::InclusionOutOfOrder { index: len + index, block });
+                }
+                last_block = Some(block);
+            }
+        }
+
+        for entry in new_entries {
+            let mut entry = entry.clone();
+            entry.drop_unproven_root();
+            self.push_entry(entry);
+        }
+        Ok(new_entries.len())
+    }
+
+    // Check a batch that is about to be appended, where `txns[0]` is at index
+    // `batch_offset` of the batch the caller was given
+    fn check_batch(&self, txns: &[&TxnType], batch_offset: usize) -> Result<(), TokenError> {
+        match txns {
+            [txn] => self.check_transferable(Some(txn))?,
+            _ => self.check_transferable(None)?,
+        }
+        self.check_capacity(txns.len())?;
+        if let Some(first) = txns.first() {
+            self.check_genesis(first)?;
+        }
+        let mut prev_txn = self.latest_transaction();
+        for (index, txn) in txns.iter().copied().enumerate() {
+            if !uid_matches(&self.uid, &txn.token_id()) {
+                return Err(TokenError::WrongToken { index: self.history.len() + index });
+            }
+            if let Some(prev_txn) = prev_txn {
+                let split = self.lineage().splits(Some(prev_txn), txn)
+                    .ok_or(TokenError::WrongToken { index: self.history.len() + index })?;
+                match follows(prev_txn, txn, split) {
+                    TxnCmp::Child if !blocks_increase(prev_txn, txn) =>
+                        return Err(TokenError::BlockNumberNotIncreasing),
+                    TxnCmp::Child => { },
+                    got => return Err(TokenError::BatchNotChildOfPrevious { index: batch_offset + index, got }),
+                }
+            }
+            prev_txn = Some(txn);
+        }
+        Ok(())
+    }
+
+    // Coins the history may be for
+    fn lineage(&self) -> Lineage<'_> {
+        Lineage { split_from: &self.split_from, uid: &self.uid }
+    }
+
+    // Strict tokens only start a history with a deposit
+    fn check_genesis(&self, txn: &TxnType) -> Result<(), TokenError> {
+        if self.strict && self.pruned == 0 && self.history.is_empty() && !txn.is_deposit() {
+            return Err(TokenError::NotDeposit);
+        }
+        Ok(())
+    }
+
+    // Transfers are only accepted on the Child Chain, except for `single`
+    // being the deposit that starts the history of a token being deposited
+    fn check_transferable(&self, single: Option<&TxnType>) -> Result<(), TokenError> {
+        match self.status {
+            TokenStatus::PlasmaChain => Ok(()),
+            TokenStatus::Deposit
+                if self.history.is_empty() && single.is_some_and(|txn| txn.is_deposit()) => Ok(()),
+            _ => Err(TokenError::WrongStatus { current: self.status }),
+        }
+    }
+
+    /// Replace the whole history (e.g. with a longer or corrected one from the
+    /// operator), leaving the token untouched if the new history is rejected.
+    ///
+    /// `proofs` is paired up with `history` the same way as for
+    /// `from_legacy_parts()`.
+    pub fn replace_history(
+        &mut self,
+        history: Vec<TxnType>,
+        proofs: Vec<Proof<HashType>>,
+    ) -> Result<(), TokenError> {
+        if let Some(index) = history.iter().position(|txn| self.lineage().coin(&txn.token_id()).is_none()) {
+            return Err(TokenError::WrongToken { index });
+        }
+        if validate_history(self.lineage(), history.iter()).is_err() {
+            return Err(TokenError::InvalidHistory);
+        }
+        if self.strict && self.pruned == 0 && history.first().is_some_and(|first| !first.is_deposit()) {
+            return Err(TokenError::NotDeposit);
+        }
+        if let Some(max) = self.max_history.filter(|max| history.len() > *max) {
+            return Err(TokenError::HistoryFull { max });
+        }
+        proofs.iter().try_for_each(|proof| self.check_proof_depth(proof))?;
+        let history = pair_legacy_proofs(history, proofs)?;
+        if !self.history.is_empty() {
+            self.history.truncate(0);
+            self.history_truncated();
+        }
+        for entry in history {
+            self.push_entry(entry);
+        }
+        Ok(())
+    }
+
+    /// Record the current history length and status, so speculative changes
+    /// can be undone with `rollback()`.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            history_len: self.pruned + self.history.len(),
+            status: self.status,
+        }
+    }
+
+    /// Undo everything added since `cp` was taken, and restore the status.
+    ///
+    /// Fails (leaving the token untouched) if the history has since been
+    /// reverted or pruned past the checkpoint.
+    pub fn rollback(&mut self, cp: Checkpoint) -> Result<(), TokenError> {
+        // Pruned history must keep at least one entry as its base
+        let min_len = if self.pruned > 0 { self.pruned + 1 } else { 0 };
+        if cp.history_len < min_len || cp.history_len > self.pruned + self.history.len() {
+            return Err(TokenError::StaleCheckpoint);
+        }
+        if cp.history_len - self.pruned < self.history.len() {
+            self.history.truncate(cp.history_len - self.pruned);
+            self.history_truncated();
+        }
+        self.set_status(cp.status);
+        Ok(())
+    }
+
+    /// Remove the latest transaction (and its proof) from the history, e.g.
+    /// after the block it was included in was orphaned.
+    pub fn revert_last_transaction(&mut self) -> Option<TxnType> {
+        let entry = self.history.pop()?;
+        self.history_truncated();
+        Some(entry.txn)
+    }
+
+    /// Truncate the history back to its first `len` entries, returning the
+    /// removed entries (oldest first).
+    ///
+    /// Nothing is removed if `len` is not shorter than the current history.
+    pub fn revert_to(&mut self, len: usize) -> Vec<HistoryEntry<TxnType, HashType>> {
+        let mut removed = Vec::new();
+        while self.history.len() > len {
+            removed.extend(self.history.pop());
+        }
+        if !removed.is_empty() {
+            self.history_truncated();
+        }
+        removed.reverse();
+        removed
+    }
+
+    /// Attach the Child Chain block the entry at `index` was included in,
+    /// with its proof of inclusion, once the operator has published it.
+    ///
+    /// Entries can be attached in any order, but blocks must increase along
+    /// the history, and come after the block the transaction spends. The proof is only checked by `verify_history_against_roots()`.
+    pub fn set_inclusion(
+        &mut self,
+        index: usize,
+        block: u64,
+        proof: Proof<HashType>,
+    ) -> Result<(), TokenError> {
+        self.check_proof_depth(&proof)?;
+        let len = self.history.len();
+        let entry = self.history.get(index).ok_or(TokenError::EntryOutOfRange { index, len })?;
+        let earlier = (0..index).rev().find_map(|i| self.history.get(i)?.block_num);
+        let later = (index + 1..len).find_map(|i| self.history.get(i)?.block_num);
+        // A block too large for `BlockNum` comes after any block spent
+        let spent = TxnType::BlockNum::try_from(block)
+            .is_ok_and(|block| entry.txn.prev_block() >= block);
+        if spent
+            || earlier.is_some_and(|earlier| earlier >= block)
+            || later.is_some_and(|later| later <= block)
+        {
+            return Err(TokenError::InclusionOutOfOrder { index, block });
+        }
+
+        let entry = self.history.get_mut(index).expect("index was checked above");
+        entry.block_num = Some(block);
+        entry.proof = Some(proof);
+        entry.block_root = None; // Not checked against a root yet
+        entry.confirmation = None;
+        self.entry_updated(index);
+        Ok(())
+    }
+
+    /// Encode the token into a compact, deterministic binary format.
+    ///
+    /// Layout (integers big-endian, see the `codec` module):
+    /// version `u8`, uid bit length `u32` + uid bytes, status `u8`,
+    /// flags `u8` (bit 1 set if the pruned entry count `u64` follows, bit 2
+    /// set if the maximum history length `u64` follows, bit 3 set for a
+    /// strict token, bit 4 set if challenges follow the exclusion proofs,
+    /// bit 5 set if the tree depth `u64` follows), history count `u32`, then for each entry its
+    /// length-prefixed transaction (encoded by `encode_txn`), a `u8` with
+    /// bit 0 set if a proof follows, bit 1 set if a block root follows, bit 2
+    /// set if a block number follows and bit 3 set if a confirmation follows,
+    /// the proof as hash count `u32` + raw hashes, the raw block root, the
+    /// block number `u64`, and the length-prefixed confirmation. Next is the
+    /// exclusion proof count `u32`, each as block number `u64` + proof. Last
+    /// is the challenge count `u32`, each as length-prefixed transaction +
+    /// reason `u8` (`TxnCmp` variants numbered in declaration order).
+    pub fn to_bytes<F>(&self, encode_txn: F) -> Vec<u8>
+        where
+            F: Fn(&TxnType) -> Vec<u8>,
+    {
+        let mut bytes = Vec::new();
+        self.write_bytes(&mut Writer::new(&mut bytes), encode_txn)
+            .expect("Token is too large to encode");
+        bytes
+    }
+
+    fn write_bytes<F>(&self, w: &mut Writer, encode_txn: F) -> Result<(), CodecError>
+        where
+            F: Fn(&TxnType) -> Vec<u8>,
+    {
+        w.put_u8(ENCODING_VERSION)?;
+        w.put_bitvec(&self.uid)?;
+
+        put_status(w, &self.status)?;
+        w.put_u8(
+            self.partially_imported as u8
+                | ((self.pruned > 0) as u8) << 1
+                | (self.max_history.is_some() as u8) << 2
+                | (self.strict as u8) << 3
+                | (!self.challenges.is_empty() as u8) << 4
+                | (self.depth.is_some() as u8) << 5
+                | (!self.split_from.is_empty() as u8) << 6
+        )?;
+        if self.pruned > 0 {
+            w.put_u64(self.pruned as u64)?;
+        }
+        if let Some(max) = self.max_history {
+            w.put_u64(max as u64)?;
+        }
+        if let Some(depth) = self.depth {
+            w.put_u64(depth as u64)?;
+        }
+        if !self.split_from.is_empty() {
+            w.put_len(self.split_from.len())?;
+            for uid in &self.split_from {
+                w.put_bitvec(uid)?;
+            }
+        }
+
+        w.put_len(self.history.len())?;
+        for entry in self.history.iter() {
+            w.put_len_prefixed(&encode_txn(&entry.txn))?;
+            w.put_u8(
+                entry.proof.is_some() as u8
+                    | (entry.block_root.is_some() as u8) << 1
+                    | (entry.block_num.is_some() as u8) << 2
+                    | (entry.confirmation.is_some() as u8) << 3
+            )?;
+            if let Some(proof) = &entry.proof {
+                proof.write(w)?;
+            }
+            if let Some(root) = &entry.block_root {
+                w.put_bytes(root.as_ref())?;
+            }
+            if let Some(block_num) = entry.block_num {
+                w.put_u64(block_num)?;
+            }
+            if let Some(confirmation) = &entry.confirmation {
+                w.put_len_prefixed(confirmation)?;
+            }
+        }
+
+        w.put_len(self.exclusion_proofs.len())?;
+        for (block_num, proof) in &self.exclusion_proofs {
+            w.put_u64(*block_num)?;
+            proof.write(w)?;
+        }
+
+        if !self.challenges.is_empty() {
+            w.put_len(self.challenges.len())?;
+            for challenge in &self.challenges {
+                w.put_len_prefixed(&encode_txn(&challenge.txn))?;
+                put_cmp(w, challenge.reason)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<TxnType, HashType> Token<TxnType, HashType>
+    where
+        TxnType: PlasmaCashTxn,
+        HashType: AsRef<[u8]> + AsMut<[u8]> + Default,
+{
+    /// Decode a token written by `to_bytes`, decoding each transaction with
+    /// `decode_txn` (which returns `None` for a malformed transaction).
+    ///
+    /// # Note
+    /// The decoded history is re-validated before the token is returned, and
+    /// any trailing bytes after the encoding are rejected. Tokens written in
+    /// the legacy layout are accepted, see `from_legacy_parts()`.
+    pub fn from_bytes<F>(bytes: &[u8], decode_txn: F) -> Result<Self, TokenError>
+        where
+            F: Fn(&[u8]) -> Option<TxnType>,
+    {
+        let mut r = Reader::new(bytes);
+
+        let version = r.take_u8()?;
+        if ![LEGACY_ENCODING_VERSION, NO_EXCLUSIONS_ENCODING_VERSION, ENCODING_VERSION]
+            .contains(&version)
+        {
+            return Err(TokenError::UnsupportedEncodingVersion(version));
+        }
+
+        let uid = r.take_bitvec()?;
+
+        let status = take_status(&mut r)?;
+        let flags = r.take_u8()?;
+        let known_flags = if version == ENCODING_VERSION { 0b1111111 } else { 0b1111 };
+        if flags & !known_flags != 0 {
+            return Err(TokenError::UnknownFlags(flags));
+        }
+        let partially_imported = flags & 0b001 != 0;
+        let pruned = match flags & 0b010 {
+            0 => 0,
+            _ => usize::try_from(r.take_u64()?).map_err(|_| CodecError::LengthOverflow)?,
+        };
+        let max_history = match flags & 0b100 {
+            0 => None,
+            _ => Some(usize::try_from(r.take_u64()?).map_err(|_| CodecError::LengthOverflow)?),
+        };
+        let strict = flags & 0b1000 != 0;
+        let depth = match flags & 0b100000 {
+            0 => None,
+            _ => Some(usize::try_from(r.take_u64()?).map_err(|_| CodecError::LengthOverflow)?),
+        };
+        if let Some(depth) = depth.filter(|depth| uid.len() != *depth) {
+            return Err(TokenError::UidDepthMismatch { uid_bits: uid.len(), depth });
+        }
+        let mut split_from = Vec::new();
+        if flags & 0b1000000 != 0 {
+            let split_from_len = r.take_len()?;
+            for _ in 0..split_from_len {
+                split_from.push(r.take_bitvec()?);
+            }
+        }
+        let lineage = Lineage { split_from: &split_from, uid: &uid };
+
+        // Don't trust counts for pre-allocation, every item takes at least a byte
+        let history_len = r.take_len()?;
+        let history = if version == LEGACY_ENCODING_VERSION {
+            let mut txns = Vec::with_capacity(history_len.min(r.remaining()));
+            for _ in 0..history_len {
+                txns.push(take_txn(&mut r, &decode_txn)?);
+            }
+            let proofs_len = r.take_len()?;
+            let mut proofs = Vec::with_capacity(proofs_len.min(r.remaining()));
+            for _ in 0..proofs_len {
+                proofs.push(Proof::read(&mut r)?);
+            }
+            pair_legacy_proofs(txns, proofs)?
+        } else {
+            let mut history = Vec::with_capacity(history_len.min(r.remaining()));
+            for _ in 0..history_len {
+                let mut entry = HistoryEntry::new(take_txn(&mut r, &decode_txn)?);
+                let present = r.take_u8()?;
+                if present & !0b1111 != 0 {
+                    return Err(TokenError::UnknownFlags(present));
+                }
+                if present & 0b01 != 0 {
+                    entry.proof = Some(Proof::read(&mut r)?);
+                }
+                if present & 0b10 != 0 {
+                    entry.block_root = Some(take_hash(&mut r)?);
+                }
+                if present & 0b100 != 0 {
+                    entry.block_num = Some(r.take_u64()?);
+                }
+                if present & 0b1000 != 0 {
+                    entry.confirmation = Some(r.take_len_prefixed()?.to_vec());
+                }
+                history.push(entry);
+            }
+            history
+        };
+
+        let mut exclusion_proofs = BTreeMap::new();
+        if version == ENCODING_VERSION {
+            let exclusions_len = r.take_len()?;
+            for _ in 0..exclusions_len {
+                let block_num = r.take_u64()?;
+                // Canonical encoding lists each block once, in ascending order
+                if exclusion_proofs.keys().next_back().is_some_and(|last| *last >= block_num) {
+                    return Err(TokenError::InvalidHistory);
+                }
+                exclusion_proofs.insert(block_num, Proof::read(&mut r)?);
+            }
+        }
+
+        let mut challenges = Vec::new();
+        if flags & 0b10000 != 0 {
+            let challenges_len = r.take_len()?;
+            for _ in 0..challenges_len {
+                let txn: TxnType = take_txn(&mut r, &decode_txn)?;
+                if lineage.coin(&txn.token_id()).is_none() {
+                    return Err(TokenError::ChallengeWrongToken);
+                }
+                challenges.push(ChallengeRecord { txn, reason: take_cmp(&mut r)? });
+            }
+        }
+
+        r.finish()?;
+
+        let txns = history.iter().map(|entry| &entry.txn);
+        let valid = if strict && pruned == 0 {
+            validate_strict_history(lineage, txns)
+        } else {
+            validate_history(lineage, txns)
+        };
+        if valid.is_err() {
+            return Err(TokenError::InvalidHistory);
+        }
+        if let Some(max) = max_history.filter(|max| history.len() > *max) {
+            return Err(TokenError::MaxHistoryTooSmall { len: history.len(), max });
+        }
+
+        Ok(Token {
+            uid,
+            status,
+            history,
+            exclusion_proofs,
+            partially_imported,
+            pruned,
+            max_history,
+            strict,
+            depth,
+            split_from,
+            challenges,
+            observer: None,
+            validated: AtomicUsize::new(0),
+            _txn: PhantomData,
+        })
+    }
+}
+
+impl<TxnType, HashType> Token<TxnType, HashType>
+    where
+        TxnType: EncodableTxn,
+        HashType: AsRef<[u8]> + AsMut<[u8]> + Default,
+{
+    /// Encode the token with `to_bytes`, using each transaction's canonical
+    /// encoding (see `EncodableTxn`).
+    pub fn encode(&self) -> Vec<u8> {
+        self.to_bytes(TxnType::encode)
+    }
+
+    /// Decode a token written by `encode`, see `from_bytes`.
+    pub fn decode(bytes: &[u8]) -> Result<Self, TokenError> {
+        Token::from_bytes(bytes, |bytes| TxnType::decode(bytes).ok())
+    }
+}
+
+impl<TxnType, HashType, Store> Token<TxnType, HashType, Store>
+    where
+        TxnType: PlasmaCashTxn<HashType = HashType>,
+        HashType: AsRef<[u8]> + Clone,
+        Store: HistoryStore<TxnType, HashType>,
+{
+    /// Add a new transaction together with its proof of inclusion in the
+    /// block whose SMT root is `expected_root`.
+    ///
+    /// The proof is checked first, and the token is left untouched if either
+    /// the proof or the transaction is rejected.
+    pub fn add_transaction_with_proof(
+        &mut self,
+        txn: TxnType,
+        proof: Proof<HashType>,
+        expected_root: HashType,
+    ) -> Result<(), TokenError> {
+        self.check_proof_depth(&proof)?;
+        let root = txn.get_root(&proof)?;
+        if root.as_ref() != expected_root.as_ref() {
+            return Err(TokenError::RootMismatch);
+        }
+        self.add_entry(HistoryEntry {
+            txn,
+            block_num: None,
+            proof: Some(proof),
+            block_root: Some(expected_root),
+            confirmation: None,
+        })
+    }
+
+    /// Digest committing to the uid and every transaction in the history, so
+    /// peers can cheaply compare the state of a token.
+    ///
+    /// Computed with `hasher()` as a hash chain, starting from the hash of
+    /// the uid bytes: `h_i = hash(h_(i-1) || leaf_hash(txn_i))`.
+    ///
+    /// # Note
+    /// Only covers the history that is still stored, see `prune_history()`.
+    /// Leaf hashes are computed in one batch, see `leaf_hashes()`.
+    pub fn history_commitment(&self) -> HashType {
+        let txns: Vec<&TxnType> = self.transactions().collect();
+        let hasher = TxnType::hasher();
+        let mut buf = Vec::new();
+        let seed = hasher.hash(&bitvec_to_bytes(&self.uid));
+        TxnType::leaf_hashes(&txns).iter().fold(seed, |digest, leaf_hash| {
+            buf.clear();
+            buf.extend_from_slice(digest.as_ref());
+            buf.extend_from_slice(leaf_hash.as_ref());
+            hasher.hash(&buf)
+        })
+    }
+
+    /// Check the history against a commitment from `history_commitment()`.
+    pub fn verify_commitment(&self, expected: &HashType) -> Result<(), TokenError> {
+        if self.history_commitment().as_ref() != expected.as_ref() {
+            return Err(TokenError::CommitmentMismatch);
+        }
+        Ok(())
+    }
+
+    /// Check the token's proofs against the Child Chain block `roots`, for
+    /// every block from the one the first transaction was included in up to
+    /// the latest block in `roots`.
+    ///
+    /// Blocks where the token moved must have an inclusion proof on the
+    /// matching history entry, and every other block must have an exclusion
+    /// proof (of `empty_leaf_hash()`) in `exclusion_proofs`.
+    ///
+    /// # Note
+    /// Every history entry needs its `block_num`, in increasing order. Newest
+    /// entries without one are still pending (see `set_inclusion()`), and are
+    /// reported as `PendingInclusion`. Only proofs are checked here, use
+    /// `is_valid()` to check the transactions.
+    pub fn verify_history_against_roots(
+        &self,
+        roots: &BTreeMap<u64, HashType>,
+    ) -> Result<(), TokenError> {
+        let pending_from = self.history.len() - self.pending_len();
+        let mut included = BTreeMap::new();
+        for (index, entry) in self.history.iter().enumerate() {
+            let block_num = match entry.block_num {
+                Some(block_num) => block_num,
+                None if index >= pending_from => return Err(TokenError::PendingInclusion { index }),
+                None => return Err(TokenError::MissingBlockNumber { index }),
+            };
+            if included.keys().next_back().is_some_and(|last| *last >= block_num) {
+                return Err(TokenError::InvalidHistory);
+            }
+            included.insert(block_num, entry);
+        }
+
+        let first_block = match included.keys().next() {
+            Some(first) => *first,
+            None => return Ok(()), // Nothing to prove yet
+        };
+        let last_block = included.keys().chain(roots.keys()).copied().max().unwrap_or(first_block);
+
+        for block in first_block..=last_block {
+            let root = roots.get(&block).ok_or(TokenError::MissingBlockRoot { block })?;
+            let (leaf_hash, proof) = match included.get(&block) {
+                Some(entry) => (entry.txn.leaf_hash(), entry.proof.as_ref()),
+                None => (TxnType::empty_leaf_hash(), self.exclusion_proofs.get(&block)),
+            };
+            let proof = proof.ok_or(TokenError::MissingProof { block })?;
+            match get_root(&self.uid, leaf_hash, proof, TxnType::hasher()) {
+                Ok(computed) if computed.as_ref() == root.as_ref() => { },
+                _ => return Err(TokenError::InvalidProof { block }),
+            }
+        }
+        Ok(())
+    }
+
+    /// Walk the history, checking each entry's inclusion proof against the
+    /// root of its block in `roots` as it is yielded.
+    ///
+    /// Every entry is yielded, failed or not, so the caller decides whether
+    /// to stop at the first failure.
+    pub fn verified_iter<'a>(
+        &'a self,
+        roots: &'a BTreeMap<u64, HashType>,
+    ) -> impl Iterator<Item = Result<VerifiedEntry<'a, TxnType, HashType>, TokenError>> + 'a {
+        let pending_from = self.history.len() - self.pending_len();
+        self.history.iter().enumerate().map(move |(index, entry)| {
+            let block_num = match entry.block_num {
+                Some(block_num) => block_num,
+                None if index >= pending_from => return Err(TokenError::PendingInclusion { index }),
+                None => return Err(TokenError::MissingBlockNumber { index }),
+            };
+            let root = roots.get(&block_num).ok_or(TokenError::MissingBlockRoot { block: block_num })?;
+            let proof = entry.proof.as_ref().ok_or(TokenError::MissingEntryProof { index })?;
+            match get_root(&self.uid, entry.txn.leaf_hash(), proof, TxnType::hasher()) {
+                Ok(computed) if computed.as_ref() == root.as_ref() =>
+                    Ok(VerifiedEntry { index, block_num, txn: &entry.txn, proof, root }),
+                _ => Err(TokenError::InvalidProof { block: block_num }),
+            }
+        })
+    }
+}
+
+impl<TxnType, HashType, Store> Token<TxnType, HashType, Store>
+    where
+        TxnType: PlasmaCashTxn + Clone,
+        HashType: AsRef<[u8]> + Clone,
+        Store: HistoryStore<TxnType, HashType>,
+{
+    /// Collect the latest transaction and its parent, along with their
+    /// inclusion proofs, for starting an exit on the Root Chain.
+    ///
+    /// A token with a single transaction is exited as a deposit, without a
+    /// parent.
+    pub fn exit_data(&self) -> Result<ExitData<TxnType, HashType>, TokenError> {
+        let mut latest = self.history.iter().enumerate().rev();
+        let (exit_index, exit) = latest.next().ok_or(TokenError::EmptyHistory)?;
+        let exit_proof = exit.proof.clone()
+            .ok_or(TokenError::MissingEntryProof { index: exit_index })?;
+
+        let (parent_txn, parent_proof, parent_block) = match latest.next() {
+            Some((parent_index, parent)) => {
+                let parent_proof = parent.proof.clone()
+                    .ok_or(TokenError::MissingEntryProof { index: parent_index })?;
+                (Some(parent.txn.clone()), Some(parent_proof), parent.block_num)
+            },
+            None => (None, None, None),
+        };
+
+        Ok(ExitData {
+            exit_txn: exit.txn.clone(),
+            exit_proof,
+            exit_block: exit.block_num,
+            parent_txn,
+            parent_proof,
+            parent_block,
+        })
+    }
+
+    /// Bundle up the history and proofs for the recipient of a transfer, see
+    /// `import_transfer_bundle()`.
+    pub fn export_transfer_bundle(&self) -> TransferBundle<TxnType, HashType> {
+        TransferBundle {
+            uid: self.uid.clone(),
+            status: self.status,
+            history: self.history.iter().cloned().collect(),
+            exclusion_proofs: self.exclusion_proofs.clone(),
+            split_from: self.split_from.clone(),
+        }
+    }
+}
+
+impl<TxnType, HashType, Store> Token<TxnType, HashType, Store>
+    where
+        TxnType: ConfirmableTxn<HashType = HashType>,
+        HashType: AsRef<[u8]> + Clone,
+        Store: HistoryStore<TxnType, HashType>,
+{
+    /// Store the sender's `confirmation` of the entry at `index`, after
+    /// checking it against the block root the entry's proof was checked
+    /// against (see `add_transaction_with_proof()`).
+    ///
+    /// # Note
+    /// The entry's inclusion proof is checked against its block root again
+    /// first, since the root may have been stored as is (e.g. by `add_entry()`).
+    pub fn confirm(&mut self, index: usize, confirmation: Vec<u8>) -> Result<(), TokenError> {
+        let len = self.history.len();
+        let entry = self.history.get(index).ok_or(TokenError::EntryOutOfRange { index, len })?;
+        if entry.block_root.is_none() {
+            return Err(TokenError::MissingInclusionRoot { index });
+        }
+        let root = entry.proven_root().ok_or(TokenError::UnprovenInclusionRoot { index })?;
+        if !entry.txn.verify_confirmation(&confirmation, root) {
+            return Err(TokenError::InvalidConfirmation { index });
+        }
+        let entry = self.history.get_mut(index).expect("index was checked above");
+        entry.confirmation = Some(confirmation);
+        self.entry_updated(index);
+        Ok(())
+    }
+
+    /// Same as `verify_history_against_roots()`, but every entry except the
+    /// deposit must also carry a valid confirmation for its block's root.
+    pub fn verify_confirmed_history_against_roots(
+        &self,
+        roots: &BTreeMap<u64, HashType>,
+    ) -> Result<(), TokenError> {
+        self.verify_history_against_roots(roots)?;
+        for (index, entry) in self.history.iter().enumerate() {
+            if entry.txn.is_deposit() {
+                continue;
+            }
+            let confirmation = entry.confirmation.as_ref()
+                .ok_or(TokenError::MissingConfirmation { index })?;
+            // Entries all have a block with a root once verified above
+            let root = entry.block_num.and_then(|block| roots.get(&block))
+                .ok_or(TokenError::MissingBlockNumber { index })?;
+            if !entry.txn.verify_confirmation(confirmation, root) {
+                return Err(TokenError::InvalidConfirmation { index });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<TxnType, HashType> Token<TxnType, HashType>
+    where
+        TxnType: PlasmaCashTxn<HashType = HashType>,
+        HashType: AsRef<[u8]> + Clone,
+{
+    /// Create a token from a transfer bundle sent by its previous owner.
+    ///
+    /// The whole history is validated, and every proof is checked against
+    /// `known_roots` (the recipient's own view of the Child Chain block roots,
+    /// see `verify_history_against_roots()`) before the token is created.
+    ///
+    /// # Note
+    /// Nothing else the sender claims is trusted. The token starts out with
+    /// the status of a new token with the same history (see
+    /// `new_with_store()`), so set `status` once it is confirmed on the Root
+    /// Chain. Block roots the entries' proofs don't prove are dropped, along
+    /// with their confirmations.
+    pub fn import_transfer_bundle(
+        bundle: TransferBundle<TxnType, HashType>,
+        known_roots: &BTreeMap<u64, HashType>,
+    ) -> Result<Token<TxnType, HashType>, TokenError> {
+        let mut history = bundle.history;
+        history.iter_mut().for_each(HistoryEntry::drop_unproven_root);
+        let mut token = Token::new_split_with_store(bundle.uid, bundle.split_from, history)?;
+        token.exclusion_proofs = bundle.exclusion_proofs;
+        token.verify_history_against_roots(known_roots)?;
+        Ok(token)
+    }
+}
+
+impl<TxnType, HashType, Store> Token<TxnType, HashType, Store>
+    where
+        TxnType: PlasmaCashTxn + PartialEq + Clone,
+        HashType: AsRef<[u8]> + Clone + PartialEq,
+        Store: HistoryStore<TxnType, HashType> + Clone,
+{
+    /// Split the coin into two halves, whose uids extend this one with a `0`
+    /// and a `1` bit respectively (e.g. for Plasma Cashflow denominations).
+    ///
+    /// Both halves carry this token's history as their provenance, and only
+    /// a token on the Child Chain can be split. The halves remember this
+    /// token's uid (see `split_from()`), so their history may start with
+    /// transactions for it, and the first transaction for a half must be sent
+    /// by the owner of this token.
+    pub fn split(self) -> Result<(Self, Self), TokenError> {
+        if self.status != TokenStatus::PlasmaChain {
+            return Err(TokenError::WrongStatus { current: self.status });
+        }
+
+        let mut zero = self.clone();
+        zero.split_from.push(zero.uid.clone());
+        zero.uid.push(false);
+        let mut one = self;
+        one.split_from.push(one.uid.clone());
+        one.uid.push(true);
+        Ok((zero, one))
+    }
+
+    /// Uids of the coins this one was split from, oldest first (see `split()`).
+    pub fn split_from(&self) -> &[BitVec] {
+        &self.split_from
+    }
+
+    /// Merge the two halves from `split()` back into the original coin.
+    ///
+    /// # Note
+    /// There is no merge transaction, so the halves can only be merged while
+    /// neither has moved since the split (i.e. their histories are the same).
+    pub fn merge(a: Self, b: Self) -> Result<Self, TokenError> {
+        let prefix_len = a.uid.len().checked_sub(1).ok_or(TokenError::NotSiblings)?;
+        if b.uid.len() != a.uid.len()
+            || a.uid.iter().take(prefix_len).ne(b.uid.iter().take(prefix_len))
+            || a.uid.get(prefix_len) == b.uid.get(prefix_len)
+            || a.split_from != b.split_from
+            || a.split_from.last() != Some(&a.uid.iter().take(prefix_len).collect())
+        {
+            return Err(TokenError::NotSiblings);
+        }
+        for half in &[&a, &b] {
+            if half.status != TokenStatus::PlasmaChain {
+                return Err(TokenError::WrongStatus { current: half.status });
+            }
+            if !half.is_valid() {
+                return Err(TokenError::InvalidHistory);
+            }
+        }
+        if !a.history.iter().eq(b.history.iter()) || a.pruned != b.pruned {
+            return Err(TokenError::HistoryMismatch);
+        }
+
+        let mut merged = a;
+        merged.uid.truncate(prefix_len);
+        merged.split_from.pop();
+        Ok(merged)
+    }
+}
+
+// See `TokenStatus` for the (stable) discriminants
+fn put_status(w: &mut Writer, status: &TokenStatus) -> Result<(), CodecError> {
+    match status {
+        TokenStatus::RootChain => w.put_u8(0),
+        TokenStatus::Deposit => w.put_u8(1),
+        TokenStatus::PlasmaChain => w.put_u8(2),
+        TokenStatus::Withdrawal => w.put_u8(3),
+        TokenStatus::Exiting { started_at_block } => {
+            w.put_u8(4)?;
+            w.put_u64(*started_at_block)
+        },
+        TokenStatus::Challenged { started_at_block } => {
+            w.put_u8(5)?;
+            w.put_u64(*started_at_block)
+        },
+    }
+}
+
+fn take_status(r: &mut Reader) -> Result<TokenStatus, TokenError> {
+    Ok(match r.take_u8()? {
+        0 => TokenStatus::RootChain,
+        1 => TokenStatus::Deposit,
+        2 => TokenStatus::PlasmaChain,
+        3 => TokenStatus::Withdrawal,
+        4 => TokenStatus::Exiting { started_at_block: r.take_u64()? },
+        5 => TokenStatus::Challenged { started_at_block: r.take_u64()? },
+        status => return Err(TokenError::UnknownStatus(status)),
+    })
+}
+
+// `TxnCmp` variants numbered in declaration order
+fn put_cmp(w: &mut Writer, cmp: TxnCmp) -> Result<(), CodecError> {
+    w.put_u8(match cmp {
+        TxnCmp::Same => 0,
+        TxnCmp::Parent => 1,
+        TxnCmp::Child => 2,
+        TxnCmp::EarlierSibling => 3,
+        TxnCmp::LaterSibling => 4,
+        TxnCmp::DoubleSpend => 5,
+        TxnCmp::Unrelated => 6,
+        TxnCmp::Cycle => 7,
+    })
+}
+
+fn take_cmp(r: &mut Reader) -> Result<TxnCmp, TokenError> {
+    Ok(match r.take_u8()? {
+        0 => TxnCmp::Same,
+        1 => TxnCmp::Parent,
+        2 => TxnCmp::Child,
+        3 => TxnCmp::EarlierSibling,
+        4 => TxnCmp::LaterSibling,
+        5 => TxnCmp::DoubleSpend,
+        6 => TxnCmp::Unrelated,
+        7 => TxnCmp::Cycle,
+        cmp => return Err(TokenError::UnknownTxnCmp(cmp)),
+    })
+}
+
+fn take_txn<TxnType, F>(r: &mut Reader, decode_txn: &F) -> Result<TxnType, TokenError>
+    where
+        F: Fn(&[u8]) -> Option<TxnType>,
+{
+    decode_txn(r.take_len_prefixed()?).ok_or(TokenError::MalformedTransaction)
+}
+
+fn take_hash<HashType>(r: &mut Reader) -> Result<HashType, CodecError>
+    where
+        HashType: AsMut<[u8]> + Default,
+{
+    let mut hash = HashType::default();
+    let hash_size = hash.as_mut().len();
+    hash.as_mut().copy_from_slice(r.take_bytes(hash_size)?);
+    Ok(hash)
+}
+
+// Pair up the old separate history and proof lists (see `Token::from_legacy_parts`)
+fn pair_legacy_proofs<TxnType, HashType>(
+    history: Vec<TxnType>,
+    proofs: Vec<Proof<HashType>>,
+) -> Result<Vec<HistoryEntry<TxnType, HashType>>, TokenError> {
+    if proofs.is_empty() {
+        return Ok(history.into_iter().map(HistoryEntry::new).collect());
+    }
+    if proofs.len() != history.len() {
+        return Err(TokenError::ProofCountMismatch {
+            history: history.len(),
+            proofs: proofs.len(),
+        });
+    }
+    Ok(history.into_iter().zip(proofs).map(|(txn, proof)| HistoryEntry {
+        txn,
+        block_num: None,
+        proof: Some(proof),
+        block_root: None,
+        confirmation: None,
+    }).collect())
+}
+
+// Validate ordered list of all transactions for a given token
+#[cfg(not(feature = "parallel"))]
+fn validate_history<'a, TxnType, I>(
+    lineage: Lineage<'_>,
+    history: I,
+) -> Result<(), HistoryError<TxnType::Error>>
+    where
+        TxnType: PlasmaCashTxn + 'a,
+        I: Iterator<Item = &'a TxnType>,
+{
+    check_history(Some(lineage), history).map(|_| ())
+}
+
+// Validate ordered list of all transactions for a given token, checking
+// entries on the rayon thread pool (e.g. to recover signatures in parallel)
+#[cfg(feature = "parallel")]
+fn validate_history<'a, TxnType, I>(
+    lineage: Lineage<'_>,
+    history: I,
+) -> Result<(), HistoryError<TxnType::Error>>
+    where
+        TxnType: PlasmaCashTxn + 'a,
+        I: Iterator<Item = &'a TxnType>,
+{
+    use rayon::prelude::*;
+
+    let history: Vec<&TxnType> = history.collect();
+    let check = |index: usize| {
+        let prev_txn = index.checked_sub(1).map(|prev| history[prev]);
+        check_entry(Some(lineage), prev_txn, history[index], index)
+    };
+    // Each entry is only checked against the one before it, so the first
+    // failing index is the same one the sequential pass stops at. Errors
+    // needn't be `Send`, so the failing entry is checked again for its cause.
+    let failure = (0..history.len()).into_par_iter().find_first(|index| check(*index).is_err());
+    failure.map_or(Ok(()), check)
+}
+
+// A token's uid, and the uids of the coins it was split from (oldest first),
+// i.e. the coins its history may be for
+#[derive(Clone, Copy)]
+struct Lineage<'a> {
+    split_from: &'a [BitVec],
+    uid: &'a BitVec,
+}
+
+impl<'a> Lineage<'a> {
+    // Coin that wasn't split from another
+    fn of(uid: &'a BitVec) -> Self {
+        Lineage { split_from: &[], uid }
+    }
+
+    // Which of the coins `token_id` is for, counting from the oldest
+    fn coin(&self, token_id: &BitVec) -> Option<usize> {
+        if uid_matches(self.uid, token_id) {
+            return Some(self.split_from.len());
+        }
+        self.split_from.iter().position(|uid| uid_matches(uid, token_id))
+    }
+
+    // Whether `txn` is the first transaction for a coin split from the one
+    // `prev_txn` (right before it) is for, or `None` if it can't follow it
+    fn splits<TxnType: PlasmaCashTxn>(&self, prev_txn: Option<&TxnType>, txn: &TxnType) -> Option<bool> {
+        let coin = self.coin(&txn.token_id())?;
+        match prev_txn {
+            Some(prev_txn) => {
+                let prev_coin = self.coin(&prev_txn.token_id())?;
+                if coin < prev_coin { None } else { Some(coin > prev_coin) }
+            },
+            None => Some(false),
+        }
+    }
+}
+
+// How `txn` relates to `prev_txn` right before it in a history. Across a
+// `split` the token ids differ, so there `txn` is the child if it's sent by
+// the receiver of `prev_txn`.
+fn follows<TxnType: PlasmaCashTxn>(prev_txn: &TxnType, txn: &TxnType, split: bool) -> TxnCmp {
+    match split {
+        false => txn.compare(prev_txn),
+        true if txn.sender().is_some() && txn.sender() == prev_txn.receiver() => TxnCmp::Child,
+        true => TxnCmp::Unrelated,
+    }
+}
+
+// Whether `txn` spends a later block than `prev_txn`
+fn blocks_increase<TxnType: PlasmaCashTxn>(prev_txn: &TxnType, txn: &TxnType) -> bool {
+    txn.prev_block() > prev_txn.prev_block()
+}
+
+// Same as `validate_history`, and the history must start with a deposit
+fn validate_strict_history<'a, TxnType, I>(
+    lineage: Lineage<'_>,
+    history: I,
+) -> Result<(), HistoryError<TxnType::Error>>
+    where
+        TxnType: PlasmaCashTxn + 'a,
+        I: Iterator<Item = &'a TxnType>,
+{
+    let mut history = history.peekable();
+    if history.peek().is_some_and(|first| !first.is_deposit()) {
+        return Err(HistoryError::NotDeposit);
+    }
+    validate_history(lineage, history)
+}
+
+/// Validate a history as it arrives (e.g. while syncing), only holding on to
+/// the previous transaction, and return how many transactions were checked.
+///
+/// Every transaction must be well-formed and for the same token as the one
+/// before it, and each one must be the child of the one before it, spending a
+/// later block (see `PlasmaCashTxn::prev_block()`). On failure the index of
+/// the offending transaction is returned with the reason.
+///
+/// # Note
+/// This doesn't know the token's uid, so check the first transaction against
+/// it separately. Histories of a split coin (see `Token::split()`) change
+/// token ids, so can only be checked by the token.
+pub fn validate_history_iter<TxnType, I>(history: I) -> Result<usize, (usize, TokenError)>
+    where
+        TxnType: PlasmaCashTxn,
+        I: IntoIterator<Item = TxnType>,
+{
+    check_history(None, history).map_err(|e| (e.index(), e.into()))
+}
+
+// Shared by `validate_history_iter` and `validate_history`, additionally
+// checking every transaction against the token's `lineage` if known
+fn check_history<TxnType, I>(
+    lineage: Option<Lineage<'_>>,
+    history: I,
+) -> Result<usize, HistoryError<TxnType::Error>>
+    where
+        TxnType: PlasmaCashTxn,
+        I: IntoIterator<Item = TxnType>,
+{
+    let mut prev_txn: Option<TxnType> = None;
+    let mut count = 0;
+    for (index, txn) in history.into_iter().enumerate() {
+        check_entry(lineage, prev_txn.as_ref(), &txn, index)?;
+        prev_txn = Some(txn);
+        count += 1;
+    }
+    Ok(count)
+}
+
+// Check `txn` at `index` of a history, where `prev_txn` comes right before it
+fn check_entry<TxnType: PlasmaCashTxn>(
+    lineage: Option<Lineage<'_>>,
+    prev_txn: Option<&TxnType>,
+    txn: &TxnType,
+    index: usize,
+) -> Result<(), HistoryError<TxnType::Error>> {
+    let split = match lineage {
+        Some(lineage) => lineage.splits(prev_txn, txn),
+        None => match prev_txn {
+            Some(prev_txn) if !uid_matches(&txn.token_id(), &prev_txn.token_id()) => None,
+            _ => Some(false),
+        },
+    };
+    let split = split.ok_or(HistoryError::WrongToken { index })?;
+    if let Err(cause) = txn.validate() {
+        return Err(HistoryError::InvalidTransaction { index, cause });
+    }
+    if let Some(prev_txn) = prev_txn {
+        match follows(prev_txn, txn, split) {
+            TxnCmp::Child if !blocks_increase(prev_txn, txn) =>
+                return Err(HistoryError::BlockNumberNotIncreasing { index }),
+            TxnCmp::Child => { },
+            got => return Err(HistoryError::NotChildOfPrevious { index, got }),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::Hasher;
+
+    #[derive(PartialEq, Eq, Hash, Clone, Debug)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub(crate) struct MockTransaction {
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::bitvec"))]
+        token_id: BitVec,
+        pub sender: u8,
+        pub receiver: u8,
+        pub block_num: u8,
+        #[cfg_attr(feature = "serde", serde(default))]
+        pub malformed: bool, // Fails `validate()`
+    }
+
+    impl MockTransaction {
+        pub fn new(
+            token_id: BitVec,
+            sender: u8,
+            receiver: u8,
+            block_num: u8,
+        ) -> Self {
+            Self {
+                token_id,
+                sender,
+                receiver,
+                block_num,
+                malformed: false,
+            }
+        }
+
+        pub fn as_bytes(&self) -> [u8; 4] {
+            let token_id: Vec<u8> = self.token_id.clone().into();
+            [token_id[0], self.sender, self.receiver, self.block_num]
+        }
+    }
+
+    #[derive(PartialEq, Eq, Clone, Copy, Debug)]
+    pub(crate) struct Malformed;
+
+    impl PlasmaCashTxn for MockTransaction {
+        type HashType = [u8; 8]; // Type returned by DefaultHasher
+        type Hasher = fn(&[u8]) -> [u8; 8];
+        type Owner = u8;
+        type BlockNum = u8;
+        type Amount = u64;
+        type Error = Malformed;
+
+        fn token_id(&self) -> BitVec {
+            self.token_id.clone()
+        }
+
+        // Receiver 0 stands in for one that can't be recovered
+        fn receiver(&self) -> Option<u8> {
+            if self.receiver == 0 { None } else { Some(self.receiver) }
+        }
+
+        // Deposits come from nobody, so have no sender
+        fn sender(&self) -> Option<u8> {
+            if self.sender == 0 { None } else { Some(self.sender) }
+        }
+
+        fn hasher() -> Self::Hasher {
+            | x: &[u8] | {
+                let mut hasher = DefaultHasher::new();
+                hasher.write(x);
+                let result = hasher.finish();
+                result.to_be_bytes()
+            }
+        }
+
+        fn empty_leaf_hash() -> Self::HashType {
+            // Empty transaction
+            let empty_leaf = MockTransaction::new(BitVec::from_element(0u8), 0, 0, 0);
+            Self::hasher()(&empty_leaf.as_bytes())
+        }
+
+        fn leaf_hash(&self) -> Self::HashType {
+            Self::hasher()(&self.as_bytes())
+        }
+
+        fn validate(&self) -> Result<(), Malformed> {
+            if self.malformed { Err(Malformed) } else { Ok(()) }
+        }
+
+        // Deposits come from nobody
+        fn is_deposit(&self) -> bool {
+            self.sender == 0
+        }
+
+        fn prev_block(&self) -> u8 {
+            self.block_num
+        }
+    }
+
+    impl EncodableTxn for MockTransaction {
+        fn encode(&self) -> Vec<u8> {
+            self.as_bytes().to_vec()
+        }
+
+        fn decode(bytes: &[u8]) -> Result<Self, Malformed> {
+            if bytes.len() != 4 {
+                return Err(Malformed);
+            }
+            Ok(MockTransaction::new(BitVec::from_element(bytes[0]), bytes[1], bytes[2], bytes[3]))
+        }
+    }
+
+    impl ConfirmableTxn for MockTransaction {
+        // Sender followed by the root it confirms
+        fn verify_confirmation(&self, confirmation: &[u8], included_root: &[u8; 8]) -> bool {
+            confirmation.split_first() == Some((&self.sender, &included_root[..]))
+        }
+    }
+
+    pub(crate) fn new_token(id: u8) -> Token<MockTransaction, [u8; 8]> {
+        Token::new(BitVec::from_element(id))
+    }
+
+    // Take `t` from `RootChain` to `status` through the lifecycle transitions
+    pub(crate) fn move_to_status<TxnType, Store>(t: &mut Token<TxnType, [u8; 8], Store>, status: TokenStatus)
+        where
+            TxnType: PlasmaCashTxn<HashType = [u8; 8]>,
+            Store: HistoryStore<TxnType, [u8; 8]>,
+    {
+        while t.status() != status {
+            let moved = match (t.status(), status) {
+                (TokenStatus::RootChain, _) => t.begin_deposit(),
+                (TokenStatus::Deposit, _) => t.finalize_deposit(),
+                (TokenStatus::PlasmaChain, _) => t.begin_withdrawal(),
+                (TokenStatus::Withdrawal, TokenStatus::Exiting { started_at_block })
+                    | (TokenStatus::Withdrawal, TokenStatus::Challenged { started_at_block }) =>
+                    t.start_exit(started_at_block),
+                (TokenStatus::Exiting { .. }, TokenStatus::Challenged { .. }) => t.challenge_exit(),
+                (from, to) => panic!("{:?} doesn't lead to {:?}", from, to),
+            };
+            assert!(moved.is_ok());
+        }
+    }
+
+    #[test]
+    fn test_add_transactions() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        assert!(t.is_valid());
+
+        // Add three transactions in a row
+        let txn1 = MockTransaction::new(t.uid.clone(), 0, 1, 0);
+        assert!(t.add_transaction(txn1.clone()).is_ok());
+        assert!(t.is_valid());
+
+        let txn2 = MockTransaction::new(t.uid.clone(), 1, 2, 1);
+        assert_eq!(txn1.compare(&txn2), TxnCmp::Parent);
+        assert!(t.add_transaction(txn2).is_ok());
+        assert!(t.is_valid());
+
+        let txn3 = MockTransaction::new(t.uid.clone(), 2, 3, 2);
+        assert!(t.add_transaction(txn3).is_ok());
+        assert!(t.is_valid());
+    }
+
+    #[test]
+    fn test_owner_follows_history() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        assert_eq!(t.owner(), None);
+        assert!(!t.is_owned_by(&0));
+
+        assert!(t.add_transaction(MockTransaction::new(t.uid.clone(), 0, 1, 0)).is_ok());
+        assert_eq!(t.owner(), Some(1));
+        assert!(t.is_owned_by(&1));
+
+        assert!(t.add_transaction(MockTransaction::new(t.uid.clone(), 1, 2, 1)).is_ok());
+        assert_eq!(t.owner(), Some(2));
+        assert!(!t.is_owned_by(&1));
+
+        // Receiver can't be recovered, so neither can the owner
+        assert!(t.add_transaction(MockTransaction::new(t.uid.clone(), 2, 0, 2)).is_ok());
+        assert_eq!(t.owner(), None);
+        assert!(!t.is_owned_by(&2));
+        assert_eq!(t.provenance(), vec![1, 2]);
+        assert!(!t.was_owned_by(&0));
+    }
+
+    #[test]
+    fn test_txn_get_root() {
+        let txn = MockTransaction::new(BitVec::from_element(1u8), 0, 1, 0);
+        let proof = Proof::from(vec![[3u8; 8]; 8]);
+        let root = get_root(&txn.token_id(), txn.leaf_hash(), &proof, MockTransaction::hasher());
+        assert_eq!(txn.get_root(&proof), root);
+        assert!(root.is_ok());
+
+        // Proof is borrowed, so it can be checked again
+        assert_eq!(txn.get_root(&proof), root);
+        assert_eq!(
+            txn.get_root(&Proof::from(vec![[3u8; 8]; 7])),
+            Err(MerkleError::ProofLengthMismatch { key_bits: 8, proof_len: 7 })
+        );
+    }
+
+    #[test]
+    fn test_add_with_proof_leaves_token_untouched_on_error() {
+        let mut t = new_token(1);
+        let txn = MockTransaction::new(t.uid.clone(), 0, 1, 0);
+        let proof = Proof::from(vec![[0u8; 8]; 8]);
+        let root = crate::merkle::get_root(
+            &t.uid, txn.leaf_hash(), &proof, MockTransaction::hasher()
+        ).unwrap();
+
+        // Proof checks out, but the transaction itself is refused
+        assert_eq!(
+            t.add_transaction_with_proof(txn.clone(), proof.clone(), root),
+            Err(TokenError::WrongStatus { current: TokenStatus::RootChain })
+        );
+        assert!(t.history().is_empty());
+
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        assert!(t.add_transaction_with_proof(txn, proof.clone(), root).is_ok());
+        assert_eq!(t.history()[0].proof, Some(proof));
+        assert_eq!(t.history()[0].block_root, Some(root));
+    }
+
+    // Token 1 moves in blocks 1 and 4, and stays put in blocks 2, 3 and 5
+    pub(crate) fn proven_token() -> (Token<MockTransaction, [u8; 8]>, BTreeMap<u64, [u8; 8]>) {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        let proof = Proof::from(vec![[0u8; 8]; 8]);
+        let root_of = |leaf_hash| {
+            get_root(&BitVec::from_element(1u8), leaf_hash, &proof, MockTransaction::hasher())
+                .unwrap()
+        };
+
+        let mut roots = BTreeMap::new();
+        for (block_num, txn) in &[
+            (1, MockTransaction::new(t.uid.clone(), 0, 1, 0)),
+            (4, MockTransaction::new(t.uid.clone(), 1, 2, 1)),
+        ] {
+            roots.insert(*block_num, root_of(txn.leaf_hash()));
+            let mut entry = HistoryEntry::new(txn.clone());
+            entry.block_num = Some(*block_num);
+            entry.proof = Some(proof.clone());
+            assert!(t.add_entry(entry).is_ok());
+        }
+        for block_num in &[2, 3, 5] {
+            roots.insert(*block_num, root_of(MockTransaction::empty_leaf_hash()));
+            t.exclusion_proofs.insert(*block_num, proof.clone());
+        }
+        (t, roots)
+    }
+
+    #[test]
+    fn test_verify_history_against_roots() {
+        let (t, roots) = proven_token();
+        assert_eq!(t.verify_history_against_roots(&roots), Ok(()));
+
+        // Nothing to prove without history
+        assert_eq!(new_token(1).verify_history_against_roots(&roots), Ok(()));
+
+        let mut missing_root = roots.clone();
+        missing_root.remove(&3);
+        assert_eq!(
+            t.verify_history_against_roots(&missing_root),
+            Err(TokenError::MissingBlockRoot { block: 3 })
+        );
+
+        // Token moved in block 2 according to the root
+        let mut wrong_root = roots.clone();
+        wrong_root.insert(2, roots[&1]);
+        assert_eq!(
+            t.verify_history_against_roots(&wrong_root),
+            Err(TokenError::InvalidProof { block: 2 })
+        );
+
+        // Later blocks must be covered too
+        let mut later_block = roots.clone();
+        later_block.insert(6, roots[&5]);
+        assert_eq!(
+            t.verify_history_against_roots(&later_block),
+            Err(TokenError::MissingProof { block: 6 })
+        );
+    }
+
+    #[test]
+    fn test_verified_iter() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        let proof = Proof::from(vec![[0u8; 8]; 8]);
+        let mut roots = BTreeMap::new();
+        for i in 0..3 {
+            let txn = MockTransaction::new(t.uid.clone(), i, i + 1, i);
+            roots.insert(i as u64 + 1, get_root(&t.uid, txn.leaf_hash(), &proof, MockTransaction::hasher()).unwrap());
+            let mut entry = HistoryEntry::new(txn);
+            entry.block_num = Some(i as u64 + 1);
+            entry.proof = Some(proof.clone());
+            assert!(t.add_entry(entry).is_ok());
+        }
+        t.history[1].proof = Some(Proof::from(vec![[1u8; 8]; 8]));
+
+        let results: Vec<_> = t.verified_iter(&roots).collect();
+        assert_eq!(results.len(), 3);
+        assert_eq!(
+            results[0],
+            Ok(VerifiedEntry { index: 0, block_num: 1, txn: &t.history[0].txn, proof: &proof, root: &roots[&1] })
+        );
+        assert_eq!(results[1], Err(TokenError::InvalidProof { block: 2 }));
+        let last = results[2].as_ref().unwrap();
+        assert_eq!((last.index, last.block_num, last.txn), (2, 3, &t.history[2].txn));
+        assert!(std::ptr::eq(last.proof, t.history[2].proof.as_ref().unwrap()));
+
+        // Missing pieces are reported per entry
+        t.history[2].block_num = None;
+        roots.remove(&1);
+        let results: Vec<_> = t.verified_iter(&roots).collect();
+        assert_eq!(results[0], Err(TokenError::MissingBlockRoot { block: 1 }));
+        assert_eq!(results[2], Err(TokenError::PendingInclusion { index: 2 }));
+    }
+
+    #[test]
+    fn test_verify_history_missing_proofs() {
+        let (mut no_exclusion, roots) = proven_token();
+        no_exclusion.exclusion_proofs.remove(&5);
+        assert_eq!(
+            no_exclusion.verify_history_against_roots(&roots),
+            Err(TokenError::MissingProof { block: 5 })
+        );
+
+        let (mut no_inclusion, _) = proven_token();
+        no_inclusion.history[1].proof = None;
+        assert_eq!(
+            no_inclusion.verify_history_against_roots(&roots),
+            Err(TokenError::MissingProof { block: 4 })
+        );
+
+        let (mut no_block_num, _) = proven_token();
+        no_block_num.history[0].block_num = None;
+        assert_eq!(
+            no_block_num.verify_history_against_roots(&roots),
+            Err(TokenError::MissingBlockNumber { index: 0 })
+        );
+
+        // Latest entry is pending until its inclusion is attached
+        let (mut pending, _) = proven_token();
+        let proof = pending.history[1].proof.clone().unwrap();
+        pending.history[1].block_num = None;
+        assert_eq!(pending.pending_len(), 1);
+        assert_eq!(
+            pending.verify_history_against_roots(&roots),
+            Err(TokenError::PendingInclusion { index: 1 })
+        );
+        assert!(pending.set_inclusion(1, 4, proof).is_ok());
+        assert_eq!(pending.pending_len(), 0);
+        assert_eq!(pending.verify_history_against_roots(&roots), Ok(()));
+    }
+
+    #[test]
+    fn test_confirmations() {
+        let (mut t, roots) = proven_token();
+        let confirmation = |sender: u8, root: &[u8; 8]| [&[sender][..], root].concat();
+        assert_eq!(t.verify_confirmed_history_against_roots(&roots), Err(TokenError::MissingConfirmation { index: 1 }));
+
+        // Confirmations are checked against the root the proof was checked against
+        assert_eq!(
+            t.confirm(1, confirmation(1, &roots[&4])),
+            Err(TokenError::MissingInclusionRoot { index: 1 })
+        );
+        t.history[1].block_root = Some(roots[&1]);
+        assert_eq!(
+            t.confirm(1, confirmation(1, &roots[&1])),
+            Err(TokenError::UnprovenInclusionRoot { index: 1 })
+        );
+        t.history[1].block_root = Some(roots[&4]);
+        assert_eq!(t.confirm(1, confirmation(2, &roots[&4])), Err(TokenError::InvalidConfirmation { index: 1 }));
+        assert_eq!(t.confirm(1, confirmation(1, &roots[&1])), Err(TokenError::InvalidConfirmation { index: 1 }));
+        assert_eq!(t.confirm(2, confirmation(1, &roots[&4])), Err(TokenError::EntryOutOfRange { index: 2, len: 2 }));
+        assert!(t.history[1].confirmation.is_none());
+
+        // The deposit doesn't need one
+        assert!(t.confirm(1, confirmation(1, &roots[&4])).is_ok());
+        assert_eq!(t.verify_confirmed_history_against_roots(&roots), Ok(()));
+
+        // Stored confirmations are checked again against the given roots
+        t.history[1].confirmation = Some(confirmation(1, &roots[&1]));
+        assert_eq!(
+            t.verify_confirmed_history_against_roots(&roots),
+            Err(TokenError::InvalidConfirmation { index: 1 })
+        );
+
+        // Attaching a new inclusion drops the old confirmation
+        let proof = t.history[1].proof.clone().unwrap();
+        assert!(t.set_inclusion(1, 4, proof).is_ok());
+        assert_eq!(t.history()[1].confirmation, None);
+    }
+
+    #[test]
+    fn test_set_inclusion_out_of_order() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        for i in 0..3 {
+            assert!(t.add_transaction(MockTransaction::new(t.uid.clone(), i, i + 1, i)).is_ok());
+        }
+        assert_eq!(t.pending_len(), 3);
+        let proof = |i: u8| Proof::from(vec![[i; 8]; 8]);
+
+        // Must come after the block it spends, which any block too large for
+        // the mock's `u8` does
+        assert_eq!(
+            t.set_inclusion(2, 2, proof(2)),
+            Err(TokenError::InclusionOutOfOrder { index: 2, block: 2 })
+        );
+        assert!(t.set_inclusion(2, 300, proof(2)).is_ok());
+
+        assert!(t.set_inclusion(2, 9, proof(2)).is_ok());
+        assert_eq!(t.pending_len(), 0);
+        assert_eq!(
+            t.set_inclusion(0, 9, proof(0)),
+            Err(TokenError::InclusionOutOfOrder { index: 0, block: 9 })
+        );
+        assert!(t.set_inclusion(0, 3, proof(0)).is_ok());
+        assert_eq!(
+            t.verify_history_against_roots(&BTreeMap::new()),
+            Err(TokenError::MissingBlockNumber { index: 1 })
+        );
+
+        // Must fit between the blocks of its neighbours
+        for block in &[2, 3, 9, 10] {
+            assert_eq!(
+                t.set_inclusion(1, *block, proof(1)),
+                Err(TokenError::InclusionOutOfOrder { index: 1, block: *block })
+            );
+        }
+        assert!(t.set_inclusion(1, 5, proof(1)).is_ok());
+        assert_eq!(
+            t.set_inclusion(3, 11, proof(3)),
+            Err(TokenError::EntryOutOfRange { index: 3, len: 3 })
+        );
+
+        assert_eq!(t.inclusion_block(1), Some(5));
+        assert_eq!(t.inclusion_block(3), None);
+        assert_eq!(t.index_of_block(9), Some(2));
+        assert_eq!(t.index_of_block(4), None);
+        assert!(t.proofs().eq(vec![Some(&proof(0)), Some(&proof(1)), Some(&proof(2))]));
+        assert!(t.is_valid());
+    }
+
+    #[test]
+    fn test_detect_double_spend() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        for (sender, receiver, block_num) in &[(0, 1, 0), (1, 2, 1), (2, 3, 2)] {
+            let txn = MockTransaction::new(t.uid.clone(), *sender, *receiver, *block_num);
+            assert!(t.add_transaction(txn).is_ok());
+        }
+
+        let candidates = [
+            // Same owner spends to someone else in the same block
+            MockTransaction::new(t.uid.clone(), 1, 5, 1),
+            // Owner spent the token earlier than our history says
+            MockTransaction::new(t.uid.clone(), 2, 4, 0),
+            // Owner spent the token again later
+            MockTransaction::new(t.uid.clone(), 1, 6, 3),
+            // Conflicting, but for another token
+            MockTransaction::new(BitVec::from_element(2u8), 1, 5, 1),
+            // Our own history is not a conflict
+            MockTransaction::new(t.uid.clone(), 1, 2, 1),
+        ];
+        assert_eq!(
+            t.detect_double_spend(&candidates),
+            vec![
+                (1, TxnCmp::DoubleSpend),
+                (2, TxnCmp::EarlierSibling),
+                (1, TxnCmp::LaterSibling),
+            ]
+        );
+        assert!(t.detect_double_spend(&candidates[3..]).is_empty());
+    }
+
+    #[test]
+    fn test_classify_challenge() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        for i in 0..4 {
+            assert!(t.add_transaction(MockTransaction::new(t.uid.clone(), i, i + 1, i)).is_ok());
+        }
+        // Exit of the last transaction (3 -> 4 in block 3)
+        let exit = 3;
+        let challenge = |sender, receiver, block_num| {
+            t.classify_challenge(exit, &MockTransaction::new(t.uid.clone(), sender, receiver, block_num))
+        };
+
+        assert_eq!(challenge(4, 8, 4), Some(ChallengeKind::SpentCoin));
+        assert_eq!(challenge(3, 9, 2), Some(ChallengeKind::DoubleSpend));
+        assert_eq!(challenge(3, 9, 3), Some(ChallengeKind::DoubleSpend));
+        assert_eq!(challenge(1, 7, 1), Some(ChallengeKind::InvalidHistory));
+
+        // Spending the exit's parent after the exit is not a challenge
+        assert_eq!(challenge(3, 9, 5), None);
+        assert_eq!(challenge(9, 10, 1), None);
+        assert_eq!(t.classify_challenge(4, &MockTransaction::new(t.uid.clone(), 4, 8, 4)), None);
+    }
+
+    #[test]
+    fn test_revert_and_readd() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        for i in 0..3 {
+            assert!(t.add_transaction(MockTransaction::new(t.uid.clone(), i, i + 1, i)).is_ok());
+        }
+
+        assert_eq!(t.revert_last_transaction(), Some(MockTransaction::new(t.uid.clone(), 2, 3, 2)));
+        let removed = t.revert_to(1);
+        assert_eq!(removed, vec![HistoryEntry::new(MockTransaction::new(t.uid.clone(), 1, 2, 1))]);
+        assert_eq!(t.history_len(), 1);
+        assert!(t.is_valid());
+
+        // Nothing to remove past the current length
+        assert!(t.revert_to(1).is_empty());
+        assert!(t.revert_to(5).is_empty());
+        assert_eq!(t.history_len(), 1);
+
+        // A different child of the remaining history can now be added
+        assert!(t.add_transaction(MockTransaction::new(t.uid.clone(), 1, 7, 1)).is_ok());
+        assert!(t.is_valid());
+        assert_eq!(t.owner(), Some(7));
+
+        assert_eq!(t.revert_to(0).len(), 2);
+        assert_eq!(t.revert_last_transaction(), None);
+        assert!(t.is_valid());
+    }
+
+    #[test]
+    fn test_replace_history() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        assert!(t.add_transaction(MockTransaction::new(t.uid.clone(), 0, 1, 0)).is_ok());
+        let original = t.history().to_vec();
+
+        let mut history: Vec<MockTransaction> = (0..4)
+            .map(|i| MockTransaction::new(t.uid.clone(), i, i + 1, i))
+            .collect();
+        let proofs = vec![Proof::from(vec![[1u8; 8]]); 4];
+
+        // Broken link half-way through
+        let mut broken = history.clone();
+        broken[2] = MockTransaction::new(t.uid.clone(), 7, 8, 2);
+        assert_eq!(t.replace_history(broken, proofs.clone()), Err(TokenError::InvalidHistory));
+        assert_eq!(t.history(), original.as_slice());
+
+        let mut other_token = history.clone();
+        other_token[3].token_id = BitVec::from_element(2u8);
+        assert_eq!(t.replace_history(other_token, proofs.clone()), Err(TokenError::WrongToken { index: 3 }));
+        assert_eq!(t.history(), original.as_slice());
+
+        assert_eq!(
+            t.replace_history(history.clone(), proofs[..3].to_vec()),
+            Err(TokenError::ProofCountMismatch { history: 4, proofs: 3 })
+        );
+        assert_eq!(t.history(), original.as_slice());
+
+        assert!(t.replace_history(history.clone(), proofs).is_ok());
+        assert_eq!(t.history_len(), 4);
+        assert!(t.proofs().all(|proof| proof == Some(&Proof::from(vec![[1u8; 8]]))));
+        assert!(t.is_valid());
+
+        // Shorter histories are fine too
+        history.truncate(2);
+        assert!(t.replace_history(history, vec![]).is_ok());
+        assert_eq!(t.history_len(), 2);
+    }
+
+    #[test]
+    fn test_new_with_history() {
+        let uid = BitVec::from_element(1u8);
+        let history: Vec<MockTransaction> = (0..3)
+            .map(|i| MockTransaction::new(uid.clone(), i, i + 1, i))
+            .collect();
+
+        let t = Token::<MockTransaction, [u8; 8]>::new_with_history(uid.clone(), history.clone()).unwrap();
+        assert_eq!(t.transactions().cloned().collect::<Vec<_>>(), history);
+        assert_eq!(t.status(), TokenStatus::PlasmaChain);
+        assert!(t.is_valid());
+
+        let proofs = vec![Proof::from(vec![[1u8; 8]]); 3];
+        let t = Token::<MockTransaction, [u8; 8]>::new_with_history_and_proofs(
+            uid.clone(), history.clone(), proofs
+        ).unwrap();
+        assert!(t.proofs().all(|proof| proof.is_some()));
+
+        let empty = Token::<MockTransaction, [u8; 8]>::new_with_history(uid.clone(), vec![]).unwrap();
+        let new = new_token(1);
+        assert_eq!(empty.uid, new.uid);
+        assert_eq!(empty.status(), new.status());
+        assert!(empty.history().is_empty());
+
+        let reordered = vec![history[1].clone(), history[0].clone(), history[2].clone()];
+        assert_eq!(
+            Token::<MockTransaction, [u8; 8]>::new_with_history(uid.clone(), reordered).err(),
+            Some(TokenError::InvalidHistory)
+        );
+        assert_eq!(
+            Token::<MockTransaction, [u8; 8]>::new_with_history(BitVec::from_element(2u8), history).err(),
+            Some(TokenError::WrongToken { index: 0 })
+        );
+    }
+
+    #[test]
+    fn test_add_transactions_batch() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        assert_eq!(t.add_transactions(vec![]), Ok(0));
+
+        let batch: Vec<MockTransaction> = (0..3)
+            .map(|i| MockTransaction::new(t.uid.clone(), i, i + 1, i))
+            .collect();
+        assert_eq!(t.add_transactions(batch), Ok(3));
+        assert!(t.is_valid());
+
+        // Third transaction doesn't follow the second
+        let batch = vec![
+            MockTransaction::new(t.uid.clone(), 3, 4, 3),
+            MockTransaction::new(t.uid.clone(), 4, 5, 4),
+            MockTransaction::new(t.uid.clone(), 9, 10, 5),
+            MockTransaction::new(t.uid.clone(), 10, 11, 6),
+        ];
+        assert_eq!(
+            t.add_transactions(batch),
+            Err(TokenError::BatchNotChildOfPrevious { index: 2, got: TxnCmp::Unrelated })
+        );
+        assert_eq!(t.history_len(), 3);
+
+        // First transaction must follow the current history
+        assert_eq!(
+            t.add_transactions(vec![MockTransaction::new(t.uid.clone(), 2, 3, 2)]),
+            Err(TokenError::BatchNotChildOfPrevious { index: 0, got: TxnCmp::Same })
+        );
+        assert_eq!(t.history_len(), 3);
+    }
+
+    #[test]
+    fn test_audit_history() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        assert!(t.audit_history().is_valid());
+
+        for i in 0..3 {
+            assert!(t.add_transaction(MockTransaction::new(t.uid.clone(), i, i + 1, i)).is_ok());
+        }
+        let report = t.audit_history();
+        assert!(report.is_valid());
+        assert_eq!(report.entries.len(), 3);
+        assert_eq!(report.entries[0].cmp_to_previous, None);
+        assert_eq!(report.entries[2].cmp_to_previous, Some(TxnCmp::Child));
+
+        // Breaks in the middle are reported, and later entries still checked
+        t.history.insert(1, HistoryEntry::new(MockTransaction::new(t.uid.clone(), 7, 8, 1)));
+        *t.validated.get_mut() = 0; // Direct edits bypass the validity cache
+        let report = t.audit_history();
+        assert!(!report.is_valid());
+        assert_eq!(t.validate(), Err(HistoryError::NotChildOfPrevious { index: 1, got: TxnCmp::Unrelated }));
+        assert_eq!(report.first_failure, Some(1));
+        let cmps: Vec<_> = report.entries.iter().map(|entry| entry.cmp_to_previous).collect();
+        assert_eq!(cmps, vec![None, Some(TxnCmp::Unrelated), Some(TxnCmp::Unrelated), Some(TxnCmp::Child)]);
+        assert!(report.entries.iter().all(|entry| entry.valid));
+
+        #[cfg(feature = "serde")]
+        assert!(serde_json::to_string(&report).unwrap().contains("\"first_failure\":1"));
+    }
+
+    #[test]
+    fn test_prune_history() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        for i in 0..4 {
+            let mut entry = HistoryEntry::new(MockTransaction::new(t.uid.clone(), i, i + 1, i));
+            entry.block_num = Some(i as u64 + 10);
+            assert!(t.add_entry(entry).is_ok());
+        }
+        t.exclusion_proofs.insert(11, Proof::from(vec![[1u8; 8]]));
+        t.exclusion_proofs.insert(13, Proof::from(vec![[2u8; 8]]));
+
+        assert_eq!(t.prune_history(4), Err(TokenError::PruneOutOfRange { index: 4, len: 4 }));
+        assert_eq!(t.prune_history(0), Ok(()));
+        assert_eq!(t.history_len(), 4);
+
+        assert_eq!(t.prune_history(2), Ok(()));
+        assert_eq!(t.pruned_len(), 2);
+        assert_eq!(t.history()[0].txn, MockTransaction::new(t.uid.clone(), 2, 3, 2));
+        assert_eq!(t.exclusion_proofs.keys().collect::<Vec<_>>(), vec![&13]);
+        assert!(t.is_valid());
+
+        // History keeps growing from the pruned base
+        assert!(t.add_transaction(MockTransaction::new(t.uid.clone(), 4, 5, 4)).is_ok());
+        assert!(t.add_transaction(MockTransaction::new(t.uid.clone(), 5, 6, 5)).is_ok());
+        assert!(t.is_valid());
+
+        // Report indices still count the pruned entries
+        assert_eq!(t.prune_history(1), Ok(()));
+        let report = t.audit_history();
+        assert_eq!(report.entries[0].index, 3);
+        t.history.push(HistoryEntry::new(MockTransaction::new(t.uid.clone(), 9, 9, 9)));
+        assert_eq!(t.audit_history().first_failure, Some(6));
+
+        // Pruned count survives encoding
+        t.history.pop();
+        let bytes = t.to_bytes(|txn| txn.as_bytes().to_vec());
+        let decoded = Token::<MockTransaction, [u8; 8]>::from_bytes(&bytes, decode_mock).unwrap();
+        assert_eq!(decoded.pruned_len(), 3);
+    }
+
+    #[test]
+    fn test_validate_history_iter() {
+        let uid = BitVec::from_element(1u8);
+        // Block numbers must increase, so stay within the mock's `u8` blocks
+        let generate = |bad_index: Option<usize>| {
+            let uid = uid.clone();
+            (0..250usize).map(move |i| {
+                let i = if Some(i) == bad_index { i + 1 } else { i };
+                MockTransaction::new(uid.clone(), i as u8, (i + 1) as u8, i as u8)
+            })
+        };
+        assert_eq!(validate_history_iter(generate(None)), Ok(250));
+        assert_eq!(validate_history_iter(Vec::<MockTransaction>::new()), Ok(0));
+
+        match validate_history_iter(generate(Some(125))) {
+            Err((125, TokenError::NotChildOfPrevious { .. })) => { },
+            result => panic!("Unexpected result {:?}", result),
+        }
+
+        // Works on borrowed transactions too
+        let t = Token::<MockTransaction, [u8; 8]>::new_with_history(
+            uid.clone(),
+            generate(None).take(3).collect(),
+        ).unwrap();
+        assert_eq!(validate_history_iter(t.transactions()), Ok(3));
+
+        let other_token = MockTransaction::new(BitVec::from_element(2u8), 3, 4, 3);
+        assert_eq!(
+            validate_history_iter(t.transactions().chain(Some(&other_token))),
+            Err((3, TokenError::WrongToken { index: 3 }))
+        );
+
+        // The transaction's own error is kept
+        let mut malformed = generate(None).nth(3).unwrap();
+        malformed.malformed = true;
+        assert_eq!(
+            validate_history_iter(t.transactions().chain(Some(&malformed))),
+            Err((3, TokenError::InvalidTransactionAt { index: 3, cause: "Malformed".into() }))
+        );
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parallel_validation_matches_sequential() {
+        let uid = BitVec::from_element(1u8);
+        let history: Vec<MockTransaction> = (0..250usize)
+            .map(|i| MockTransaction::new(uid.clone(), i as u8, (i + 1) as u8, i as u8))
+            .collect();
+        let sequential = |history: &[MockTransaction]| {
+            check_history(Some(Lineage::of(&uid)), history.iter()).map(|_| ())
+        };
+        assert_eq!(validate_history(Lineage::of(&uid), history.iter()), Ok(()));
+        assert_eq!(sequential(&history), Ok(()));
+
+        // Several failures, only the earliest of which is reported
+        let mut broken = history.clone();
+        broken[75] = MockTransaction::new(uid.clone(), 74, 2, 74); // Double spends 74
+        broken[150] = MockTransaction::new(BitVec::from_element(2u8), 150, 151, 150);
+        broken[225] = MockTransaction::new(uid.clone(), 7, 8, 7);
+        for len in &[0, 1, 76, 151, 250] {
+            let result = validate_history(Lineage::of(&uid), broken[..*len].iter());
+            assert_eq!(result, sequential(&broken[..*len]));
+        }
+        assert_eq!(
+            validate_history(Lineage::of(&uid), broken.iter()),
+            Err(HistoryError::NotChildOfPrevious { index: 75, got: TxnCmp::DoubleSpend })
+        );
+        assert_eq!(
+            validate_history(Lineage::of(&uid), broken[76..].iter()),
+            Err(HistoryError::WrongToken { index: 74 })
+        );
+    }
+
+    #[test]
+    fn test_split_and_merge() {
+        let mut t = new_token(1);
+        assert_eq!(
+            t.clone().split().err(),
+            Some(TokenError::WrongStatus { current: TokenStatus::RootChain })
+        );
+
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        assert!(t.add_transaction(MockTransaction::new(t.uid.clone(), 0, 1, 0)).is_ok());
+        let (zero, one) = t.clone().split().unwrap();
+        assert_eq!(zero.uid.len(), 9);
+        assert_eq!(zero.uid.get(8), Some(false));
+        assert_eq!(one.uid.get(8), Some(true));
+        assert!(zero.is_valid());
+        assert!(one.is_valid());
+        assert!(one.is_owned_by(&1));
+        assert_eq!(one.split_from(), &[t.uid.clone()][..]);
+        let bytes = zero.to_bytes(|txn| txn.as_bytes().to_vec());
+        assert_eq!(Token::from_bytes(&bytes, decode_mock), Ok(zero.clone()));
+
+        assert_eq!(Token::merge(one.clone(), zero.clone()), Ok(t.clone()));
+        assert_eq!(Token::merge(zero.clone(), one.clone()), Ok(t.clone()));
+
+        // Unrelated coins, and a coin with itself
+        let mut other = new_token(2);
+        move_to_status(&mut other, TokenStatus::PlasmaChain);
+        let (other_zero, _) = other.split().unwrap();
+        assert_eq!(Token::merge(other_zero, one.clone()).err(), Some(TokenError::NotSiblings));
+        assert_eq!(Token::merge(one.clone(), one.clone()).err(), Some(TokenError::NotSiblings));
+        assert_eq!(Token::merge(t.clone(), one.clone()).err(), Some(TokenError::NotSiblings));
+
+        // Halves move independently, and can't be merged after that
+        let mut moved = one.clone();
+        assert!(moved.add_transaction(MockTransaction::new(one.uid.clone(), 1, 2, 1)).is_ok());
+        assert!(moved.is_valid());
+        assert!(moved.audit_history().is_valid());
+        assert_eq!(moved.export_transfer_bundle().split_from, moved.split_from());
+        assert_eq!(Token::merge(zero.clone(), moved).err(), Some(TokenError::HistoryMismatch));
+
+        // Transactions for the other half are not part of the history, and
+        // only the owner of the coin that was split can move a half
+        assert_eq!(
+            zero.clone().add_transaction(MockTransaction::new(one.uid.clone(), 1, 2, 1)),
+            Err(TokenError::WrongToken { index: 1 })
+        );
+        assert_eq!(
+            one.clone().add_transaction(MockTransaction::new(one.uid.clone(), 2, 3, 1)),
+            Err(TokenError::NotChildOfPrevious { got: TxnCmp::Unrelated })
+        );
+
+        // Without the split, the coin's history isn't the half's
+        let mut unsplit: Token<MockTransaction, [u8; 8]> = Token::new(one.uid.clone());
+        move_to_status(&mut unsplit, TokenStatus::PlasmaChain);
+        assert_eq!(
+            unsplit.add_transaction(MockTransaction::new(t.uid.clone(), 0, 1, 0)),
+            Err(TokenError::WrongToken { index: 0 })
+        );
+    }
+
+    #[test]
+    fn test_foreign_token_id_rejected() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        for token_id in &[BitVec::from_element(0u8), BitVec::new(), BitVec::from_element(2u8)] {
+            let txn = MockTransaction::new(token_id.clone(), 0, 1, 0);
+            assert_eq!(t.add_transaction(txn.clone()), Err(TokenError::WrongToken { index: 0 }));
+            assert_eq!(txn.compare(&MockTransaction::new(t.uid.clone(), 0, 1, 0)), TxnCmp::Unrelated);
+        }
+        assert_eq!(t.history().len(), 0);
+        assert!(t.is_valid());
+
+        // Leading zero bits don't make a different coin
+        let mut long_id = BitVec::from_element(0u8);
+        long_id.extend(t.uid.iter());
+        assert!(t.add_transaction(MockTransaction::new(long_id, 0, 1, 0)).is_ok());
+    }
+
+    #[test]
+    fn test_transfer_bundle() {
+        let (mut t, roots) = proven_token();
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+
+        t.history[1].block_root = Some(roots[&4]);
+        t.history[1].confirmation = Some(vec![1]);
+        let bundle = t.export_transfer_bundle();
+        assert_eq!(bundle.history.len(), 2);
+        assert_eq!(Token::import_transfer_bundle(bundle.clone(), &roots), Ok(t.clone()));
+
+        // Status and unproven roots from the sender aren't taken on
+        let mut forged = bundle.clone();
+        forged.status = TokenStatus::Deposit;
+        forged.history[0].block_root = Some([7u8; 8]);
+        forged.history[0].confirmation = Some(vec![1]);
+        let imported = Token::import_transfer_bundle(forged, &roots).unwrap();
+        assert_eq!(imported.status(), TokenStatus::PlasmaChain);
+        assert_eq!(imported.history()[0].block_root, None);
+        assert_eq!(imported.history()[0].confirmation, None);
+        assert_eq!(imported.history()[1], t.history()[1]);
+
+        #[cfg(feature = "serde")]
+        {
+            let encoded = serde_json::to_string(&bundle).unwrap();
+            let decoded: TransferBundle<MockTransaction, [u8; 8]> = serde_json::from_str(&encoded).unwrap();
+            assert_eq!(decoded, bundle);
+        }
+
+        let mut corrupted = bundle.clone();
+        corrupted.history[1].proof = Some(Proof::from(vec![[1u8; 8]; 8]));
+        assert_eq!(
+            Token::import_transfer_bundle(corrupted, &roots).err(),
+            Some(TokenError::InvalidProof { block: 4 })
+        );
+
+        let mut corrupted = bundle;
+        corrupted.exclusion_proofs.remove(&3);
+        assert_eq!(
+            Token::import_transfer_bundle(corrupted, &roots).err(),
+            Some(TokenError::MissingProof { block: 3 })
+        );
+    }
+
+    #[test]
+    fn test_exit_priority() {
+        use std::collections::BinaryHeap;
+
+        // Exiting the transfer out of block `parent_block`
+        let exiting = |id: u8, parent_block: u64| {
+            let mut t = new_token(id);
+            move_to_status(&mut t, TokenStatus::PlasmaChain);
+            for (i, block_num) in [parent_block, parent_block + 5].iter().enumerate() {
+                let i = i as u8;
+                let mut entry = HistoryEntry::new(MockTransaction::new(t.uid.clone(), i, i + 1, i));
+                entry.block_num = Some(*block_num);
+                assert!(t.add_entry(entry).is_ok());
+            }
+            t.exit_priority().unwrap()
+        };
+        let older = exiting(2, 3);
+        let newer = exiting(1, 7);
+        assert_eq!(older.block_num(), 3);
+        assert!(older > newer);
+
+        for order in &[[&older, &newer], [&newer, &older]] {
+            let mut queue: BinaryHeap<ExitPriority> = order.iter().map(|p| (*p).clone()).collect();
+            assert_eq!(queue.pop(), Some(older.clone()));
+            assert_eq!(queue.pop(), Some(newer.clone()));
+        }
+
+        // Same block, lowest uid first (regardless of leading zero bits)
+        let mut wide = exiting(1, 3);
+        wide.uid.insert(0, false);
+        assert!(wide > older);
+        assert_eq!(wide, exiting(1, 3));
+
+        // Deposit exits use the deposit block
+        let deposit = Token::<MockTransaction, [u8; 8]>::from_deposit(
+            BitVec::from_element(1u8),
+            MockTransaction::new(BitVec::from_element(1u8), 0, 1, 0),
+            4,
+        ).unwrap();
+        assert_eq!(deposit.exit_priority().map(|p| p.block_num()), Some(4));
+
+        assert_eq!(new_token(1).exit_priority(), None);
+        let mut no_block = new_token(1);
+        move_to_status(&mut no_block, TokenStatus::PlasmaChain);
+        assert!(no_block.add_transaction(MockTransaction::new(no_block.uid.clone(), 0, 1, 0)).is_ok());
+        assert_eq!(no_block.exit_priority(), None);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let uid = BitVec::from_element(1u8);
+        let mut t: Token<MockTransaction, [u8; 8]> = Token::with_capacity(uid.clone(), 16);
+        assert!(t.capacity() >= 16);
+        t.reserve(32);
+        assert!(t.capacity() >= 32);
+
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        for i in 0..10 {
+            let mut entry = HistoryEntry::new(MockTransaction::new(uid.clone(), i, i + 1, i));
+            entry.proof = Some(Proof::from(vec![[i; 8]; 8]));
+            assert!(t.add_entry(entry).is_ok());
+        }
+        let full = t.heap_usage();
+        assert!(full >= 10 * 8 * 8);
+
+        // Pruning alone keeps the allocation around
+        assert!(t.prune_history(8).is_ok());
+        let pruned = t.heap_usage();
+        assert!(pruned < full);
+        t.shrink_to_fit();
+        assert!(t.capacity() >= t.history_len());
+        assert!(t.heap_usage() < pruned);
+        assert!(t.is_valid());
+    }
+
+    #[test]
+    fn test_strict_genesis() {
+        let uid = BitVec::from_element(1u8);
+        let deposit = MockTransaction::new(uid.clone(), 0, 1, 0);
+        let transfer = MockTransaction::new(uid.clone(), 1, 2, 1);
+
+        // Lenient tokens take any first transaction
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        assert!(!t.is_strict());
+        assert!(t.add_transaction(transfer.clone()).is_ok());
+
+        let mut t: Token<MockTransaction, [u8; 8]> = Token::new_strict(uid.clone());
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        assert!(t.is_strict());
+        assert_eq!(t.add_transaction(transfer.clone()), Err(TokenError::NotDeposit));
+        assert_eq!(t.add_transactions(vec![transfer.clone()]), Err(TokenError::NotDeposit));
+        assert_eq!(t.replace_history(vec![transfer.clone()], Vec::new()), Err(TokenError::NotDeposit));
+        assert_eq!(t.history_len(), 0);
+
+        assert!(t.add_transaction(deposit.clone()).is_ok());
+        assert!(t.add_transaction(transfer.clone()).is_ok());
+        assert!(t.is_valid());
+
+        // Strictness survives encoding, and is checked when decoding
+        let bytes = t.to_bytes(|txn| txn.as_bytes().to_vec());
+        let decoded = Token::<MockTransaction, [u8; 8]>::from_bytes(&bytes, decode_mock).unwrap();
+        assert!(decoded.is_strict());
+        assert_eq!(decoded, t);
+
+        t.history.remove(0);
+        *t.validated.get_mut() = 0; // Direct edits bypass the validity cache
+        assert_eq!(t.validate(), Err(HistoryError::NotDeposit));
+        let bytes = t.to_bytes(|txn| txn.as_bytes().to_vec());
+        assert_eq!(
+            Token::<MockTransaction, [u8; 8]>::from_bytes(&bytes, decode_mock).err(),
+            Some(TokenError::InvalidHistory)
+        );
+    }
+
+    #[test]
+    fn test_history_commitment() {
+        let build = |receivers: &[u8]| {
+            let mut t = new_token(1);
+            move_to_status(&mut t, TokenStatus::PlasmaChain);
+            let mut sender = 0;
+            for (i, receiver) in receivers.iter().enumerate() {
+                let txn = MockTransaction::new(t.uid.clone(), sender, *receiver, i as u8);
+                assert!(t.add_transaction(txn).is_ok());
+                sender = *receiver;
+            }
+            t
+        };
+        let t = build(&[1, 2, 3]);
+        let commitment = t.history_commitment();
+        assert_eq!(build(&[1, 2, 3]).history_commitment(), commitment);
+        assert_eq!(t.verify_commitment(&commitment), Ok(()));
+
+        // Any difference changes the commitment
+        assert_ne!(build(&[1, 2, 4]).history_commitment(), commitment);
+        assert_ne!(build(&[1, 2]).history_commitment(), commitment);
+        assert_ne!(build(&[]).history_commitment(), new_token(2).history_commitment());
+        assert_eq!(
+            build(&[1, 5, 3]).verify_commitment(&commitment),
+            Err(TokenError::CommitmentMismatch)
+        );
+
+        // Stable across encoding
+        let bytes = t.to_bytes(|txn| txn.as_bytes().to_vec());
+        let decoded = Token::<MockTransaction, [u8; 8]>::from_bytes(&bytes, decode_mock).unwrap();
+        assert_eq!(decoded.history_commitment(), commitment);
+
+        #[cfg(feature = "serde")]
+        {
+            let decoded: Token<MockTransaction, [u8; 8]> =
+                serde_json::from_str(&serde_json::to_string(&t).unwrap()).unwrap();
+            assert_eq!(decoded.history_commitment(), commitment);
+        }
+    }
+
+    #[test]
+    fn test_max_history() {
+        let uid = BitVec::from_element(1u8);
+        let mut t: Token<MockTransaction, [u8; 8]> = Token::with_max_history(uid.clone(), 3);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        assert_eq!(t.max_history(), Some(3));
+        for i in 0..3 {
+            assert!(t.add_transaction(MockTransaction::new(uid.clone(), i, i + 1, i)).is_ok());
+        }
+        assert_eq!(
+            t.add_transaction(MockTransaction::new(uid.clone(), 3, 4, 3)),
+            Err(TokenError::HistoryFull { max: 3 })
+        );
+        assert_eq!(
+            t.add_transactions(vec![MockTransaction::new(uid.clone(), 3, 4, 3)]),
+            Err(TokenError::HistoryFull { max: 3 })
+        );
+        assert_eq!(t.history_len(), 3);
+
+        // Cap survives encoding
+        let bytes = t.to_bytes(|txn| txn.as_bytes().to_vec());
+        let decoded = Token::<MockTransaction, [u8; 8]>::from_bytes(&bytes, decode_mock).unwrap();
+        assert_eq!(decoded.max_history(), Some(3));
+        assert_eq!(decoded, t);
+
+        #[cfg(feature = "serde")]
+        {
+            let decoded: Token<MockTransaction, [u8; 8]> =
+                serde_json::from_str(&serde_json::to_string(&t).unwrap()).unwrap();
+            assert_eq!(decoded.max_history(), Some(3));
+        }
+
+        assert_eq!(t.set_max_history(Some(2)), Err(TokenError::MaxHistoryTooSmall { len: 3, max: 2 }));
+        assert_eq!(t.max_history(), Some(3));
+
+        // Pruning makes room again
+        assert!(t.prune_history(2).is_ok());
+        assert!(t.add_transaction(MockTransaction::new(uid.clone(), 3, 4, 3)).is_ok());
+        assert!(t.add_transaction(MockTransaction::new(uid.clone(), 4, 5, 4)).is_ok());
+        assert!(t.is_valid());
+
+        assert!(t.set_max_history(None).is_ok());
+        assert!(t.add_transaction(MockTransaction::new(uid, 5, 6, 5)).is_ok());
+        assert_eq!(t.history_len(), 4);
+    }
+
+    #[test]
+    fn test_tree_depth() {
+        let uid = BitVec::from_element(1u8);
+        let t: Token<MockTransaction, [u8; 8]> = Token::new_with_depth(uid.clone(), 8).unwrap();
+        assert_eq!(t.depth(), Some(8));
+        assert_eq!(new_token(1).depth(), None);
+        assert_eq!(
+            Token::<MockTransaction, [u8; 8]>::new_with_depth(uid.clone(), 16).unwrap_err(),
+            TokenError::UidDepthMismatch { uid_bits: 8, depth: 16 }
+        );
+
+        // Short uids can be padded, but long ones are never truncated
+        let mut t: Token<MockTransaction, [u8; 8]> = Token::new_with_padded_uid(uid.clone(), 16).unwrap();
+        let mut padded = BitVec::from_element(0u8);
+        padded.extend(uid.iter());
+        assert_eq!(t.uid, padded);
+        assert_eq!(t.depth(), Some(16));
+        assert_eq!(
+            Token::<MockTransaction, [u8; 8]>::new_with_padded_uid(padded.clone(), 8).unwrap_err(),
+            TokenError::UidDepthMismatch { uid_bits: 16, depth: 8 }
+        );
+
+        // Transactions may leave out the padding
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        assert!(t.add_transaction(MockTransaction::new(uid.clone(), 0, 1, 0)).is_ok());
+        assert!(t.is_valid());
+
+        // Proofs for a different depth are rejected up front
+        assert_eq!(
+            t.set_inclusion(0, 1, Proof::from(vec![[0u8; 8]; 8])),
+            Err(TokenError::Merkle(MerkleError::ProofLengthMismatch { key_bits: 16, proof_len: 8 }))
+        );
+        assert_eq!(t.proofs().next(), Some(None));
+        assert!(t.set_inclusion(0, 1, Proof::from(vec![[0u8; 8]; 16])).is_ok());
+        assert_eq!(
+            t.add_transaction_with_proof(
+                MockTransaction::new(uid, 1, 2, 1),
+                Proof::from(vec![[0u8; 8]; 8]),
+                [0u8; 8],
+            ),
+            Err(TokenError::Merkle(MerkleError::ProofLengthMismatch { key_bits: 16, proof_len: 8 }))
+        );
+        assert_eq!(t.history_len(), 1);
+
+        // Depth survives encoding, and must match the uid
+        let bytes = t.to_bytes(|txn| txn.as_bytes().to_vec());
+        let decoded = Token::<MockTransaction, [u8; 8]>::from_bytes(&bytes, decode_mock).unwrap();
+        assert_eq!(decoded.depth(), Some(16));
+        assert_eq!(decoded, t);
+        let mut t = new_token(1);
+        t.depth = Some(16);
+        let bytes = t.to_bytes(|txn| txn.as_bytes().to_vec());
+        assert_eq!(
+            Token::<MockTransaction, [u8; 8]>::from_bytes(&bytes, decode_mock),
+            Err(TokenError::UidDepthMismatch { uid_bits: 8, depth: 16 })
+        );
+    }
+
+    #[test]
+    fn test_clone_eq_and_debug() {
+        let build = || {
+            let mut t = new_token(0xab);
+            move_to_status(&mut t, TokenStatus::PlasmaChain);
+            let txns = (0..2).map(|i| MockTransaction::new(BitVec::from_element(0xab), i, i + 1, i));
+            assert_eq!(t.add_transactions(txns), Ok(2));
+            t
+        };
+        let t1 = build();
+        let t2 = build();
+        assert_eq!(t1, t2);
+        assert_eq!(t1.clone(), t2);
+
+        let mut t3 = t1.clone();
+        assert!(t3.begin_withdrawal().is_ok());
+        assert_ne!(t1, t3);
+
+        assert_eq!(
+            format!("{:?}", t1),
+            "Token { uid: 0xab, status: PlasmaChain, history_len: 2 }"
+        );
+    }
+
+    #[test]
+    fn test_wrong_uid_rejected() {
+        let other_uid = BitVec::from_element(2u8);
+
+        // First transaction has nothing to be compared against
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        assert_eq!(
+            t.add_transaction(MockTransaction::new(other_uid.clone(), 0, 1, 0)),
+            Err(TokenError::WrongToken { index: 0 })
+        );
+        assert_eq!(t.history_len(), 0);
+
+        // Later transaction would otherwise be a valid child
+        assert!(t.add_transaction(MockTransaction::new(t.uid.clone(), 0, 1, 0)).is_ok());
+        assert_eq!(
+            t.add_transaction(MockTransaction::new(other_uid.clone(), 1, 2, 1)),
+            Err(TokenError::WrongToken { index: 1 })
+        );
+
+        // Same value with more leading zero bits is the same token
+        let mut wide_uid = BitVec::from_element(0u8);
+        wide_uid.extend(t.uid.iter());
+        assert!(t.add_transaction(MockTransaction::new(wide_uid, 1, 2, 1)).is_ok());
+
+        // History smuggled in some other way is caught too
+        t.history.push(HistoryEntry::new(MockTransaction::new(other_uid, 2, 3, 2)));
+        assert_eq!(t.validate(), Err(HistoryError::WrongToken { index: 2 }));
+        assert_eq!(t.audit_history().first_failure, Some(2));
+        let bytes = t.to_bytes(|txn| txn.as_bytes().to_vec());
+        assert_eq!(
+            Token::<MockTransaction, [u8; 8]>::from_bytes(&bytes, decode_mock).err(),
+            Some(TokenError::InvalidHistory)
+        );
+    }
+
+    #[test]
+    fn test_checkpoint_rollback() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        assert!(t.add_transaction(MockTransaction::new(t.uid.clone(), 0, 1, 0)).is_ok());
+
+        let outer = t.checkpoint();
+        assert!(t.add_transaction(MockTransaction::new(t.uid.clone(), 1, 2, 1)).is_ok());
+        let inner = t.checkpoint();
+        assert!(t.add_transaction(MockTransaction::new(t.uid.clone(), 2, 3, 2)).is_ok());
+        assert!(t.begin_withdrawal().is_ok());
+
+        assert_eq!(t.rollback(inner), Ok(()));
+        assert_eq!(t.history_len(), 2);
+        assert_eq!(t.status(), TokenStatus::PlasmaChain);
+        assert_eq!(t.rollback(outer), Ok(()));
+        assert_eq!(t.history_len(), 1);
+        assert!(t.is_valid());
+
+        // Inner checkpoint is now ahead of the history
+        assert_eq!(t.rollback(inner), Err(TokenError::StaleCheckpoint));
+
+        // Pruning past a checkpoint invalidates it
+        let start = new_token(1).checkpoint();
+        assert!(t.add_transaction(MockTransaction::new(t.uid.clone(), 1, 2, 1)).is_ok());
+        assert!(t.prune_history(1).is_ok());
+        assert_eq!(t.rollback(outer), Err(TokenError::StaleCheckpoint));
+        assert_eq!(t.rollback(start), Err(TokenError::StaleCheckpoint));
+        assert_eq!(t.history_len(), 1);
+    }
+
+    #[test]
+    fn test_from_deposit() {
+        let uid = BitVec::from_element(1u8);
+        let deposit = MockTransaction::new(uid.clone(), 0, 1, 0);
+        let t: Token<MockTransaction, [u8; 8]> =
+            Token::from_deposit(uid.clone(), deposit.clone(), 7).unwrap();
+        assert_eq!(t.status(), TokenStatus::PlasmaChain);
+        assert_eq!(t.history_len(), 1);
+        assert_eq!(t.history()[0].txn, deposit);
+        assert_eq!(t.history()[0].block_num, Some(7));
+        assert!(t.is_owned_by(&1));
+        assert!(t.is_valid());
+
+        // Verifying proofs starts from the deposit block
+        let mut roots = BTreeMap::new();
+        roots.insert(6, [0u8; 8]);
+        assert_eq!(t.verify_history_against_roots(&roots), Err(TokenError::MissingBlockRoot { block: 7 }));
+
+        let transfer = MockTransaction::new(uid.clone(), 1, 2, 1);
+        assert_eq!(
+            Token::<MockTransaction, [u8; 8]>::from_deposit(uid.clone(), transfer, 7),
+            Err(TokenError::NotDeposit)
+        );
+
+        let other_token = MockTransaction::new(BitVec::from_element(2u8), 0, 1, 0);
+        assert_eq!(
+            Token::<MockTransaction, [u8; 8]>::from_deposit(uid, other_token, 7),
+            Err(TokenError::WrongToken { index: 0 })
+        );
+    }
+
+    #[test]
+    fn test_exit_data() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        assert_eq!(t.exit_data().err(), Some(TokenError::EmptyHistory));
+
+        for i in 0..3 {
+            let mut entry = HistoryEntry::new(MockTransaction::new(t.uid.clone(), i, i + 1, i));
+            entry.block_num = Some(i as u64 + 1);
+            entry.proof = Some(Proof::from(vec![[i; 8]]));
+            assert!(t.add_entry(entry).is_ok());
+
+            // Deposit exit has no parent
+            if i == 0 {
+                let exit = t.exit_data().unwrap();
+                assert_eq!(exit.parent_txn, None);
+                assert_eq!(exit.parent_proof, None);
+            }
+        }
+
+        let exit = t.exit_data().unwrap();
+        assert_eq!(exit.exit_txn, MockTransaction::new(t.uid.clone(), 2, 3, 2));
+        assert_eq!(exit.exit_proof, Proof::from(vec![[2u8; 8]]));
+        assert_eq!(exit.exit_block, Some(3));
+        assert_eq!(exit.parent_txn, Some(MockTransaction::new(t.uid.clone(), 1, 2, 1)));
+        assert_eq!(exit.parent_proof, Some(Proof::from(vec![[1u8; 8]])));
+        assert_eq!(exit.parent_block, Some(2));
+
+        #[cfg(feature = "serde")]
+        {
+            let encoded = serde_json::to_string(&exit).unwrap();
+            let decoded: ExitData<MockTransaction, [u8; 8]> = serde_json::from_str(&encoded).unwrap();
+            assert_eq!(decoded, exit);
+        }
+
+        t.history[1].proof = None;
+        assert_eq!(t.exit_data().err(), Some(TokenError::MissingEntryProof { index: 1 }));
+    }
+
+    #[test]
+    fn test_add_twice() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        let txn1 = MockTransaction::new(t.uid.clone(), 0, 1, 0);
+        assert!(t.add_transaction(txn1.clone()).is_ok());
+
+        // Try and add the same transaction twice
+        assert_eq!(txn1.compare(&txn1), TxnCmp::Same);
+        assert_eq!(
+            t.add_transaction(txn1),
+            Err(TokenError::NotChildOfPrevious { got: TxnCmp::Same })
+        );
+        assert!(t.is_valid());
+    }
+
+    #[test]
+    fn test_status_transitions() {
+        type Transition = fn(&mut Token<MockTransaction, [u8; 8]>) -> Result<(), TokenError>;
+        let exiting = TokenStatus::Exiting { started_at_block: 5 };
+        let transitions: [(Transition, TokenStatus, TokenStatus); 5] = [
+            (Token::begin_deposit, TokenStatus::RootChain, TokenStatus::Deposit),
+            (Token::finalize_deposit, TokenStatus::Deposit, TokenStatus::PlasmaChain),
+            (Token::begin_withdrawal, TokenStatus::PlasmaChain, TokenStatus::Withdrawal),
+            (|t| t.start_exit(5), TokenStatus::Withdrawal, exiting),
+            (Token::finalize_withdrawal, exiting, TokenStatus::RootChain),
+        ];
+        let statuses = [
+            TokenStatus::RootChain,
+            TokenStatus::Deposit,
+            TokenStatus::PlasmaChain,
+            TokenStatus::Withdrawal,
+            exiting,
+            TokenStatus::Challenged { started_at_block: 5 },
+        ];
+
+        // Every transition from every status: only the lifecycle step succeeds
+        for (transition, from, to) in transitions.iter() {
+            for status in statuses.iter() {
+                let mut t = new_token(1);
+                move_to_status(&mut t, *status);
+                if status == from {
+                    assert!(transition(&mut t).is_ok());
+                    assert_eq!(t.status(), *to);
+                } else {
+                    assert_eq!(
+                        transition(&mut t),
+                        Err(TokenError::InvalidStatusTransition { from: *status, to: *to })
+                    );
+                    assert_eq!(t.status(), *status);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_full_lifecycle_and_withdrawal_blocks_transfers() {
+        let mut t = new_token(1);
+        assert!(t.begin_deposit().is_ok());
+        assert!(t.finalize_deposit().is_ok());
+        let txn1 = MockTransaction::new(t.uid.clone(), 0, 1, 0);
+        assert!(t.add_transaction(txn1).is_ok());
+
+        assert!(t.begin_withdrawal().is_ok());
+        let txn2 = MockTransaction::new(t.uid.clone(), 1, 2, 1);
+        assert_eq!(
+            t.add_transaction(txn2),
+            Err(TokenError::WrongStatus { current: TokenStatus::Withdrawal })
+        );
+        assert_eq!(t.history_len(), 1);
+
+        assert!(t.start_exit(10).is_ok());
+        assert!(t.finalize_withdrawal().is_ok());
+        assert_eq!(t.status(), TokenStatus::RootChain);
+    }
+
+    #[test]
+    fn test_transfers_need_child_chain_status() {
+        let mut t = new_token(1);
+        let deposit = MockTransaction::new(t.uid.clone(), 0, 1, 0);
+        let transfer = MockTransaction::new(t.uid.clone(), 1, 2, 1);
+        assert_eq!(
+            t.add_transaction(deposit.clone()),
+            Err(TokenError::WrongStatus { current: TokenStatus::RootChain })
+        );
+        assert_eq!(
+            t.add_transactions(vec![deposit.clone()]),
+            Err(TokenError::WrongStatus { current: TokenStatus::RootChain })
+        );
+
+        // Only the deposit itself is accepted while depositing
+        assert!(t.begin_deposit().is_ok());
+        assert_eq!(
+            t.add_transactions(vec![deposit.clone(), transfer.clone()]),
+            Err(TokenError::WrongStatus { current: TokenStatus::Deposit })
+        );
+        assert!(t.add_transaction(deposit.clone()).is_ok());
+        assert_eq!(
+            t.add_transaction(transfer.clone()),
+            Err(TokenError::WrongStatus { current: TokenStatus::Deposit })
+        );
+        assert!(t.finalize_deposit().is_ok());
+        assert!(t.add_transaction(transfer.clone()).is_ok());
+
+        // Tooling can still build histories regardless of status
+        let mut t = new_token(1);
+        assert!(t.add_transaction_unchecked(deposit).is_ok());
+        assert!(t.add_transaction_unchecked(transfer.clone()).is_ok());
+        assert_eq!(
+            t.add_transaction_unchecked(transfer),
+            Err(TokenError::NotChildOfPrevious { got: TxnCmp::Same })
+        );
+        assert_eq!(t.status(), TokenStatus::RootChain);
+        assert_eq!(t.history_len(), 2);
+    }
+
+    #[test]
+    fn test_challenged_exit() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::Withdrawal);
+        assert_eq!(t.challenge_exit(), Err(TokenError::WrongStatus { current: TokenStatus::Withdrawal }));
+        assert_eq!(t.clear_challenge(), Err(TokenError::WrongStatus { current: TokenStatus::Withdrawal }));
+
+        assert!(t.start_exit(10).is_ok());
+        assert!(t.challenge_exit().is_ok());
+        assert_eq!(t.status(), TokenStatus::Challenged { started_at_block: 10 });
+
+        // No transfers or finalization while challenged
+        let txn = MockTransaction::new(t.uid.clone(), 0, 1, 0);
+        assert_eq!(t.add_transaction(txn.clone()), Err(TokenError::WrongStatus { current: t.status() }));
+        assert_eq!(
+            t.finalize_withdrawal(),
+            Err(TokenError::InvalidStatusTransition {
+                from: TokenStatus::Challenged { started_at_block: 10 },
+                to: TokenStatus::RootChain,
+            })
+        );
+
+        // Clearing the challenge restores the original exit
+        assert!(t.clear_challenge().is_ok());
+        assert_eq!(t.status(), TokenStatus::Exiting { started_at_block: 10 });
+        assert_eq!(t.add_transaction(txn), Err(TokenError::WrongStatus { current: t.status() }));
+        assert!(t.finalize_withdrawal().is_ok());
+    }
+
+    #[test]
+    fn test_bytes_exit_status_round_trip() {
+        for status in &[
+            TokenStatus::Exiting { started_at_block: 1 << 40 },
+            TokenStatus::Challenged { started_at_block: 7 },
+        ] {
+            let mut t = new_token(1);
+            move_to_status(&mut t, *status);
+            let bytes = t.to_bytes(|txn| txn.as_bytes().to_vec());
+            let decoded = Token::<MockTransaction, [u8; 8]>::from_bytes(&bytes, decode_mock).unwrap();
+            assert_eq!(decoded.status(), *status);
+        }
+    }
+
+    #[test]
+    fn test_error_messages_unchanged() {
+        assert_eq!(
+            TokenError::NotChildOfPrevious { got: TxnCmp::Same }.to_string(),
+            "Transaction is not a child of previous transaction."
+        );
+    }
+
+    #[test]
+    fn test_default_compare_is_antisymmetric() {
+        let flipped = |cmp| match cmp {
+            TxnCmp::Parent => TxnCmp::Child,
+            TxnCmp::Child => TxnCmp::Parent,
+            TxnCmp::EarlierSibling => TxnCmp::LaterSibling,
+            TxnCmp::LaterSibling => TxnCmp::EarlierSibling,
+            cmp => cmp,
+        };
+        let mut txns = Vec::new();
+        for uid in &[1u8, 2] {
+            for sender in 0..3 {
+                for receiver in 0..3 {
+                    for block_num in 0..3 {
+                        let uid = BitVec::from_element(*uid);
+                        txns.push(MockTransaction::new(uid, sender, receiver, block_num));
+                    }
+                }
+            }
+        }
+
+        for a in &txns {
+            assert_eq!(a.compare(a), TxnCmp::Same);
+            for b in &txns {
+                assert_eq!(b.compare(a), flipped(a.compare(b)), "{:?} vs {:?}", a, b);
+            }
+        }
+
+        // Deposits have no sender, so can only be a parent
+        let uid = BitVec::from_element(1u8);
+        let deposit = MockTransaction::new(uid.clone(), 0, 1, 0);
+        assert_eq!(deposit.compare(&MockTransaction::new(uid.clone(), 1, 2, 1)), TxnCmp::Parent);
+        assert_eq!(deposit.compare(&MockTransaction::new(uid.clone(), 0, 2, 1)), TxnCmp::Unrelated);
+        assert_eq!(deposit.compare(&MockTransaction::new(uid.clone(), 2, 0, 1)), TxnCmp::Unrelated);
+
+        // Hidden receiver, so can only be a child
+        let hidden = MockTransaction::new(uid.clone(), 1, 0, 1);
+        assert_eq!(hidden.compare(&deposit), TxnCmp::Child);
+        assert_eq!(hidden.compare(&MockTransaction::new(uid, 2, 3, 2)), TxnCmp::Unrelated);
+    }
+
+    #[test]
+    fn test_cycle_rejected() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        let txn1 = MockTransaction::new(t.uid.clone(), 1, 2, 3);
+        assert!(t.add_transaction(txn1.clone()).is_ok());
+
+        // Each could have spent the other
+        let txn2 = MockTransaction::new(t.uid.clone(), 2, 1, 3);
+        assert_eq!(txn2.compare(&txn1), TxnCmp::Cycle);
+        assert_eq!(txn1.compare(&txn2), TxnCmp::Cycle);
+        assert_eq!(
+            t.add_transaction(txn2.clone()),
+            Err(TokenError::NotChildOfPrevious { got: TxnCmp::Cycle })
+        );
+        t.history.push(HistoryEntry::new(txn2));
+        assert_eq!(
+            t.validate(),
+            Err(HistoryError::NotChildOfPrevious { index: 1, got: TxnCmp::Cycle })
+        );
+        assert_eq!(
+            validate_history_iter(t.transactions()),
+            Err((1, TokenError::NotChildOfPrevious { got: TxnCmp::Cycle }))
+        );
+
+        // Coin returned to its previous owner in a later block
+        t.revert_to(1);
+        let txn2 = MockTransaction::new(t.uid.clone(), 2, 1, 4);
+        assert_eq!(txn2.compare(&txn1), TxnCmp::Child);
+        assert_eq!(txn1.compare(&txn2), TxnCmp::Parent);
+        assert!(t.add_transaction(txn2).is_ok());
+        assert!(t.is_valid());
+    }
+
+    #[test]
+    fn test_compare_siblings() {
+        // Only the blocks spent are looked at
+        let uid = BitVec::from_element(1u8);
+        let txn1 = MockTransaction::new(uid.clone(), 1, 2, 3);
+        let txn2 = MockTransaction::new(uid.clone(), 4, 5, 6);
+        assert_eq!(txn1.compare_siblings(&txn2), TxnCmp::EarlierSibling);
+        assert_eq!(txn2.compare_siblings(&txn1), TxnCmp::LaterSibling);
+        assert_eq!(txn1.compare_siblings(&MockTransaction::new(uid, 7, 8, 3)), TxnCmp::DoubleSpend);
+
+        // Also through references
+        let (txn1, txn2) = (&txn1, &txn2);
+        assert_eq!(PlasmaCashTxn::compare_siblings(&txn1, &txn2), TxnCmp::EarlierSibling);
+    }
+
+    #[test]
+    fn test_earlier_sibling() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        let txn1 = MockTransaction::new(t.uid.clone(), 3, 1, 1);
+        assert!(t.add_transaction(txn1.clone()).is_ok());
+
+        // Try and add a transaction sent before the stored one
+        let txn2 = MockTransaction::new(t.uid.clone(), 3, 2, 0);
+        assert_eq!(txn2.compare(&txn1), TxnCmp::EarlierSibling);
+        assert_eq!(
+            t.add_transaction(txn2.clone()),
+            Err(TokenError::NotChildOfPrevious { got: TxnCmp::EarlierSibling })
+        );
+        assert_eq!(t.validate(), Ok(()));
+
+        // Same transaction in the history is reported as the failure
+        t.history.push(HistoryEntry::new(txn2));
+        assert!(!t.is_valid());
+        assert_eq!(
+            t.validate(),
+            Err(HistoryError::NotChildOfPrevious { index: 1, got: TxnCmp::EarlierSibling })
+        );
+    }
+
+    #[test]
+    fn test_later_sibling() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        let txn1 = MockTransaction::new(t.uid.clone(), 3, 1, 0);
+        assert!(t.add_transaction(txn1.clone()).is_ok());
+
+        // Try and add a transaction sent after the stored one that conflicts
+        let txn2 = MockTransaction::new(t.uid.clone(), 3, 2, 1);
+        assert_eq!(txn2.compare(&txn1), TxnCmp::LaterSibling);
+        assert_eq!(
+            t.add_transaction(txn2.clone()),
+            Err(TokenError::NotChildOfPrevious { got: TxnCmp::LaterSibling })
+        );
+        assert_eq!(t.validate(), Ok(()));
+
+        // Same transaction in the history is reported as the failure
+        t.history.push(HistoryEntry::new(txn2));
+        assert!(!t.is_valid());
+        assert_eq!(
+            t.validate(),
+            Err(HistoryError::NotChildOfPrevious { index: 1, got: TxnCmp::LaterSibling })
+        );
     }
 
     #[test]
     fn test_double_spend() {
         let mut t = new_token(1);
-        let txn1 = MockTransaction::new(t.uid.clone(), 0, 1, 0);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        let txn1 = MockTransaction::new(t.uid.clone(), 3, 1, 0);
         assert!(t.add_transaction(txn1.clone()).is_ok());
 
         // try and add a transaction that conflicts at the same height as the stored one
-        let txn2 = MockTransaction::new(t.uid.clone(), 0, 2, 0);
+        let txn2 = MockTransaction::new(t.uid.clone(), 3, 2, 0);
         assert_eq!(txn2.compare(&txn1), TxnCmp::DoubleSpend);
-        assert!(t.add_transaction(txn2).is_err());
+        assert_eq!(
+            t.add_transaction(txn2.clone()),
+            Err(TokenError::NotChildOfPrevious { got: TxnCmp::DoubleSpend })
+        );
+        assert_eq!(t.validate(), Ok(()));
+
+        // Same transaction in the history is reported as the failure
+        t.history.push(HistoryEntry::new(txn2));
+        assert!(!t.is_valid());
+        assert_eq!(
+            t.validate(),
+            Err(HistoryError::NotChildOfPrevious { index: 1, got: TxnCmp::DoubleSpend })
+        );
+    }
+
+    #[test]
+    fn test_leaf_hashes_matches_leaf_hash() {
+        let uid = BitVec::from_element(1u8);
+        let txns = [
+            MockTransaction::new(uid.clone(), 0, 1, 0),
+            MockTransaction::new(uid.clone(), 1, 2, 1),
+            MockTransaction::new(uid, 2, 3, 2),
+        ];
+        let hashes = MockTransaction::leaf_hashes(&[&txns[0], &txns[1], &txns[2]]);
+        assert_eq!(hashes.len(), txns.len());
+        for (txn, hash) in txns.iter().zip(hashes.iter()) {
+            assert_eq!(&txn.leaf_hash(), hash);
+        }
+    }
+
+    #[test]
+    fn test_import_lenient() {
+        let uid = BitVec::from_element(1u8);
+        let mut history: Vec<MockTransaction> = (0..12)
+            .map(|i| MockTransaction::new(uid.clone(), i, i + 1, i))
+            .collect();
+        // Break the chain at entry 7 of 12
+        history[7] = MockTransaction::new(uid.clone(), 100, 101, 7);
+
+        let (t, rejected): (Token<MockTransaction, [u8; 8]>, _) =
+            Token::import_lenient(uid.clone(), history);
+        assert_eq!(t.history_len(), 7);
+        assert!(t.is_valid());
+        assert!(t.is_partially_imported());
+        let report = t.audit_history();
+        assert!(report.is_valid());
+        assert!(report.partially_imported);
+
+        assert_eq!(rejected.len(), 5);
+        assert_eq!(rejected[0].index, 7);
+        assert_eq!(rejected[0].reason, TokenError::NotChildOfPrevious { got: TxnCmp::Unrelated });
+        for (i, entry) in rejected.iter().enumerate().skip(1) {
+            assert_eq!(entry.index, 7 + i);
+            assert_eq!(entry.reason, TokenError::FollowsRejectedTransaction);
+        }
+
+        // A fully valid history imports cleanly
+        let history: Vec<MockTransaction> = (0..3)
+            .map(|i| MockTransaction::new(uid.clone(), i, i + 1, i))
+            .collect();
+        let (t, rejected): (Token<MockTransaction, [u8; 8]>, _) =
+            Token::import_lenient(uid, history);
+        assert_eq!(t.history_len(), 3);
+        assert!(rejected.is_empty());
+        assert!(!t.is_partially_imported());
+        assert!(!t.audit_history().partially_imported);
+    }
+
+    fn decode_mock(bytes: &[u8]) -> Option<MockTransaction> {
+        MockTransaction::decode(bytes).ok()
+    }
+
+    fn encoded_token() -> (Token<MockTransaction, [u8; 8]>, Vec<u8>) {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        let txn1 = MockTransaction::new(t.uid.clone(), 0, 1, 0);
+        let txn2 = MockTransaction::new(t.uid.clone(), 1, 2, 1);
+        assert!(t.add_transaction(txn1).is_ok());
+        assert!(t.add_transaction(txn2).is_ok());
+        t.history[0].proof = Some(Proof::from(vec![[1u8; 8], [2u8; 8]]));
+        t.history[1].proof = Some(Proof::from(vec![[3u8; 8]]));
+        t.history[1].block_root = Some([4u8; 8]);
+        t.history[1].block_num = Some(7);
+        t.history[1].confirmation = Some(vec![1, 2, 3]);
+        t.exclusion_proofs.insert(8, Proof::from(vec![[5u8; 8]]));
+        let bytes = t.to_bytes(|txn| txn.as_bytes().to_vec());
+        (t, bytes)
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let (t, bytes) = encoded_token();
+        let decoded = Token::<MockTransaction, [u8; 8]>::from_bytes(&bytes, decode_mock).unwrap();
+        assert_eq!(decoded.uid, t.uid);
+        assert_eq!(decoded.status(), t.status());
+        assert_eq!(decoded.history(), t.history());
+        assert_eq!(decoded.exclusion_proofs, t.exclusion_proofs);
+
+        // Encoding is deterministic
+        assert_eq!(decoded.to_bytes(|txn| txn.as_bytes().to_vec()), bytes);
+    }
+
+    #[test]
+    fn test_canonical_encoding() {
+        let (t, bytes) = encoded_token();
+        assert_eq!(t.encode(), bytes);
+        let decoded = Token::<MockTransaction, [u8; 8]>::decode(&bytes).unwrap();
+        assert_eq!(decoded.history(), t.history());
+        assert_eq!(decoded.encode(), bytes);
+
+        for entry in t.history() {
+            let txn = &entry.txn;
+            assert_eq!(MockTransaction::decode(&txn.encode()), Ok(txn.clone()));
+            assert_eq!(txn.leaf_hash(), MockTransaction::hasher()(&txn.encode()));
+        }
+        assert_eq!(MockTransaction::decode(&[1, 2, 3]), Err(Malformed));
+    }
+
+    #[test]
+    fn test_bytes_rejects_trailing_and_invalid() {
+        let (_, mut bytes) = encoded_token();
+        bytes.push(0);
+        assert_eq!(
+            Token::<MockTransaction, [u8; 8]>::from_bytes(&bytes, decode_mock).err(),
+            Some(TokenError::Codec(CodecError::TrailingBytes { remaining: 1 }))
+        );
+
+        // Second transaction is not a child of the first
+        let mut t = new_token(1);
+        t.history.push(HistoryEntry::new(MockTransaction::new(t.uid.clone(), 0, 1, 0)));
+        t.history.push(HistoryEntry::new(MockTransaction::new(t.uid.clone(), 5, 6, 1)));
+        let bytes = t.to_bytes(|txn| txn.as_bytes().to_vec());
+        assert_eq!(
+            Token::<MockTransaction, [u8; 8]>::from_bytes(&bytes, decode_mock).err(),
+            Some(TokenError::InvalidHistory)
+        );
+    }
+
+    #[test]
+    fn test_bytes_truncated_and_corrupted_never_panic() {
+        let (_, bytes) = encoded_token();
+        for len in 0..bytes.len() {
+            assert!(Token::<MockTransaction, [u8; 8]>::from_bytes(&bytes[..len], decode_mock).is_err());
+        }
+        for i in 0..bytes.len() {
+            for flip in &[0x01u8, 0x80, 0xff] {
+                let mut corrupted = bytes.clone();
+                corrupted[i] ^= flip;
+                // Any result is fine, as long as parsing doesn't panic
+                let _ = Token::<MockTransaction, [u8; 8]>::from_bytes(&corrupted, decode_mock);
+            }
+        }
+    }
+
+    fn legacy_bytes(proofs: &[Vec<[u8; 8]>]) -> Vec<u8> {
+        let uid = BitVec::from_element(1u8);
+        let mut bytes = Vec::new();
+        let mut w = Writer::new(&mut bytes);
+        w.put_u8(LEGACY_ENCODING_VERSION).unwrap();
+        w.put_bitvec(&uid).unwrap();
+        w.put_u8(2).unwrap(); // PlasmaChain
+        w.put_u8(0).unwrap(); // No flags
+        w.put_len(2).unwrap();
+        w.put_len_prefixed(&MockTransaction::new(uid.clone(), 0, 1, 0).as_bytes()).unwrap();
+        w.put_len_prefixed(&MockTransaction::new(uid, 1, 2, 1).as_bytes()).unwrap();
+        w.put_len(proofs.len()).unwrap();
+        for proof in proofs {
+            w.put_len(proof.len()).unwrap();
+            for hash in proof {
+                w.put_bytes(hash).unwrap();
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_bytes_legacy_layout() {
+        let bytes = legacy_bytes(&[vec![[1u8; 8]], vec![[2u8; 8], [3u8; 8]]]);
+        let t = Token::<MockTransaction, [u8; 8]>::from_bytes(&bytes, decode_mock).unwrap();
+        assert_eq!(t.status(), TokenStatus::PlasmaChain);
+        assert_eq!(t.history_len(), 2);
+        assert_eq!(t.history()[0].proof, Some(Proof::from(vec![[1u8; 8]])));
+        assert_eq!(t.history()[1].proof, Some(Proof::from(vec![[2u8; 8], [3u8; 8]])));
+        assert_eq!(t.history()[1].block_root, None);
+
+        let bytes = legacy_bytes(&[]);
+        let t = Token::<MockTransaction, [u8; 8]>::from_bytes(&bytes, decode_mock).unwrap();
+        assert!(t.proofs().all(|proof| proof.is_none()));
+
+        // One proof for two transactions can't be paired up
+        let bytes = legacy_bytes(&[vec![[1u8; 8]]]);
+        assert_eq!(
+            Token::<MockTransaction, [u8; 8]>::from_bytes(&bytes, decode_mock).err(),
+            Some(TokenError::ProofCountMismatch { history: 2, proofs: 1 })
+        );
+    }
+
+    #[test]
+    fn test_bytes_unchanged() {
+        // `encoded_token()` as the first `to_bytes()` wrote it, with the
+        // proofs in a list of their own and no exclusion proofs
+        let v1 = [
+            1, 0, 0, 0, 8, 1, 2, 0, // Version, uid, status, flags
+            0, 0, 0, 2, 0, 0, 0, 4, 1, 0, 1, 0, 0, 0, 0, 4, 1, 1, 2, 1, // Transactions
+            0, 0, 0, 2, // Proofs
+            0, 0, 0, 2, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2,
+            0, 0, 0, 1, 3, 3, 3, 3, 3, 3, 3, 3,
+        ];
+        assert_eq!(legacy_bytes(&[vec![[1u8; 8], [2u8; 8]], vec![[3u8; 8]]]), v1);
+        let t = Token::<MockTransaction, [u8; 8]>::from_bytes(&v1, decode_mock).unwrap();
+        let (expected, bytes) = encoded_token();
+        assert!(t.transactions().eq(expected.transactions()));
+        assert!(t.proofs().eq(expected.proofs()));
+
+        assert_eq!(bytes, [
+            3, 0, 0, 0, 8, 1, 2, 0, // Version, uid, status, flags
+            0, 0, 0, 2, // History
+            0, 0, 0, 4, 1, 0, 1, 0, 1, // Transaction, proof only
+            0, 0, 0, 2, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2,
+            0, 0, 0, 4, 1, 1, 2, 1, 15, // Transaction, proof, root, block and confirmation
+            0, 0, 0, 1, 3, 3, 3, 3, 3, 3, 3, 3,
+            4, 4, 4, 4, 4, 4, 4, 4,
+            0, 0, 0, 0, 0, 0, 0, 7,
+            0, 0, 0, 3, 1, 2, 3,
+            0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 8, 0, 0, 0, 1, 5, 5, 5, 5, 5, 5, 5, 5, // Exclusion proofs
+        ]);
+    }
+
+    #[test]
+    fn test_bytes_without_exclusions() {
+        // Older layout is the current one minus the (empty) exclusion proof list
+        let (mut t, _) = encoded_token();
+        t.history[1].block_num = None;
+        t.exclusion_proofs.clear();
+        let mut bytes = t.to_bytes(|txn| txn.as_bytes().to_vec());
+        bytes.truncate(bytes.len() - 4);
+        bytes[0] = NO_EXCLUSIONS_ENCODING_VERSION;
+        let decoded = Token::<MockTransaction, [u8; 8]>::from_bytes(&bytes, decode_mock).unwrap();
+        assert_eq!(decoded.history(), t.history());
+        assert!(decoded.exclusion_proofs.is_empty());
+    }
+
+    #[test]
+    fn test_from_legacy_parts() {
+        let uid = BitVec::from_element(1u8);
+        let history = vec![
+            MockTransaction::new(uid.clone(), 0, 1, 0),
+            MockTransaction::new(uid.clone(), 1, 2, 1),
+        ];
+        let proofs = vec![Proof::from(vec![[1u8; 8]]), Proof::from(vec![[2u8; 8]])];
+
+        let t = Token::from_legacy_parts(uid.clone(), history.clone(), proofs).unwrap();
+        assert_eq!(t.transactions().cloned().collect::<Vec<_>>(), history);
+        assert_eq!(
+            t.proofs().collect::<Vec<_>>(),
+            vec![Some(&Proof::from(vec![[1u8; 8]])), Some(&Proof::from(vec![[2u8; 8]]))]
+        );
+        assert_eq!(t.latest_transaction(), history.last());
+        assert_eq!(t.transaction_at(0), history.first());
+        assert_eq!(t.transaction_at(2), None);
+
+        assert_eq!(
+            Token::<MockTransaction, [u8; 8]>::from_legacy_parts(uid.clone(), vec![], vec![Proof::default()]).err(),
+            Some(TokenError::ProofCountMismatch { history: 0, proofs: 1 })
+        );
+        let reversed = history.into_iter().rev().collect();
+        assert_eq!(
+            Token::<MockTransaction, [u8; 8]>::from_legacy_parts(uid, reversed, vec![]).err(),
+            Some(TokenError::InvalidHistory)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        let txn1 = MockTransaction::new(t.uid.clone(), 0, 1, 0);
+        let txn2 = MockTransaction::new(t.uid.clone(), 1, 2, 1);
+        assert!(t.add_transaction(txn1).is_ok());
+        assert!(t.add_transaction(txn2).is_ok());
+        t.history[0].proof = Some(Proof::from(vec![[1u8; 8], [2u8; 8]]));
+        t.history[1].proof = Some(Proof::from(vec![[3u8; 8], [4u8; 8]]));
+        t.history[1].block_root = Some([5u8; 8]);
+        t.history[1].block_num = Some(2);
+        t.history[1].confirmation = Some(vec![1, 2, 3]);
+        t.exclusion_proofs.insert(3, Proof::from(vec![[6u8; 8]]));
+
+        // Hashes and the uid are hex in JSON
+        let encoded = serde_json::to_string(&t).unwrap();
+        assert!(encoded.contains("\"uid\":\"0x01\""));
+        assert!(encoded.contains("\"proof\":[\"0x0101010101010101\",\"0x0202020202020202\"]"));
+        assert!(encoded.contains("\"block_root\":\"0x0505050505050505\""));
+        let decoded: Token<MockTransaction, [u8; 8]> = serde_json::from_str(&encoded).unwrap();
+        assert_eq!(decoded.uid, t.uid);
+        assert_eq!(decoded.status(), t.status());
+        assert_eq!(decoded.history(), t.history());
+        assert_eq!(decoded.exclusion_proofs, t.exclusion_proofs);
+        assert!(decoded.is_valid());
+
+        // ...and raw bytes in binary formats
+        let encoded = bincode::serialize(&t).unwrap();
+        assert!(!encoded.windows(4).any(|w| w == b"0x01"));
+        let decoded: Token<MockTransaction, [u8; 8]> = bincode::deserialize(&encoded).unwrap();
+        assert_eq!(decoded.uid, t.uid);
+        assert_eq!(decoded.history(), t.history());
+        assert_eq!(decoded.exclusion_proofs, t.exclusion_proofs);
+
+        // JSON written with hashes as integer arrays still decodes
+        let legacy = serde_json::to_string(&t).unwrap().replace(
+            "\"block_root\":\"0x0505050505050505\"",
+            "\"block_root\":[5,5,5,5,5,5,5,5]",
+        );
+        let decoded: Token<MockTransaction, [u8; 8]> = serde_json::from_str(&legacy).unwrap();
+        assert_eq!(decoded.history(), t.history());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_uid_bit_order() {
+        // 5-bit uid doesn't fill a byte, and must not gain or lose bits
+        let mut uid = BitVec::new();
+        for bit in &[true, false, true, true, false] {
+            uid.push(*bit);
+        }
+        let mut t: Token<MockTransaction, [u8; 8]> = Token::new(uid);
+        let mut entry = HistoryEntry::new(MockTransaction::new(t.uid.clone(), 0, 1, 0));
+        entry.proof = Some(Proof::from(vec![[1u8; 8]]));
+        t.history.push(entry);
+        let encoded = serde_json::to_string(&t).unwrap();
+        let decoded: Token<MockTransaction, [u8; 8]> = serde_json::from_str(&encoded).unwrap();
+        assert_eq!(decoded.uid, t.uid);
+        assert_eq!(decoded.uid.len(), 5);
+        let decoded: Token<MockTransaction, [u8; 8]> =
+            bincode::deserialize(&bincode::serialize(&t).unwrap()).unwrap();
+        assert_eq!(decoded.uid, t.uid);
+
+        // Wrong-size hashes are rejected
+        let bad = encoded.replace("\"proof\":[\"0x0101010101010101\"]", "\"proof\":[\"0x010203\"]");
+        assert_ne!(bad, encoded);
+        assert!(serde_json::from_str::<Token<MockTransaction, [u8; 8]>>(&bad).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_split_from() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        assert!(t.add_transaction(MockTransaction::new(t.uid.clone(), 0, 1, 0)).is_ok());
+        let (_, one) = t.split().unwrap();
+        let decoded: Token<MockTransaction, [u8; 8]> =
+            serde_json::from_str(&serde_json::to_string(&one).unwrap()).unwrap();
+        assert_eq!(decoded, one);
+        assert!(decoded.is_valid());
+        let decoded: Token<MockTransaction, [u8; 8]> =
+            bincode::deserialize(&bincode::serialize(&one).unwrap()).unwrap();
+        assert_eq!(decoded, one);
+    }
+
+    #[test]
+    fn test_resolve_conflict() {
+        let uid = BitVec::from_element(1u8);
+        let common = [
+            MockTransaction::new(uid.clone(), 0, 1, 0),
+            MockTransaction::new(uid.clone(), 1, 2, 1),
+        ];
+        let mut older = new_token(1);
+        move_to_status(&mut older, TokenStatus::PlasmaChain);
+        let mut newer = new_token(1);
+        move_to_status(&mut newer, TokenStatus::PlasmaChain);
+        assert_eq!(Token::resolve_conflict(&older, &newer), ConflictResolution::Equivalent);
+        for txn in &common {
+            assert!(older.add_transaction(txn.clone()).is_ok());
+            assert!(newer.add_transaction(txn.clone()).is_ok());
+        }
+
+        // Owner 2 spends the coin in block 2, and again in block 4
+        assert!(older.add_transaction(MockTransaction::new(uid.clone(), 2, 3, 2)).is_ok());
+        assert!(older.add_transaction(MockTransaction::new(uid.clone(), 3, 4, 3)).is_ok());
+        assert!(newer.add_transaction(MockTransaction::new(uid.clone(), 2, 5, 4)).is_ok());
+        assert_eq!(
+            Token::resolve_conflict(&older, &newer),
+            ConflictResolution::First { divergence_index: 2 }
+        );
+        assert_eq!(
+            Token::resolve_conflict(&newer, &older),
+            ConflictResolution::Second { divergence_index: 2 }
+        );
+
+        // A longer history that agrees isn't a conflict
+        let mut shorter = older.clone();
+        shorter.revert_last_transaction();
+        assert_eq!(Token::resolve_conflict(&older, &older), ConflictResolution::Equivalent);
+        assert_eq!(Token::resolve_conflict(&shorter, &older), ConflictResolution::Equivalent);
+
+        // Positions line up across pruned histories
+        let mut pruned = older.clone();
+        assert!(pruned.prune_history(1).is_ok());
+        assert_eq!(
+            Token::resolve_conflict(&newer, &pruned),
+            ConflictResolution::Second { divergence_index: 2 }
+        );
+
+        // Double spends are decided by which was included first
+        let mut double = shorter.clone();
+        double.revert_last_transaction();
+        assert!(double.add_transaction(MockTransaction::new(uid.clone(), 2, 6, 2)).is_ok());
+        assert_eq!(Token::resolve_conflict(&shorter, &double), ConflictResolution::Unrelated);
+        double.history[2].block_num = Some(2);
+        assert_eq!(
+            Token::resolve_conflict(&shorter, &double),
+            ConflictResolution::Second { divergence_index: 2 }
+        );
+        shorter.history[2].block_num = Some(1);
+        assert_eq!(
+            Token::resolve_conflict(&shorter, &double),
+            ConflictResolution::First { divergence_index: 2 }
+        );
+
+        // Different coins
+        let other = new_token(2);
+        assert_eq!(Token::resolve_conflict(&older, &other), ConflictResolution::Unrelated);
+    }
+
+    #[test]
+    fn test_find_common_ancestor() {
+        let uid = BitVec::from_element(1u8);
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        assert_eq!(t.find_common_ancestor(&t), None);
+        for i in 0..4 {
+            assert!(t.add_transaction(MockTransaction::new(uid.clone(), i, i + 1, i)).is_ok());
+        }
+        assert_eq!(t.find_common_ancestor(&t.clone()), Some(3));
+
+        // Fork after index 1
+        let mut fork = t.clone();
+        fork.revert_to(2);
+        assert!(fork.add_transaction(MockTransaction::new(uid.clone(), 2, 7, 5)).is_ok());
+        assert_eq!(t.find_common_ancestor(&fork), Some(1));
+        assert_eq!(fork.find_common_ancestor(&t), Some(1));
+
+        // Indices count pruned entries
+        let mut pruned = t.clone();
+        assert!(pruned.prune_history(1).is_ok());
+        assert_eq!(pruned.find_common_ancestor(&fork), Some(1));
+        assert!(pruned.prune_history(1).is_ok());
+        assert_eq!(pruned.find_common_ancestor(&fork), None);
+
+        // Disjoint histories, and different coins
+        let mut disjoint = new_token(1);
+        move_to_status(&mut disjoint, TokenStatus::PlasmaChain);
+        assert!(disjoint.add_transaction(MockTransaction::new(uid, 0, 9, 0)).is_ok());
+        assert_eq!(t.find_common_ancestor(&disjoint), None);
+        let mut other = new_token(2);
+        move_to_status(&mut other, TokenStatus::PlasmaChain);
+        let txns = (0..4).map(|i| MockTransaction::new(BitVec::from_element(2u8), i, i + 1, i));
+        assert_eq!(other.add_transactions(txns), Ok(4));
+        assert_eq!(t.find_common_ancestor(&other), None);
+    }
+
+    #[test]
+    fn test_challenges() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        let uid = t.uid.clone();
+        assert!(t.add_transaction(MockTransaction::new(uid.clone(), 0, 1, 0)).is_ok());
+        assert!(t.add_transaction(MockTransaction::new(uid.clone(), 1, 2, 1)).is_ok());
+
+        // Conflicting spend is rejected from the history, but kept as a challenge
+        let conflict = MockTransaction::new(uid.clone(), 1, 3, 2);
+        assert!(t.add_transaction(conflict.clone()).is_err());
+        assert_eq!(t.record_challenge(conflict.clone(), TxnCmp::LaterSibling), Ok(0));
+        assert_eq!(t.record_challenge(conflict.clone(), TxnCmp::LaterSibling), Ok(0));
+        let spend = MockTransaction::new(uid.clone(), 1, 4, 1);
+        assert_eq!(t.record_challenge(spend.clone(), TxnCmp::DoubleSpend), Ok(1));
+        assert_eq!(
+            t.record_challenge(MockTransaction::new(BitVec::from_element(2u8), 1, 3, 2), TxnCmp::Unrelated),
+            Err(TokenError::ChallengeWrongToken)
+        );
+        assert_eq!(
+            t.pending_challenges(),
+            &[
+                ChallengeRecord { txn: conflict.clone(), reason: TxnCmp::LaterSibling },
+                ChallengeRecord { txn: spend.clone(), reason: TxnCmp::DoubleSpend },
+            ][..]
+        );
+        assert_eq!(
+            t.audit_history().pending_challenges,
+            vec![TxnCmp::LaterSibling, TxnCmp::DoubleSpend]
+        );
+        assert!(t.audit_history().is_valid());
+
+        // Challenges survive encoding
+        let bytes = t.to_bytes(|txn| txn.as_bytes().to_vec());
+        let decoded = Token::<MockTransaction, [u8; 8]>::from_bytes(&bytes, decode_mock).unwrap();
+        assert_eq!(decoded, t);
+        #[cfg(feature = "serde")]
+        {
+            let decoded: Token<MockTransaction, [u8; 8]> =
+                serde_json::from_str(&serde_json::to_string(&t).unwrap()).unwrap();
+            assert_eq!(decoded, t);
+        }
+
+        assert_eq!(
+            t.remove_challenge(0),
+            Ok(ChallengeRecord { txn: conflict, reason: TxnCmp::LaterSibling })
+        );
+        assert_eq!(t.remove_challenge(1), Err(TokenError::ChallengeOutOfRange { index: 1, len: 1 }));
+        assert_eq!(t.pending_challenges()[0].txn, spend);
+        assert!(t.remove_challenge(0).is_ok());
+        assert!(t.pending_challenges().is_empty());
+
+        // Without challenges, the encoding is unchanged
+        let mut plain = new_token(1);
+        move_to_status(&mut plain, TokenStatus::PlasmaChain);
+        assert!(plain.add_transaction(MockTransaction::new(uid.clone(), 0, 1, 0)).is_ok());
+        assert!(plain.add_transaction(MockTransaction::new(uid, 1, 2, 1)).is_ok());
+        assert_eq!(t.to_bytes(|txn| txn.as_bytes().to_vec()), plain.to_bytes(|txn| txn.as_bytes().to_vec()));
+    }
+
+    #[test]
+    fn test_provenance() {
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        assert!(t.provenance().is_empty());
+        assert!(!t.was_owned_by(&1));
+
+        // Deposit has no sender, and owner 2 sends the coin to themselves
+        let uid = t.uid.clone();
+        for (sender, receiver, block) in &[(0, 1, 0), (1, 2, 1), (2, 2, 2), (2, 3, 3)] {
+            assert!(t.add_transaction(MockTransaction::new(uid.clone(), *sender, *receiver, *block)).is_ok());
+        }
+        assert_eq!(t.provenance(), vec![1, 2, 3]);
+        assert!(t.was_owned_by(&2));
+        assert!(!t.was_owned_by(&0));
+
+        // Known first sender comes first
+        assert!(t.prune_history(1).is_ok());
+        assert_eq!(t.provenance(), vec![1, 2, 3]);
+        assert!(t.was_owned_by(&1));
+        assert!(t.prune_history(2).is_ok());
+        assert_eq!(t.provenance(), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_sync_with_peer() {
+        let uid = BitVec::from_element(1u8);
+        let mut ours = new_token(1);
+        move_to_status(&mut ours, TokenStatus::PlasmaChain);
+        let mut theirs = ours.clone();
+        for i in 0..5 {
+            let mut entry = HistoryEntry::new(MockTransaction::new(uid.clone(), i, i + 1, i));
+            entry.block_num = Some(u64::from(i) + 1);
+            if i < 3 {
+                assert!(ours.add_entry(entry.clone()).is_ok());
+            }
+            assert!(theirs.add_entry(entry).is_ok());
+        }
+
+        // Peer sends what we're missing after our latest block
+        assert_eq!(theirs.history_after(3), &theirs.history()[3..]);
+        assert_eq!(theirs.history_after(0), &theirs.history()[..]);
+        assert!(theirs.history_after(5).is_empty());
+
+        // Failed syncs leave the token untouched
+        let mut gap = ours.clone();
+        assert_eq!(
+            gap.extend_from(theirs.history_after(4)),
+            Err(TokenError::BatchNotChildOfPrevious { index: 0, got: TxnCmp::Unrelated })
+        );
+        let mut late = theirs.history_after(3).to_vec();
+        late[1].block_num = Some(4);
+        assert_eq!(
+            gap.extend_from(&late),
+            Err(TokenError::InclusionOutOfOrder { index: 4, block: 4 })
+        );
+        assert_eq!(gap, ours);
+
+        // Roots the peer's proofs don't prove aren't kept
+        let mut unproven = theirs.history_after(3).to_vec();
+        unproven[0].block_root = Some([9u8; 8]);
+        unproven[0].confirmation = Some(vec![3]);
+        let mut t = ours.clone();
+        assert_eq!(t.extend_from(&unproven), Ok(2));
+        assert_eq!(t.history()[3].block_root, None);
+        assert_eq!(t.history()[3].confirmation, None);
+        assert_eq!(t, theirs);
+
+        // Entries we already hold are skipped
+        let mut synced = ours.clone();
+        assert_eq!(synced.extend_from(theirs.history_after(1)), Ok(2));
+        assert_eq!(synced, theirs);
+        assert_eq!(synced.extend_from(theirs.history()), Ok(0));
+        assert_eq!(ours.extend_from(theirs.history_after(3)), Ok(2));
+        assert_eq!(ours, theirs);
+
+        // Overlap must match our history
+        let mut forked = theirs.history_after(2).to_vec();
+        forked[1].txn = MockTransaction::new(uid.clone(), 3, 9, 3);
+        let mut t = synced.clone();
+        t.revert_to(4);
+        assert_eq!(
+            t.extend_from(&forked),
+            Err(TokenError::BatchNotChildOfPrevious { index: 1, got: TxnCmp::DoubleSpend })
+        );
+
+        // Entries still waiting for their block come after any block
+        let pending = MockTransaction::new(uid, 5, 6, 5);
+        assert!(theirs.add_transaction(pending.clone()).is_ok());
+        assert_eq!(theirs.history_after(5).len(), 1);
+        assert_eq!(ours.extend_from(theirs.history_after(5)), Ok(1));
+        assert_eq!(ours.latest_transaction(), Some(&pending));
+    }
+
+    // Only used by `test_validity_is_cached`, entries may be checked on other threads
+    static VALIDATE_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    // Counts calls to `validate()`
+    #[derive(PartialEq, Clone, Debug)]
+    struct CountingTransaction(MockTransaction);
+
+    impl PlasmaCashTxn for CountingTransaction {
+        type HashType = [u8; 8];
+        type Hasher = fn(&[u8]) -> [u8; 8];
+        type Owner = u8;
+        type BlockNum = u8;
+        type Amount = u64;
+        type Error = Malformed;
+
+        fn token_id(&self) -> BitVec {
+            self.0.token_id()
+        }
+
+        fn receiver(&self) -> Option<u8> {
+            self.0.receiver()
+        }
+
+        fn validate(&self) -> Result<(), Malformed> {
+            VALIDATE_CALLS.fetch_add(1, atomic::Ordering::Relaxed);
+            self.0.validate()
+        }
+
+        fn prev_block(&self) -> u8 {
+            self.0.prev_block()
+        }
+
+        fn leaf_hash(&self) -> [u8; 8] {
+            self.0.leaf_hash()
+        }
+
+        fn empty_leaf_hash() -> [u8; 8] {
+            MockTransaction::empty_leaf_hash()
+        }
+
+        fn hasher() -> Self::Hasher {
+            MockTransaction::hasher()
+        }
+
+        fn compare(&self, other: &Self) -> TxnCmp {
+            self.0.compare(&other.0)
+        }
+    }
+
+    // Only used by `test_leaf_hashes_override_is_used`
+    static LEAF_HASH_CALLS: AtomicUsize = AtomicUsize::new(0);
+    static LEAF_HASHES_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    // Counts single and batched leaf hashing
+    #[derive(PartialEq, Clone, Debug)]
+    struct BatchHashedTransaction(MockTransaction);
+
+    impl PlasmaCashTxn for BatchHashedTransaction {
+        type HashType = [u8; 8];
+        type Hasher = fn(&[u8]) -> [u8; 8];
+        type Owner = u8;
+        type BlockNum = u8;
+        type Amount = u64;
+        type Error = Malformed;
+
+        fn token_id(&self) -> BitVec {
+            self.0.token_id()
+        }
+
+        fn receiver(&self) -> Option<u8> {
+            self.0.receiver()
+        }
+
+        fn sender(&self) -> Option<u8> {
+            self.0.sender()
+        }
+
+        fn validate(&self) -> Result<(), Malformed> {
+            self.0.validate()
+        }
+
+        fn prev_block(&self) -> u8 {
+            self.0.prev_block()
+        }
+
+        fn leaf_hash(&self) -> [u8; 8] {
+            LEAF_HASH_CALLS.fetch_add(1, atomic::Ordering::Relaxed);
+            self.0.leaf_hash()
+        }
+
+        fn leaf_hashes(txns: &[&Self]) -> Vec<[u8; 8]> {
+            LEAF_HASHES_CALLS.fetch_add(1, atomic::Ordering::Relaxed);
+            txns.iter().map(|txn| txn.0.leaf_hash()).collect()
+        }
+
+        fn empty_leaf_hash() -> [u8; 8] {
+            MockTransaction::empty_leaf_hash()
+        }
+
+        fn hasher() -> Self::Hasher {
+            MockTransaction::hasher()
+        }
+    }
+
+    #[test]
+    fn test_leaf_hashes_override_is_used() {
+        let uid = BitVec::from_element(1u8);
+        let mut t: Token<BatchHashedTransaction, [u8; 8]> = Token::new(uid.clone());
+        let mut mock = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        move_to_status(&mut mock, TokenStatus::PlasmaChain);
+        for i in 0..4 {
+            let txn = MockTransaction::new(uid.clone(), i, i + 1, i);
+            assert!(t.add_transaction(BatchHashedTransaction(txn.clone())).is_ok());
+            assert!(mock.add_transaction(txn).is_ok());
+        }
+
+        let single_calls = LEAF_HASH_CALLS.load(atomic::Ordering::Relaxed);
+        assert_eq!(t.history_commitment(), mock.history_commitment());
+        assert_eq!(LEAF_HASHES_CALLS.load(atomic::Ordering::Relaxed), 1);
+        assert_eq!(LEAF_HASH_CALLS.load(atomic::Ordering::Relaxed), single_calls);
+
+        // Still batched when hashed through references
+        let txns: Vec<&BatchHashedTransaction> = t.transactions().collect();
+        let refs: Vec<&&BatchHashedTransaction> = txns.iter().collect();
+        assert_eq!(<&BatchHashedTransaction>::leaf_hashes(&refs), BatchHashedTransaction::leaf_hashes(&txns));
+        assert_eq!(LEAF_HASHES_CALLS.load(atomic::Ordering::Relaxed), 3);
+        assert_eq!(LEAF_HASH_CALLS.load(atomic::Ordering::Relaxed), single_calls);
+    }
+
+    #[test]
+    fn test_invalid_transaction_cause() {
+        let uid = BitVec::from_element(1u8);
+        let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        assert!(t.add_transaction(MockTransaction::new(uid.clone(), 0, 1, 0)).is_ok());
+        let mut txn = MockTransaction::new(uid, 1, 2, 1);
+        txn.malformed = true;
+        assert!(t.add_transaction(txn).is_ok());
+        let e = t.validate().unwrap_err();
+        assert_eq!(e, HistoryError::InvalidTransaction { index: 1, cause: Malformed });
+        assert_eq!(e.to_string(), "Transaction 1 is not well-formed (Malformed).");
+        let e = TokenError::from(e);
+        assert_eq!(e, TokenError::InvalidTransactionAt { index: 1, cause: "Malformed".into() });
+        assert_eq!(e.to_string(), "Transaction 1 is not well-formed (Malformed).");
+    }
+
+    #[test]
+    fn test_validity_is_cached() {
+        let validate_calls = || VALIDATE_CALLS.load(atomic::Ordering::Relaxed);
+        let uid = BitVec::from_element(1u8);
+        let txn = |i| CountingTransaction(MockTransaction::new(uid.clone(), i, i + 1, i));
+
+        let mut t: Token<CountingTransaction, [u8; 8]> = Token::new(uid.clone());
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
+        for i in 0..5 {
+            assert!(t.add_transaction(txn(i)).is_ok());
+        }
+        assert_eq!(validate_calls(), 5);
+        assert!(t.is_valid());
+        assert!(t.clone().is_valid());
+        assert_eq!(validate_calls(), 5);
+
+        // Reverting and pruning keep the rest of the history known valid
+        t.revert_to(3);
+        assert!(t.prune_history(1).is_ok());
+        assert!(t.add_transaction(txn(3)).is_ok());
         assert!(t.is_valid());
+        assert_eq!(validate_calls(), 6);
+
+        // Entries added without being checked are checked once
+        t.history.push(HistoryEntry::new(txn(4)));
+        assert!(t.is_valid());
+        assert!(t.is_valid());
+        assert_eq!(validate_calls(), 7);
+
+        // Decoded tokens are checked in full again
+        let bytes = t.to_bytes(|txn| txn.0.as_bytes().to_vec());
+        let decoded = Token::<CountingTransaction, [u8; 8]>::from_bytes(&bytes, |bytes| {
+            decode_mock(bytes).map(CountingTransaction)
+        }).unwrap();
+        let before = validate_calls();
+        assert!(decoded.is_valid());
+        assert_eq!(validate_calls(), before + decoded.history_len());
     }
 
     #[test]
     fn test_history_revision() {
         let mut t = new_token(1);
+        move_to_status(&mut t, TokenStatus::PlasmaChain);
         let txn1 = MockTransaction::new(t.uid.clone(), 0, 1, 0);
         assert!(t.add_transaction(txn1.clone()).is_ok());
 
         // try and add a transaction that has no relationship to the stored one
         let txn2 = MockTransaction::new(t.uid.clone(), 2, 2, 1);
         assert_eq!(txn2.compare(&txn1), TxnCmp::Unrelated);
-        assert!(t.add_transaction(txn2).is_err());
+        assert_eq!(
+            t.add_transaction(txn2),
+            Err(TokenError::NotChildOfPrevious { got: TxnCmp::Unrelated })
+        );
         assert!(t.is_valid());
     }
 }
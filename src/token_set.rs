@@ -0,0 +1,214 @@
+//! Collection of many tokens, keyed by uid.
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeMap;
+
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
+
+#[cfg(not(feature = "std"))]
+use core::result::Result;
+
+use core::fmt;
+
+use bitvec::prelude::BitVec;
+
+use crate::codec::bitvec_to_bytes;
+use crate::token::{Token, TokenError};
+use crate::transaction::PlasmaCashTxn;
+
+/// Reasons a `TokenSet` operation failed.
+#[derive(Debug, PartialEq)]
+pub enum TokenSetError {
+    /// A token with the same uid is already in the set.
+    DuplicateUid,
+    /// No token in the set has the transaction's uid.
+    UnknownToken,
+    /// The token rejected the transaction.
+    Token(TokenError),
+}
+
+impl fmt::Display for TokenSetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenSetError::DuplicateUid =>
+                write!(f, "Token with this uid is already in the set."),
+            TokenSetError::UnknownToken =>
+                write!(f, "No token with this uid in the set."),
+            TokenSetError::Token(e) =>
+                write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TokenSetError {}
+
+impl From<TokenError> for TokenSetError {
+    fn from(e: TokenError) -> TokenSetError {
+        TokenSetError::Token(e)
+    }
+}
+
+// Orders uids by value, and treats uids that only differ in leading zero
+// bits as the same token (the same as `Token::add_transaction` does)
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct UidKey(usize, Vec<u8>);
+
+impl UidKey {
+    fn new(uid: &BitVec) -> UidKey {
+        let bits: BitVec = uid.iter().skip_while(|bit| !bit).collect();
+        UidKey(bits.len(), bitvec_to_bytes(&bits))
+    }
+}
+
+/// Set of tokens with unique uids, iterated in order of uid.
+pub struct TokenSet<TxnType, HashType>
+    where
+        TxnType: PlasmaCashTxn,
+        HashType: AsRef<[u8]>,
+{
+    tokens: BTreeMap<UidKey, Token<TxnType, HashType>>,
+}
+
+impl<TxnType, HashType> TokenSet<TxnType, HashType>
+    where
+        TxnType: PlasmaCashTxn,
+        HashType: AsRef<[u8]>,
+{
+    /// Create an empty set.
+    pub fn new() -> TokenSet<TxnType, HashType> {
+        TokenSet { tokens: BTreeMap::new() }
+    }
+
+    /// Number of tokens in the set.
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// Add `token` to the set, unless one with the same uid is already there.
+    pub fn insert(&mut self, token: Token<TxnType, HashType>) -> Result<(), TokenSetError> {
+        let key = UidKey::new(&token.uid);
+        if self.tokens.contains_key(&key) {
+            return Err(TokenSetError::DuplicateUid);
+        }
+        self.tokens.insert(key, token);
+        Ok(())
+    }
+
+    pub fn get(&self, uid: &BitVec) -> Option<&Token<TxnType, HashType>> {
+        self.tokens.get(&UidKey::new(uid))
+    }
+
+    pub fn get_mut(&mut self, uid: &BitVec) -> Option<&mut Token<TxnType, HashType>> {
+        self.tokens.get_mut(&UidKey::new(uid))
+    }
+
+    pub fn remove(&mut self, uid: &BitVec) -> Option<Token<TxnType, HashType>> {
+        self.tokens.remove(&UidKey::new(uid))
+    }
+
+    /// Tokens in the set, in order of uid.
+    pub fn iter(&self) -> impl Iterator<Item = &Token<TxnType, HashType>> {
+        self.tokens.values()
+    }
+
+    /// Add `txn` to the history of the token it is for (by `token_id()`).
+    pub fn apply_transaction(&mut self, txn: TxnType) -> Result<(), TokenSetError> {
+        let token = self.tokens.get_mut(&UidKey::new(&txn.token_id()))
+            .ok_or(TokenSetError::UnknownToken)?;
+        token.add_transaction(txn)?;
+        Ok(())
+    }
+}
+
+impl<TxnType, HashType> Default for TokenSet<TxnType, HashType>
+    where
+        TxnType: PlasmaCashTxn,
+        HashType: AsRef<[u8]>,
+{
+    fn default() -> Self {
+        TokenSet::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::token::test::{move_to_status, new_token, MockTransaction};
+    use crate::transaction::TxnCmp;
+
+    type MockSet = TokenSet<MockTransaction, [u8; 8]>;
+
+    fn uid(id: u8) -> BitVec {
+        BitVec::from_element(id)
+    }
+
+    #[test]
+    fn insert_rejects_duplicates() {
+        let mut set = MockSet::new();
+        assert_eq!(set.insert(new_token(1)), Ok(()));
+        assert_eq!(set.insert(new_token(1)), Err(TokenSetError::DuplicateUid));
+        assert_eq!(set.len(), 1);
+
+        // Same uid with extra leading zero bits
+        let mut wide_uid = BitVec::from_element(0u8);
+        wide_uid.extend(uid(1).iter());
+        assert_eq!(set.insert(Token::new(wide_uid.clone())), Err(TokenSetError::DuplicateUid));
+        assert!(set.get(&wide_uid).is_some());
+
+        assert!(set.remove(&uid(1)).is_some());
+        assert!(set.is_empty());
+        assert_eq!(set.insert(new_token(1)), Ok(()));
+    }
+
+    #[test]
+    fn apply_transaction_routes_by_uid() {
+        let mut set = MockSet::new();
+        for id in &[1, 2] {
+            let mut token = new_token(*id);
+            move_to_status(&mut token, crate::TokenStatus::PlasmaChain);
+            set.insert(token).unwrap();
+        }
+
+        assert_eq!(set.apply_transaction(MockTransaction::new(uid(2), 0, 1, 0)), Ok(()));
+        assert_eq!(set.get(&uid(1)).unwrap().history_len(), 0);
+        assert_eq!(set.get(&uid(2)).unwrap().history_len(), 1);
+
+        assert_eq!(
+            set.apply_transaction(MockTransaction::new(uid(3), 0, 1, 0)),
+            Err(TokenSetError::UnknownToken)
+        );
+        assert_eq!(
+            set.apply_transaction(MockTransaction::new(uid(2), 0, 1, 0)),
+            Err(TokenSetError::Token(TokenError::NotChildOfPrevious { got: TxnCmp::Same }))
+        );
+
+        move_to_status(set.get_mut(&uid(1)).unwrap(), crate::TokenStatus::Withdrawal);
+        assert!(set.apply_transaction(MockTransaction::new(uid(1), 0, 1, 0)).is_err());
+    }
+
+    #[test]
+    fn iterates_in_uid_order() {
+        let mut set = MockSet::new();
+        for id in &[200, 3, 17, 1] {
+            set.insert(new_token(*id)).unwrap();
+        }
+        // Shorter uid with a smaller value sorts first
+        let mut short_uid = BitVec::new();
+        short_uid.push(true);
+        short_uid.push(false); // 0b10
+        set.insert(Token::new(short_uid.clone())).unwrap();
+
+        let uids: Vec<BitVec> = set.iter().map(|t| t.uid.clone()).collect();
+        assert_eq!(uids, vec![uid(1), short_uid, uid(3), uid(17), uid(200)]);
+    }
+}
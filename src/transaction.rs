@@ -7,9 +7,17 @@ use core::result::Result;
 #[cfg(not(feature = "std"))]
 use core::convert::AsRef;
 
+use core::cmp::Ordering;
+use core::convert::TryFrom;
+use core::fmt;
+
 use bitvec::prelude::BitVec;
 
-use crate::merkle::get_root;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+use crate::fee::FeeAmount;
+use crate::merkle::{get_root, MerkleError, NodeHasher, Proof};
 
 /// Different types of comparisions of Plasma Transactions.
 ///
@@ -24,7 +32,8 @@ use crate::merkle::get_root;
 /// ordering, since transactions may be encrypted in some context and unencrypted in
 /// others, which means relationships may differ depending on information privledge
 /// of the client.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum TxnCmp {
     /// LHS & RHS are the same exact transaction
     Same,
@@ -40,8 +49,27 @@ pub enum TxnCmp {
     DoubleSpend,
     /// LHS & RHS have no relationship to each other
     Unrelated,
+    /// LHS & RHS could each have spent the other, and it isn't known which
+    /// one came first
+    Cycle,
 }
 
+/// `Sync` with the `parallel` feature enabled, so histories can be validated
+/// across threads, and implemented for every type otherwise.
+#[cfg(feature = "parallel")]
+pub trait MaybeSync: Sync {}
+
+#[cfg(feature = "parallel")]
+impl<T: Sync> MaybeSync for T {}
+
+/// `Sync` with the `parallel` feature enabled, so histories can be validated
+/// across threads, and implemented for every type otherwise.
+#[cfg(not(feature = "parallel"))]
+pub trait MaybeSync {}
+
+#[cfg(not(feature = "parallel"))]
+impl<T> MaybeSync for T {}
+
 /// Plasma Cash Transaction trait for a given Token.
 ///
 /// All the methods a Plasma Cash Transaction must implement to allow
@@ -58,6 +86,8 @@ pub enum TxnCmp {
 /// privledged parties to the transaction, so that should be taken into
 /// account when using this API.
 ///
+/// With the `parallel` feature enabled, transactions must also be `Sync`.
+///
 /// # Example
 /// Users of this API should should define this e.g.
 /// ```ignore
@@ -65,18 +95,105 @@ pub enum TxnCmp {
 ///
 /// impl PlasmaCashTxn for Transaction { ... }
 /// ```
-pub trait PlasmaCashTxn {
+pub trait PlasmaCashTxn: MaybeSync {
     type HashType: AsRef<[u8]>;
 
+    /// Hash function used to verify proofs, see `hasher()`.
+    type Hasher: NodeHasher<Out = Self::HashType>;
+
+    /// Identity of a token owner (e.g. an account address).
+    type Owner: PartialEq;
+
+    /// Child Chain block number, see `prev_block()`. Must hold any `u64`
+    /// inclusion block it can be compared against (see
+    /// `Token::set_inclusion()`), larger ones failing the conversion.
+    type BlockNum: Ord + Copy + TryFrom<u64>;
+
+    /// Amount fees are paid in, see `fee()`.
+    type Amount: FeeAmount;
+
+    /// Why a transaction is not well-formed, see `validate()`.
+    type Error: fmt::Debug;
+
     /// Needed to obtain the key for a Merkle Proof.
     fn token_id(&self) -> BitVec;
 
-    /// Transaction is well-formed (implementation-specific).
+    /// Owner of the token once this transaction is applied, if it can be
+    /// recovered (e.g. `None` for an encrypted transaction).
+    fn receiver(&self) -> Option<Self::Owner>;
+
+    /// Owner of the token before this transaction is applied, if it can be
+    /// recovered (e.g. from a signature).
+    ///
+    /// # Note
+    /// Defaults to `None`. Only used for reporting (see `Token::provenance()`),
+    /// history validation relies on `compare()` instead.
+    fn sender(&self) -> Option<Self::Owner> {
+        None
+    }
+
+    /// Check the transaction is well-formed (implementation-specific),
+    /// reporting why it isn't (e.g. a bad signature or wrong chain id).
     ///
     /// # Note
     /// This might be used for certain use-cases to verify zk proofs,
     /// whereas other use cases might have only signature validation.
-    fn valid(&self) -> bool;
+    fn validate(&self) -> Result<(), Self::Error>;
+
+    /// Transaction is well-formed, see `validate()`.
+    fn valid(&self) -> bool {
+        self.validate().is_ok()
+    }
+
+    /// Transaction can start a token's history, i.e. it is the deposit of
+    /// the token into the Child Chain.
+    ///
+    /// # Note
+    /// Defaults to `false`, so implementations must opt in to creating tokens
+    /// with `Token::from_deposit()` or to strict tokens (see
+    /// `Token::new_strict()`).
+    fn is_deposit(&self) -> bool {
+        false
+    }
+
+    /// Block number of the transaction this one spends.
+    ///
+    /// # Note
+    /// It must strictly increase along a token's history, which catches
+    /// histories going back in time that `compare()` alone can't (e.g. when
+    /// it only looks at senders and receivers).
+    fn prev_block(&self) -> Self::BlockNum;
+
+    /// Fee this transaction pays the operator, if any.
+    ///
+    /// # Note
+    /// Defaults to `None`, i.e. transfers are free. See `Token::total_fees()`
+    /// and `FeeSchedule` for adding fees up and checking them.
+    fn fee(&self) -> Option<Self::Amount> {
+        None
+    }
+
+    /// Amount of the coin being transferred, if known. Only needed for fees
+    /// charged on it (see `FeeSchedule::BasisPoints`).
+    fn amount(&self) -> Option<Self::Amount> {
+        None
+    }
+
+    /// Order this transaction against `other` spending the same parent, by
+    /// the block each one spends (see `prev_block()`): spending the earlier
+    /// block makes it the earlier sibling, and spending the same block is a
+    /// double spend.
+    ///
+    /// # Note
+    /// Meant for `compare()` to use once both transactions are known to have
+    /// the same parent.
+    fn compare_siblings(&self, other: &Self) -> TxnCmp {
+        match self.prev_block().cmp(&other.prev_block()) {
+            Ordering::Less => TxnCmp::EarlierSibling,
+            Ordering::Greater => TxnCmp::LaterSibling,
+            Ordering::Equal => TxnCmp::DoubleSpend,
+        }
+    }
 
     /// Return "Leaf Hash" of this transaction.
     ///
@@ -90,30 +207,188 @@ pub trait PlasmaCashTxn {
     /// is standardized around for it's key: value txn datastore.
     ///
     /// It does *not* have to match the hash function used for SMT proofs, but it must
-    /// be consistent and of the same size as the hashes returned by `hash_fn()` for
+    /// be consistent and of the same size as the hashes returned by `hasher()` for
     /// the smt proof validation to work.
     // TODO Validate security proof
     fn leaf_hash(&self) -> Self::HashType;
 
+    /// Return the "Leaf Hash" of every transaction in `txns`.
+    ///
+    /// # Note
+    /// The default maps `leaf_hash()` over each item. Hash functions that
+    /// benefit from batching (e.g. SNARK-friendly hashes like Poseidon) may
+    /// override this, but the output must have the same length as `txns` and
+    /// be in exactly the same order. Transactions are borrowed, so they can
+    /// be hashed where they are stored (e.g. in a token's history).
+    fn leaf_hashes(txns: &[&Self]) -> Vec<Self::HashType> {
+        txns.iter().map(|txn| txn.leaf_hash()).collect()
+    }
+
     /// Returns an empty leaf hash.
     ///
     /// Used for proofs of exclusion in txn trie.
     fn empty_leaf_hash() -> Self::HashType;
 
-    /// Function used to verify proofs.
-    fn hash_fn() -> (fn(&[u8]) -> Self::HashType);
+    /// Hash function used to verify proofs.
+    ///
+    /// # Note
+    /// A plain function can be used as the `Hasher`, e.g.
+    /// `type Hasher = fn(&[u8]) -> H256`.
+    fn hasher() -> Self::Hasher;
 
     /// Returns the relationship of another transaction (other) to this
     /// one (self).
     ///
     /// See [TxnCmp](enum.TxnCmp.html) enum definition for more information.
-    fn compare(&self, other: &Self) -> TxnCmp;
+    ///
+    /// # Note
+    /// The default only relates transactions for the same token. A matching
+    /// leaf hash is the `Same` transaction, this one's receiver being the
+    /// sender of `other` makes it the `Parent`, this one's sender being the
+    /// receiver of `other` makes it the `Child`, and having the same sender
+    /// makes them siblings (see `compare_siblings()`). When both directions
+    /// match (e.g. a coin returned to its previous owner), the one spending
+    /// the earlier block (see `prev_block()`) is the parent, or it's a
+    /// `Cycle` if both spend the same block. A transaction whose sender can't be
+    /// recovered is only ever related as a parent, so it is `Unrelated` to
+    /// anything it would have to spend, and one whose receiver can't be
+    /// recovered is only ever related as a child. Override this when the
+    /// accessors can't tell (e.g. for encrypted transactions).
+    fn compare(&self, other: &Self) -> TxnCmp {
+        let (token_id, other_token_id) = (self.token_id(), other.token_id());
+        if !uid_matches(&token_id, &other_token_id) {
+            return TxnCmp::Unrelated;
+        }
+        if self.leaf_hash().as_ref() == other.leaf_hash().as_ref() {
+            return TxnCmp::Same;
+        }
+
+        let (sender, other_sender) = (self.sender(), other.sender());
+        let (receiver, other_receiver) = (self.receiver(), other.receiver());
+        let is_parent = other_sender.is_some() && other_sender == receiver;
+        let is_child = sender.is_some() && sender == other_receiver;
+        match (is_parent, is_child) {
+            (true, true) => match self.prev_block().cmp(&other.prev_block()) {
+                Ordering::Less => TxnCmp::Parent,
+                Ordering::Greater => TxnCmp::Child,
+                Ordering::Equal => TxnCmp::Cycle,
+            },
+            (true, false) => TxnCmp::Parent,
+            (false, true) => TxnCmp::Child,
+            (false, false) if sender.is_some() && sender == other_sender => self.compare_siblings(other),
+            (false, false) => TxnCmp::Unrelated,
+        }
+    }
 
     /// Obtain the root hash following the SMT algorithm.
     ///
     /// # Note
     /// Proof must be in un-compressed form (`proof.len() == smt.depth()`)
-    fn get_root(&self, proof: Vec<Self::HashType>) -> Result<Self::HashType, &'static str> {
-        get_root(&self.token_id(), self.leaf_hash(), proof, Self::hash_fn())
+    fn get_root(&self, proof: &Proof<Self::HashType>) -> Result<Self::HashType, MerkleError> {
+        get_root(&self.token_id(), self.leaf_hash(), proof, Self::hasher())
+    }
+}
+
+// Whether `token_id` refers to `uid`, ignoring leading zero bits (e.g. a
+// 256-bit id from the Root Chain against a shorter uid with the same value)
+pub(crate) fn uid_matches(uid: &BitVec, token_id: &BitVec) -> bool {
+    uid.iter().skip_while(|bit| !bit).eq(token_id.iter().skip_while(|bit| !bit))
+}
+
+// Lets history be checked through references, without cloning transactions
+impl<T: PlasmaCashTxn> PlasmaCashTxn for &T {
+    type HashType = T::HashType;
+    type Hasher = T::Hasher;
+    type Owner = T::Owner;
+    type BlockNum = T::BlockNum;
+    type Amount = T::Amount;
+    type Error = T::Error;
+
+    fn token_id(&self) -> BitVec {
+        (*self).token_id()
+    }
+
+    fn receiver(&self) -> Option<Self::Owner> {
+        (*self).receiver()
+    }
+
+    fn sender(&self) -> Option<Self::Owner> {
+        (*self).sender()
+    }
+
+    fn validate(&self) -> Result<(), Self::Error> {
+        (*self).validate()
+    }
+
+    fn valid(&self) -> bool {
+        (*self).valid()
+    }
+
+    fn is_deposit(&self) -> bool {
+        (*self).is_deposit()
+    }
+
+    fn prev_block(&self) -> Self::BlockNum {
+        (*self).prev_block()
+    }
+
+    fn fee(&self) -> Option<Self::Amount> {
+        (*self).fee()
+    }
+
+    fn amount(&self) -> Option<Self::Amount> {
+        (*self).amount()
+    }
+
+    fn compare_siblings(&self, other: &Self) -> TxnCmp {
+        (*self).compare_siblings(*other)
+    }
+
+    fn leaf_hash(&self) -> Self::HashType {
+        (*self).leaf_hash()
+    }
+
+    fn leaf_hashes(txns: &[&Self]) -> Vec<Self::HashType> {
+        let txns: Vec<&T> = txns.iter().map(|txn| **txn).collect();
+        T::leaf_hashes(&txns)
+    }
+
+    fn empty_leaf_hash() -> Self::HashType {
+        T::empty_leaf_hash()
+    }
+
+    fn hasher() -> Self::Hasher {
+        T::hasher()
     }
+
+    fn compare(&self, other: &Self) -> TxnCmp {
+        (*self).compare(*other)
+    }
+}
+
+/// Plasma Cash Transaction with a canonical byte encoding, so tokens can be
+/// encoded without being told how (see `Token::encode()`).
+///
+/// # Note
+/// `leaf_hash()` should be the hash (see `PlasmaCashTxn::hasher()`) of
+/// `encode()`, unless the implementation documents otherwise (e.g. when the
+/// leaf hash leaves out the signature).
+pub trait EncodableTxn: PlasmaCashTxn + Sized {
+    /// Canonical encoding of the transaction.
+    fn encode(&self) -> Vec<u8>;
+
+    /// Transaction encoded by `encode()`.
+    fn decode(bytes: &[u8]) -> Result<Self, Self::Error>;
+}
+
+/// Plasma Cash Transaction whose sender must confirm it after seeing it
+/// included in a block, as some Plasma Cash variants require.
+///
+/// # Note
+/// Confirmations are opaque bytes (e.g. a signature), so tokens can store and
+/// encode them without knowing their format.
+pub trait ConfirmableTxn: PlasmaCashTxn {
+    /// `confirmation` is valid for this transaction's inclusion in the block
+    /// with SMT root `included_root`.
+    fn verify_confirmation(&self, confirmation: &[u8], included_root: &Self::HashType) -> bool;
 }
@@ -1,7 +1,7 @@
 extern crate plasma_cash_tokens;
 use plasma_cash_tokens::{
-    Token, TokenStatus,
-    PlasmaCashTxn, TxnCmp,
+    Token, TokenStatus, TokenError, HistoryError, HistoryEntry, MerkleError, Proof, validate_history_iter,
+    PlasmaCashTxn, ConfirmableTxn, EncodableTxn, TxnCmp,
     BigEndian, BitVec,
 };
 
@@ -16,8 +16,10 @@ use ethereum_types::{Address, U256, H256};
 
 extern crate ethabi;
 
+use std::collections::BTreeMap;
+
 fn pkey_to_address(pkey: &PublicKey) -> Address {
-    let pkey_hash = keccak(&pkey.serialize().to_vec());
+    let pkey_hash = keccak(pkey.serialize());
     Address::from_slice(&pkey_hash[..20])
 }
 
@@ -38,8 +40,7 @@ impl UnsignedTransaction {
         let prev_blk_num = ethabi::Token::Uint(self.prevBlkNum);
         let msg_vec = &[new_owner, token_id, prev_blk_num];
         // Encode vector of Tokens
-        let msg_bytes = ethabi::encode(msg_vec);
-        msg_bytes
+        ethabi::encode(msg_vec)
     }
 
     fn unsigned_msg(&self) -> Message {
@@ -54,6 +55,15 @@ impl UnsignedTransaction {
     }
 }
 
+/// Why a `Transaction` is not well-formed.
+#[derive(Debug, PartialEq)]
+pub enum TxnError {
+    /// Sender can't be recovered from the signature.
+    BadSignature(secp256k1::Error),
+    /// Bytes aren't an encoded transaction.
+    BadEncoding,
+}
+
 #[allow(non_snake_case)]
 pub struct Transaction {
     pub newOwner: Address,
@@ -66,6 +76,7 @@ pub struct Transaction {
 impl Transaction {
     // camelCase is used here because of EIP-712
     #[allow(non_snake_case)]
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(newOwner: Address,
                tokenId: U256,
                prevBlkNum: U256) -> UnsignedTransaction
@@ -105,15 +116,18 @@ impl Transaction {
         Message::parse_slice(self.leaf_hash().as_ref()).unwrap()
     }
 
-    pub fn receiver(&self) -> Option<Address> {
-        Some(self.newOwner)
+    // Sender confirms the transaction's inclusion by signing
+    // `keccak(leaf_hash || root)`
+    fn confirmation_msg(&self, included_root: &H256) -> Message {
+        let msg_hash = keccak([self.leaf_hash().as_ref(), included_root.as_ref()].concat());
+        Message::parse_slice(msg_hash.as_ref()).unwrap()
     }
 
-    pub fn sender(&self) -> Option<Address> {
-        let pkey = recover(&self.unsigned_msg(),
-                           &self.signature,
-                           &self.recovery_id).unwrap();
-        Some(pkey_to_address(&pkey))
+    pub fn confirm(&self, included_root: &H256, skey: &SecretKey) -> Vec<u8> {
+        let (sig, recovery_id) = sign(&self.confirmation_msg(included_root), skey);
+        let mut confirmation = sig.serialize().to_vec();
+        confirmation.push(recovery_id.serialize());
+        confirmation
     }
 }
 
@@ -128,68 +142,111 @@ fn uid_to_bitvec(uid: U256) -> BitVec {
 
 impl PlasmaCashTxn for Transaction {
     type HashType = H256;
+    type Hasher = fn(&[u8]) -> H256;
+    type Owner = Address;
+    type BlockNum = U256;
+    type Amount = u128;
+    type Error = TxnError;
 
     fn token_id(&self) -> BitVec {
         uid_to_bitvec(self.tokenId)
     }
 
-    fn valid(&self) -> bool {
+    fn receiver(&self) -> Option<Address> {
+        Some(self.newOwner)
+    }
+
+    fn sender(&self) -> Option<Address> {
+        let pkey = recover(&self.unsigned_msg(),
+                           &self.signature,
+                           &self.recovery_id).ok()?;
+        Some(pkey_to_address(&pkey))
+    }
+
+    fn validate(&self) -> Result<(), TxnError> {
         // Signature is there, and it's valid
-        self.sender().is_some()
+        recover(&self.unsigned_msg(), &self.signature, &self.recovery_id)
+            .map(|_| ())
+            .map_err(TxnError::BadSignature)
+    }
+
+    fn prev_block(&self) -> U256 {
+        self.prevBlkNum
+    }
+
+    fn is_deposit(&self) -> bool {
+        // Deposits don't spend an earlier transaction
+        self.prevBlkNum.is_zero()
     }
 
     fn empty_leaf_hash() -> H256 {
-        Self::hash_fn()(H256::from([0; 32]).as_ref())
+        Self::hasher()(H256::from([0; 32]).as_ref())
     }
 
-    fn hash_fn() -> (fn(&[u8]) -> H256) {
+    fn hasher() -> Self::Hasher {
         |b| { keccak(b) }
     }
 
     fn leaf_hash(&self) -> H256 {
         // Return keccak hash of encoded struct
-        Self::hash_fn()(&self.encoded_msg())
+        Self::hasher()(&self.encoded_msg())
     }
+}
 
-    fn compare(&self, other: &Transaction) -> TxnCmp {
-
-        // Transactions must be with the same tokenId to be related
-        if self.tokenId == other.tokenId {
-
-            // The other one is the direct parent of this one
-            if self.newOwner == other.sender().unwrap() {
-                return TxnCmp::Parent; // FIXME Because this comes first, a cycle is possible
-
-            // This one is the direct parent of the other one
-            } else if self.sender().unwrap() == other.newOwner {
-                return TxnCmp::Child;
-
-            // Both of us have the same parent
-            // Note: due to how Plasma Cash is designed, one of these is
-            //       most likely not in the txn trie, unless the operator
-            //       made malicious modifications.
-            } else if self.sender().unwrap() == other.sender().unwrap() {
-
-                // But mine comes before, so I'm earlier
-                if self.prevBlkNum < other.prevBlkNum {
-                    return TxnCmp::EarlierSibling;
-
-                // The other comes before, so I'm later
-                } else if self.prevBlkNum > other.prevBlkNum {
-                    return TxnCmp::LaterSibling;
-
-                // We're both at the same height, but different destinations!
-                } else if self.newOwner != other.newOwner {
-                    return TxnCmp::DoubleSpend;
-                }
+// ABI-encoded message, followed by the 64 byte signature and the recovery id.
+// The leaf hash is of the message only, so it is the same however it's signed.
+impl EncodableTxn for Transaction {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = self.encoded_msg();
+        bytes.extend_from_slice(&self.signature.serialize());
+        bytes.push(self.recovery_id.serialize());
+        bytes
+    }
 
-                // We're both the same transaction (same tokenId, reciever, and sender)
-                return TxnCmp::Same;
-            }
+    fn decode(bytes: &[u8]) -> Result<Self, TxnError> {
+        if bytes.len() != 161 {
+            return Err(TxnError::BadEncoding);
         }
+        let msg_types = [
+            ethabi::ParamType::Address,
+            ethabi::ParamType::Uint(256),
+            ethabi::ParamType::Uint(256),
+        ];
+        let mut msg = ethabi::decode(&msg_types, &bytes[..96])
+            .map_err(|_| TxnError::BadEncoding)?
+            .into_iter();
+        let mut next = || msg.next().ok_or(TxnError::BadEncoding);
+        let unsigned_txn = UnsignedTransaction {
+            newOwner: next()?.to_address().ok_or(TxnError::BadEncoding)?,
+            tokenId: next()?.to_uint().ok_or(TxnError::BadEncoding)?,
+            prevBlkNum: next()?.to_uint().ok_or(TxnError::BadEncoding)?,
+        };
+        let signature = Signature::parse_slice(&bytes[96..160])
+            .map_err(TxnError::BadSignature)?;
+        let recovery_id = RecoveryId::parse(bytes[160])
+            .map_err(TxnError::BadSignature)?;
+        Ok(Transaction::new_signed(unsigned_txn, signature, recovery_id))
+    }
+}
 
-        // All else fails, we're unrelated
-        TxnCmp::Unrelated
+impl ConfirmableTxn for Transaction {
+    fn verify_confirmation(&self, confirmation: &[u8], included_root: &H256) -> bool {
+        // 64 byte signature, followed by the recovery id
+        if confirmation.len() != 65 {
+            return false;
+        }
+        let signature = match Signature::parse_slice(&confirmation[..64]) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+        let recovery_id = match RecoveryId::parse(confirmation[64]) {
+            Ok(recovery_id) => recovery_id,
+            Err(_) => return false,
+        };
+        match recover(&self.confirmation_msg(included_root), &signature, &recovery_id) {
+            Ok(pkey) => Some(pkey_to_address(&pkey)) == self.sender(),
+            Err(_) => false,
+        }
     }
 }
 
@@ -202,13 +259,38 @@ fn gen_addr_and_skey_pair(data: &[u8]) -> (Address, SecretKey) {
     (a, skey)
 }
 
+// Deposit the token into the Child Chain, so it accepts transfers
+fn deposit(t: &mut Token<Transaction, H256>) {
+    assert!(t.begin_deposit().is_ok());
+    assert!(t.finalize_deposit().is_ok());
+}
+
+// Proof for `txn` in a tree where every other leaf is empty, and that tree's root
+fn single_leaf_proof(txn: &Transaction) -> (Proof<H256>, H256) {
+    let mut default_node = Transaction::empty_leaf_hash();
+    let mut node = txn.leaf_hash();
+    let mut proof = Vec::new();
+    for is_right in txn.token_id().iter().rev() {
+        proof.push(default_node);
+        node = if is_right {
+            keccak([default_node.as_ref(), node.as_ref()].concat())
+        } else {
+            keccak([node.as_ref(), default_node.as_ref()].concat())
+        };
+        default_node = keccak([default_node.as_ref(), default_node.as_ref()].concat());
+    }
+    proof.reverse(); // Proofs are in root->leaf order
+    (Proof::new(proof, 256).unwrap(), node)
+}
+
 #[test]
 fn validate_empty_token() {
     let uid = U256::from(123);
     let t: Token<Transaction, H256> = Token::new(uid_to_bitvec(uid));
     assert_eq!(t.uid, uid_to_bitvec(uid));
-    assert_eq!(t.status, TokenStatus::RootChain);
-    assert_eq!(t.history.len(), 0);
+    assert_eq!(t.status(), TokenStatus::RootChain);
+    assert_eq!(t.history_len(), 0);
+    assert_eq!(t.owner(), None);
     assert!(t.is_valid());
 }
 
@@ -218,11 +300,13 @@ fn add_transaction() {
     let uid = U256::from(123);
     let prev_blk_num = U256::from(0);
     let mut t: Token<Transaction, H256> = Token::new(uid_to_bitvec(uid));
+    deposit(&mut t);
     let txn = Transaction::new(a, uid, prev_blk_num).sign(&skey);
 
-    assert_eq!(t.history.len(), 0);
+    assert_eq!(t.history_len(), 0);
     assert!(t.add_transaction(txn).is_ok());
-    assert_eq!(t.history.len(), 1);
+    assert_eq!(t.history_len(), 1);
+    assert_eq!(t.owner(), Some(a));
     assert!(t.is_valid());
 }
 
@@ -231,6 +315,7 @@ fn lots_of_history() {
     // Same token
     let uid = U256::from(123);
     let mut t: Token<Transaction, H256> = Token::new(uid_to_bitvec(uid));
+    deposit(&mut t);
 
     // 3 accounts
     let (a1, skey1) = gen_addr_and_skey_pair(&[1; 32]);
@@ -255,4 +340,225 @@ fn lots_of_history() {
 
     // Verify txn history is valid
     assert!(t.is_valid());
+
+    // Token ends up back with a3
+    assert!(t.is_owned_by(&a3));
+    assert!(!t.is_owned_by(&a1));
+
+    // Deposited by a3, and owned by every account since
+    assert_eq!(t.provenance(), vec![a3, a1, a2, a3]);
+    assert!(t.was_owned_by(&a1));
+    let (a4, _) = gen_addr_and_skey_pair(&[4; 32]);
+    assert!(!t.was_owned_by(&a4));
+}
+
+#[test]
+fn from_deposit() {
+    let (a1, skey1) = gen_addr_and_skey_pair(&[1; 32]);
+    let (a2, _) = gen_addr_and_skey_pair(&[2; 32]);
+    let uid = U256::from(123);
+
+    let deposit = Transaction::new(a1, uid, U256::from(0)).sign(&skey1);
+    let t: Token<Transaction, H256> = Token::from_deposit(uid_to_bitvec(uid), deposit, 5).unwrap();
+    assert_eq!(t.status(), TokenStatus::PlasmaChain);
+    assert_eq!(t.history()[0].block_num, Some(5));
+    assert!(t.is_owned_by(&a1));
+    assert!(t.is_valid());
+
+    // A transfer spends an earlier block, so it isn't a deposit
+    let transfer = Transaction::new(a2, uid, U256::from(5)).sign(&skey1);
+    assert_eq!(
+        Token::<Transaction, H256>::from_deposit(uid_to_bitvec(uid), transfer, 6).err(),
+        Some(TokenError::NotDeposit)
+    );
+
+    let other_token = Transaction::new(a1, U256::from(124), U256::from(0)).sign(&skey1);
+    assert_eq!(
+        Token::<Transaction, H256>::from_deposit(uid_to_bitvec(uid), other_token, 5).err(),
+        Some(TokenError::WrongToken { index: 0 })
+    );
+}
+
+#[test]
+fn bad_signature_is_reported() {
+    let uid = U256::from(123);
+    let (a, skey) = gen_addr_and_skey_pair(&[1; 32]);
+    let txn = Transaction::new(a, uid, U256::from(0)).sign(&skey);
+    let forged = Transaction::new_signed(
+        Transaction::new(a, uid, U256::from(0)),
+        Signature::parse(&[0; 64]),
+        txn.recovery_id,
+    );
+    assert_eq!(forged.validate(), Err(TxnError::BadSignature(secp256k1::Error::InvalidSignature)));
+    assert!(!forged.valid());
+
+    // Signatures are only checked when validating the history
+    let mut t: Token<Transaction, H256> = Token::new(uid_to_bitvec(uid));
+    deposit(&mut t);
+    assert!(t.add_transaction(forged).is_ok());
+    assert_eq!(
+        t.validate(),
+        Err(HistoryError::InvalidTransaction {
+            index: 0,
+            cause: TxnError::BadSignature(secp256k1::Error::InvalidSignature),
+        })
+    );
+    assert!(!t.is_valid());
+}
+
+#[test]
+fn encoding_round_trip() {
+    let (a1, skey1) = gen_addr_and_skey_pair(&[1; 32]);
+    let (a2, skey2) = gen_addr_and_skey_pair(&[2; 32]);
+    let uid = U256::from(123);
+    let txn = Transaction::new(a2, uid, U256::from(1)).sign(&skey1);
+    let bytes = txn.encode();
+    assert_eq!(bytes.len(), 161);
+    let decoded = Transaction::decode(&bytes).unwrap();
+    assert_eq!(decoded.encode(), bytes);
+    assert_eq!(decoded.sender(), Some(a1));
+    assert_eq!(decoded.receiver(), Some(a2));
+    assert_eq!(decoded.leaf_hash(), txn.leaf_hash());
+    assert_eq!(Transaction::decode(&bytes[..160]).err(), Some(TxnError::BadEncoding));
+
+    let mut t: Token<Transaction, H256> = Token::new(uid_to_bitvec(uid));
+    deposit(&mut t);
+    assert!(t.add_transaction(Transaction::new(a1, uid, U256::from(0)).sign(&skey2)).is_ok());
+    assert!(t.add_transaction(txn).is_ok());
+    let bytes = t.encode();
+    let decoded = Token::<Transaction, H256>::decode(&bytes).unwrap();
+    assert_eq!(decoded.history().len(), 2);
+    assert_eq!(decoded.history()[1].txn.sender(), Some(a1));
+    assert_eq!(decoded.encode(), bytes);
+}
+
+#[test]
+fn siblings_ordered_by_block() {
+    let uid = U256::from(123);
+    let (_, skey1) = gen_addr_and_skey_pair(&[1; 32]);
+    let (a2, _) = gen_addr_and_skey_pair(&[2; 32]);
+    let (a3, _) = gen_addr_and_skey_pair(&[3; 32]);
+
+    // a1 spends the token to a2, and to a3
+    let earlier = Transaction::new(a2, uid, U256::from(3)).sign(&skey1);
+    let later = Transaction::new(a3, uid, U256::from(5)).sign(&skey1);
+    let double_spend = Transaction::new(a3, uid, U256::from(3)).sign(&skey1);
+    assert_eq!(earlier.compare_siblings(&later), TxnCmp::EarlierSibling);
+    assert_eq!(later.compare_siblings(&earlier), TxnCmp::LaterSibling);
+    assert_eq!(earlier.compare_siblings(&double_spend), TxnCmp::DoubleSpend);
+
+    assert_eq!(earlier.compare(&later), TxnCmp::EarlierSibling);
+    assert_eq!(later.compare(&earlier), TxnCmp::LaterSibling);
+    assert_eq!(earlier.compare(&double_spend), TxnCmp::DoubleSpend);
+    assert_eq!(earlier.compare(&Transaction::new(a2, uid, U256::from(3)).sign(&skey1)), TxnCmp::Same);
+
+    // Any block number off the wire can be compared, even past a u64
+    let far = Transaction::new(a3, uid, U256::max_value()).sign(&skey1);
+    let far = Transaction::decode(&far.encode()).unwrap();
+    assert_eq!(earlier.compare(&far), TxnCmp::EarlierSibling);
+    assert_eq!(far.compare(&earlier), TxnCmp::LaterSibling);
+}
+
+#[test]
+fn block_numbers_must_increase() {
+    let uid = U256::from(123);
+    let (_, skey1) = gen_addr_and_skey_pair(&[1; 32]);
+    let (a2, skey2) = gen_addr_and_skey_pair(&[2; 32]);
+    let (a3, _) = gen_addr_and_skey_pair(&[3; 32]);
+
+    // a1 -> a2 -> a3 is a valid chain of owners, but goes back in time
+    let txn1 = Transaction::new(a2, uid, U256::from(5)).sign(&skey1);
+    let txn2 = Transaction::new(a3, uid, U256::from(3)).sign(&skey2);
+    assert_eq!(txn2.compare(&txn1), TxnCmp::Child);
+
+    let mut t: Token<Transaction, H256> = Token::new(uid_to_bitvec(uid));
+    deposit(&mut t);
+    assert!(t.add_transaction(txn1).is_ok());
+    assert_eq!(t.add_transaction(txn2), Err(TokenError::BlockNumberNotIncreasing));
+    assert_eq!(t.history_len(), 1);
+
+    let txn1 = Transaction::new(a2, uid, U256::from(5)).sign(&skey1);
+    let txn2 = Transaction::new(a3, uid, U256::from(3)).sign(&skey2);
+    assert_eq!(
+        Token::<Transaction, H256>::new_with_history(uid_to_bitvec(uid), vec![txn1, txn2]).err(),
+        Some(TokenError::InvalidHistory)
+    );
+    assert_eq!(
+        validate_history_iter(vec![
+            Transaction::new(a2, uid, U256::from(5)).sign(&skey1),
+            Transaction::new(a3, uid, U256::from(3)).sign(&skey2),
+        ]),
+        Err((1, TokenError::BlockNumberNotIncreasing))
+    );
+}
+
+#[test]
+fn add_transaction_with_proof() {
+    let (a, skey) = gen_addr_and_skey_pair(&[1; 32]);
+    let uid = U256::from(123);
+    let mut t: Token<Transaction, H256> = Token::new(uid_to_bitvec(uid));
+    deposit(&mut t);
+    let unsigned_txn = Transaction::new(a, uid, U256::from(0));
+    let (proof, root) = single_leaf_proof(&unsigned_txn.sign(&skey));
+
+    // Corrupted proof doesn't reach the block root
+    let mut bad_proof = proof.clone().into_inner();
+    bad_proof[0] = H256::from([1; 32]);
+    assert_eq!(
+        t.add_transaction_with_proof(unsigned_txn.sign(&skey), bad_proof.into(), root),
+        Err(TokenError::RootMismatch)
+    );
+
+    // Proof must cover every level of the tree
+    assert_eq!(
+        t.add_transaction_with_proof(unsigned_txn.sign(&skey), proof.as_slice()[1..].to_vec().into(), root),
+        Err(TokenError::Merkle(MerkleError::ProofLengthMismatch { key_bits: 256, proof_len: 255 }))
+    );
+    assert_eq!(t.history_len(), 0);
+
+    assert!(t.add_transaction_with_proof(unsigned_txn.sign(&skey), proof.clone(), root).is_ok());
+    assert_eq!(t.history_len(), 1);
+    assert_eq!(t.history()[0].proof, Some(proof));
+    assert_eq!(t.history()[0].block_root, Some(root));
+    assert!(t.is_valid());
+}
+
+#[test]
+fn confirmation_flow() {
+    let (a1, skey1) = gen_addr_and_skey_pair(&[1; 32]);
+    let (a2, skey2) = gen_addr_and_skey_pair(&[2; 32]);
+    let (_, skey3) = gen_addr_and_skey_pair(&[3; 32]);
+    let uid = U256::from(123);
+    let mut t: Token<Transaction, H256> = Token::new(uid_to_bitvec(uid));
+    deposit(&mut t);
+
+    // Deposit (a3 -> a1) in block 1, then a1 -> a2 in block 2
+    let mut roots = BTreeMap::new();
+    for (block, txn) in [
+        (1, Transaction::new(a1, uid, U256::from(0)).sign(&skey3)),
+        (2, Transaction::new(a2, uid, U256::from(1)).sign(&skey1)),
+    ] {
+        let (proof, root) = single_leaf_proof(&txn);
+        let mut entry = HistoryEntry::new(txn);
+        entry.block_num = Some(block);
+        entry.proof = Some(proof);
+        entry.block_root = Some(root);
+        assert!(t.add_entry(entry).is_ok());
+        roots.insert(block, root);
+    }
+    assert_eq!(t.verify_history_against_roots(&roots), Ok(()));
+    assert_eq!(
+        t.verify_confirmed_history_against_roots(&roots),
+        Err(TokenError::MissingConfirmation { index: 1 })
+    );
+
+    // Only the sender can confirm, and only for the block it was included in
+    let by_receiver = t.history()[1].txn.confirm(&roots[&2], &skey2);
+    assert_eq!(t.confirm(1, by_receiver), Err(TokenError::InvalidConfirmation { index: 1 }));
+    let wrong_root = t.history()[1].txn.confirm(&roots[&1], &skey1);
+    assert_eq!(t.confirm(1, wrong_root), Err(TokenError::InvalidConfirmation { index: 1 }));
+
+    let confirmation = t.history()[1].txn.confirm(&roots[&2], &skey1);
+    assert!(t.confirm(1, confirmation).is_ok());
+    assert_eq!(t.verify_confirmed_history_against_roots(&roots), Ok(()));
 }